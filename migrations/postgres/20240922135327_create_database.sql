@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE "user" (
+    id              BIGINT              GENERATED BY DEFAULT AS IDENTITY    PRIMARY KEY,
+    username        TEXT                NOT NULL                UNIQUE,
+    email           TEXT                NOT NULL                UNIQUE,
+    password        TEXT                NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Default unit system and language of weather responses, NULL when not chosen
+ALTER TABLE "user" ADD COLUMN units TEXT CHECK (units IN ('metric', 'imperial', 'si'));
+ALTER TABLE "user" ADD COLUMN lang TEXT;
@@ -0,0 +1,12 @@
+-- Named locations saved by users, at most one of them is the default of its user
+CREATE TABLE location (
+    id              BIGINT              GENERATED BY DEFAULT AS IDENTITY    PRIMARY KEY,
+    user_id         BIGINT              NOT NULL                REFERENCES "user" (id) ON DELETE CASCADE,
+    name            TEXT                NOT NULL,
+    latitude        DOUBLE PRECISION    NOT NULL,
+    longitude       DOUBLE PRECISION    NOT NULL,
+    is_default      BOOLEAN             NOT NULL                DEFAULT FALSE,
+    UNIQUE (user_id, name)
+);
+
+CREATE UNIQUE INDEX location_default ON location (user_id) WHERE is_default;
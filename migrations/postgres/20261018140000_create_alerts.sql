@@ -0,0 +1,33 @@
+-- Webhook alerts of a user are delivered to, payloads are signed with the secret
+CREATE TABLE alert_webhook (
+    user_id         BIGINT              PRIMARY KEY             REFERENCES "user" (id) ON DELETE CASCADE,
+    url             TEXT                NOT NULL,
+    secret          TEXT                NOT NULL
+);
+
+-- Conditions evaluated against the weather at saved locations, `firing` is set while the condition holds
+CREATE TABLE alert_rule (
+    id              BIGINT              GENERATED BY DEFAULT AS IDENTITY    PRIMARY KEY,
+    user_id         BIGINT              NOT NULL                REFERENCES "user" (id) ON DELETE CASCADE,
+    location_id     BIGINT              NOT NULL                REFERENCES location (id) ON DELETE CASCADE,
+    condition       TEXT                NOT NULL                CHECK (condition IN ('temperature_below', 'temperature_above', 'wind_speed_above', 'uv_index_above', 'precipitation_expected')),
+    threshold       DOUBLE PRECISION    NOT NULL,
+    within_hours    SMALLINT,
+    firing          BOOLEAN             NOT NULL                DEFAULT FALSE,
+    last_fired_at   BIGINT
+);
+
+-- Every attempt to deliver an alert, kept after its rule is deleted
+CREATE TABLE alert_delivery (
+    id              BIGINT              GENERATED BY DEFAULT AS IDENTITY    PRIMARY KEY,
+    user_id         BIGINT              NOT NULL                REFERENCES "user" (id) ON DELETE CASCADE,
+    rule_id         BIGINT              NOT NULL,
+    event_id        TEXT                NOT NULL,
+    attempt         INTEGER             NOT NULL,
+    status_code     INTEGER,
+    error           TEXT,
+    delivered       BOOLEAN             NOT NULL,
+    attempted_at    BIGINT              NOT NULL
+);
+
+CREATE INDEX alert_delivery_user ON alert_delivery (user_id, id);
@@ -0,0 +1,18 @@
+-- Hourly weather observations, keyed by coordinates in hundredths of a degree and the start of the hour
+CREATE TABLE observation (
+    grid_latitude   BIGINT              NOT NULL,
+    grid_longitude  BIGINT              NOT NULL,
+    observed_at     BIGINT              NOT NULL,
+    temperature_c   DOUBLE PRECISION    NOT NULL,
+    feels_like_c    DOUBLE PRECISION,
+    condition       TEXT,
+    condition_code  INTEGER,
+    wind_kph        DOUBLE PRECISION,
+    humidity        SMALLINT,
+    pressure_mb     DOUBLE PRECISION,
+    precip_mm       DOUBLE PRECISION,
+    cloud           SMALLINT,
+    uv              DOUBLE PRECISION,
+    provider        TEXT                NOT NULL,
+    PRIMARY KEY (grid_latitude, grid_longitude, observed_at)
+);
@@ -0,0 +1,23 @@
+-- Security-relevant events of accounts, kept after their user is deleted
+CREATE TABLE audit_event (
+    id              BIGINT              GENERATED BY DEFAULT AS IDENTITY    PRIMARY KEY,
+    user_id         BIGINT,
+    event           TEXT                NOT NULL                CHECK (event IN ('registration', 'login', 'token_issuance')),
+    outcome         TEXT                NOT NULL                CHECK (outcome IN ('success', 'failure')),
+    client_ip       TEXT,
+    user_agent      TEXT,
+    occurred_at     BIGINT              NOT NULL
+);
+
+CREATE INDEX audit_event_user ON audit_event (user_id, id);
+CREATE INDEX audit_event_occurred_at ON audit_event (occurred_at);
+
+-- Events are only ever appended
+CREATE FUNCTION audit_event_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit events can not be modified or deleted';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_event_append_only BEFORE UPDATE OR DELETE ON audit_event
+    FOR EACH ROW EXECUTE FUNCTION audit_event_append_only();
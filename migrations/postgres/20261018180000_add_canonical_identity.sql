@@ -0,0 +1,8 @@
+-- Canonical forms of usernames and emails, so case and Unicode variants of an identity belong to a single user
+-- Filled by the server after migrating, so both backends canonicalize alike, and left NULL for a user whose
+-- canonical form collides with an older user's, so the collision is reported instead of failing the migration
+ALTER TABLE "user" ADD COLUMN username_canonical TEXT;
+ALTER TABLE "user" ADD COLUMN email_canonical TEXT;
+
+CREATE UNIQUE INDEX user_username_canonical ON "user" (username_canonical);
+CREATE UNIQUE INDEX user_email_canonical ON "user" (email_canonical);
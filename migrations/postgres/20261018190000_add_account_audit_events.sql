@@ -0,0 +1,5 @@
+-- Disabling, enabling, deletion, restoration and purge of accounts are audited as well
+ALTER TABLE audit_event
+    DROP CONSTRAINT audit_event_event_check,
+    ADD CONSTRAINT audit_event_event_check
+        CHECK (event IN ('registration', 'login', 'token_issuance', 'disabling', 'enabling', 'deletion', 'restoration', 'purge'));
@@ -0,0 +1,12 @@
+-- Named locations saved by users, at most one of them is the default of its user
+CREATE TABLE location (
+    id              INTEGER             PRIMARY KEY,
+    user_id         INTEGER             NOT NULL                REFERENCES user (id) ON DELETE CASCADE,
+    name            TEXT                NOT NULL,
+    latitude        REAL                NOT NULL,
+    longitude       REAL                NOT NULL,
+    is_default      BOOLEAN             NOT NULL                DEFAULT FALSE,
+    UNIQUE (user_id, name)
+);
+
+CREATE UNIQUE INDEX location_default ON location (user_id) WHERE is_default;
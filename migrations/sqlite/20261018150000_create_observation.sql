@@ -0,0 +1,18 @@
+-- Hourly weather observations, keyed by coordinates in hundredths of a degree and the start of the hour
+CREATE TABLE observation (
+    grid_latitude   INTEGER             NOT NULL,
+    grid_longitude  INTEGER             NOT NULL,
+    observed_at     INTEGER             NOT NULL,
+    temperature_c   REAL                NOT NULL,
+    feels_like_c    REAL,
+    condition       TEXT,
+    condition_code  INTEGER,
+    wind_kph        REAL,
+    humidity        INTEGER,
+    pressure_mb     REAL,
+    precip_mm       REAL,
+    cloud           INTEGER,
+    uv              REAL,
+    provider        TEXT                NOT NULL,
+    PRIMARY KEY (grid_latitude, grid_longitude, observed_at)
+);
@@ -0,0 +1,24 @@
+-- Security-relevant events of accounts, kept after their user is deleted
+CREATE TABLE audit_event (
+    id              INTEGER             PRIMARY KEY,
+    user_id         INTEGER,
+    event           TEXT                NOT NULL                CHECK (event IN ('registration', 'login', 'token_issuance')),
+    outcome         TEXT                NOT NULL                CHECK (outcome IN ('success', 'failure')),
+    client_ip       TEXT,
+    user_agent      TEXT,
+    occurred_at     INTEGER             NOT NULL
+);
+
+CREATE INDEX audit_event_user ON audit_event (user_id, id);
+CREATE INDEX audit_event_occurred_at ON audit_event (occurred_at);
+
+-- Events are only ever appended
+CREATE TRIGGER audit_event_no_update BEFORE UPDATE ON audit_event
+BEGIN
+    SELECT RAISE(ABORT, 'audit events can not be modified');
+END;
+
+CREATE TRIGGER audit_event_no_delete BEFORE DELETE ON audit_event
+BEGIN
+    SELECT RAISE(ABORT, 'audit events can not be deleted');
+END;
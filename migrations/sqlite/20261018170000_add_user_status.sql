@@ -0,0 +1,6 @@
+-- UNIX times a user is disabled and deleted at, NULL while active
+-- Deleted users are kept until the retention period passes, so deletions can be undone
+ALTER TABLE user ADD COLUMN disabled_at INTEGER;
+ALTER TABLE user ADD COLUMN deleted_at INTEGER;
+
+CREATE INDEX user_deleted_at ON user (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,35 @@
+-- Disabling, enabling, deletion, restoration and purge of accounts are audited as well
+-- SQLite can not alter a CHECK constraint, so the table is rebuilt, with its indexes and triggers
+DROP TRIGGER audit_event_no_update;
+DROP TRIGGER audit_event_no_delete;
+
+CREATE TABLE audit_event_new (
+    id              INTEGER             PRIMARY KEY,
+    user_id         INTEGER,
+    event           TEXT                NOT NULL                CHECK (event IN ('registration', 'login', 'token_issuance', 'disabling', 'enabling', 'deletion', 'restoration', 'purge')),
+    outcome         TEXT                NOT NULL                CHECK (outcome IN ('success', 'failure')),
+    client_ip       TEXT,
+    user_agent      TEXT,
+    occurred_at     INTEGER             NOT NULL
+);
+
+INSERT INTO audit_event_new (id, user_id, event, outcome, client_ip, user_agent, occurred_at)
+SELECT id, user_id, event, outcome, client_ip, user_agent, occurred_at
+FROM audit_event;
+
+DROP TABLE audit_event;
+ALTER TABLE audit_event_new RENAME TO audit_event;
+
+CREATE INDEX audit_event_user ON audit_event (user_id, id);
+CREATE INDEX audit_event_occurred_at ON audit_event (occurred_at);
+
+-- Events are only ever appended
+CREATE TRIGGER audit_event_no_update BEFORE UPDATE ON audit_event
+BEGIN
+    SELECT RAISE(ABORT, 'audit events can not be modified');
+END;
+
+CREATE TRIGGER audit_event_no_delete BEFORE DELETE ON audit_event
+BEGIN
+    SELECT RAISE(ABORT, 'audit events can not be deleted');
+END;
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::time::MissedTickBehavior;
+
+use crate::audit::{self, AuditEventKind, AuditOutcome, Requester};
+use crate::config::AccountsConfig;
+use crate::queries::{Database, SqlError};
+
+/// Permanently deletes users once they have been deleted for longer than the retention period.
+pub struct AccountPurge {
+    database: Database,
+    /// Seconds deleted users are kept for.
+    retention: i64,
+    interval: Duration,
+}
+
+impl AccountPurge {
+    /// Creates the purge of deleted users of given database with given configuration.
+    #[must_use]
+    pub fn new(config: &AccountsConfig, database: Database) -> Self {
+        Self {
+            database,
+            retention: i64::from(config.deletion_retention_days) * 86_400,
+            interval: Duration::from_secs(config.purge_interval_seconds.max(1)),
+        }
+    }
+
+    /// Permanently deletes the users deleted longer than the retention period ago and returns their number.
+    ///
+    /// The purge of every user is recorded to the audit log.
+    ///
+    /// # Errors
+    /// Returns error if the database operation fails.
+    pub async fn purge(&self) -> Result<u64, SqlError> {
+        let purged = self
+            .database
+            .purge_deleted_users(Utc::now().timestamp() - self.retention)
+            .await?;
+        for &user_id in &purged {
+            let requester = Requester::default();
+            audit::record(&self.database, AuditEventKind::Purge, AuditOutcome::Success, Some(user_id), &requester).await;
+        }
+        if !purged.is_empty() {
+            tracing::info!(purged = purged.len(), "deleted users purged");
+        }
+
+        Ok(purged.len() as u64)
+    }
+
+    /// Purges deleted users every interval, starting immediately. Never returns.
+    ///
+    /// Failures are logged and the purge is attempted again at the next interval.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            if self.purge().await.is_err() {
+                tracing::error!("purging deleted users failed");
+            }
+        }
+    }
+}
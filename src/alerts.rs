@@ -0,0 +1,577 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use hmac::{Hmac, Mac};
+use poem_openapi::Enum;
+use rand::Rng;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{redirect, StatusCode, Url};
+use sha2::Sha256;
+use tokio::time::MissedTickBehavior;
+
+use crate::client_ip;
+use crate::config::AlertsConfig;
+use crate::http_client::{Forecast, HttpClient, WeatherApiResponse};
+use crate::queries::{AlertDelivery, Database, ScheduledAlertRule};
+use crate::resilience::{self, RetryPolicy};
+
+/// Header carrying the signature of alert payloads, in `t=<UNIX time>,v1=<hex HMAC-SHA256>` form.
+///
+/// The HMAC is computed with the webhook secret over `<UNIX time>.<body>`.
+pub const SIGNATURE_HEADER: &str = "X-Alert-Signature";
+
+/// Header carrying the ID of the alert, same for every attempt to deliver it.
+pub const ALERT_ID_HEADER: &str = "X-Alert-Id";
+
+/// Condition of an alert rule.
+///
+/// Thresholds are in metric units: °C, km/h and percent.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// Temperature is below the threshold.
+    TemperatureBelow,
+    /// Temperature is above the threshold.
+    TemperatureAbove,
+    /// Wind speed is above the threshold.
+    WindSpeedAbove,
+    /// UV index is above the threshold.
+    UvIndexAbove,
+    /// Chance of rain reaches the threshold within `within_hours` hours.
+    PrecipitationExpected,
+}
+
+impl AlertCondition {
+    /// Returns the name of the condition as written in requests and the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::TemperatureBelow => "temperature_below",
+            Self::TemperatureAbove => "temperature_above",
+            Self::WindSpeedAbove => "wind_speed_above",
+            Self::UvIndexAbove => "uv_index_above",
+            Self::PrecipitationExpected => "precipitation_expected",
+        }
+    }
+
+    /// Returns whether the condition is evaluated against the forecast instead of current weather.
+    #[must_use]
+    pub const fn is_forecast(self) -> bool {
+        matches!(self, Self::PrecipitationExpected)
+    }
+
+    /// Evaluates the condition against the weather at the location of the rule.
+    ///
+    /// `current` and `forecast` are `None` if they could not be fetched.
+    fn evaluate(
+        self,
+        threshold: f64,
+        within_hours: Option<u8>,
+        current: Option<&WeatherApiResponse>,
+        forecast: Option<&Forecast>,
+    ) -> Evaluation {
+        let observed = match self {
+            Self::TemperatureBelow | Self::TemperatureAbove => current.map(|c| c.current.temp_c),
+            Self::WindSpeedAbove => current.and_then(|c| c.current.wind_kph),
+            Self::UvIndexAbove => current.and_then(|c| c.current.uv),
+            Self::PrecipitationExpected => {
+                let Some(forecast) = forecast else {
+                    return Evaluation::Unknown;
+                };
+
+                // Hours with rain but no reported chance count as certain
+                forecast
+                    .hours
+                    .iter()
+                    .take(usize::from(within_hours.unwrap_or(1)))
+                    .filter_map(|h| match (h.chance_of_rain, h.precip_mm) {
+                        (Some(chance), _) => Some(f64::from(chance)),
+                        (None, Some(mm)) if mm > 0.0 => Some(100.0),
+                        (None, _) => None,
+                    })
+                    .reduce(f64::max)
+                    .or(Some(0.0))
+            }
+        };
+
+        let Some(observed) = observed else {
+            return Evaluation::Unknown;
+        };
+
+        let holds = match self {
+            Self::TemperatureBelow => observed < threshold,
+            Self::TemperatureAbove | Self::WindSpeedAbove | Self::UvIndexAbove => observed > threshold,
+            Self::PrecipitationExpected => observed >= threshold,
+        };
+
+        if holds {
+            Evaluation::Holds(observed)
+        } else {
+            Evaluation::Clear
+        }
+    }
+}
+
+impl FromStr for AlertCondition {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "temperature_below" => Ok(Self::TemperatureBelow),
+            "temperature_above" => Ok(Self::TemperatureAbove),
+            "wind_speed_above" => Ok(Self::WindSpeedAbove),
+            "uv_index_above" => Ok(Self::UvIndexAbove),
+            "precipitation_expected" => Ok(Self::PrecipitationExpected),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Result of evaluating a rule.
+#[derive(Debug, PartialEq)]
+enum Evaluation {
+    /// The weather needed to evaluate the rule is not available, its state is kept.
+    Unknown,
+    /// The condition does not hold.
+    Clear,
+    /// The condition holds with the observed value.
+    Holds(f64),
+}
+
+/// Payload delivered to webhooks when a rule fires.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct AlertEvent {
+    /// ID of the alert, same for every attempt to deliver it, so receivers can deduplicate.
+    pub id: String,
+    pub rule_id: u64,
+    pub condition: AlertCondition,
+    pub threshold: f64,
+    #[serde(default)]
+    pub within_hours: Option<u8>,
+    /// Value that made the rule fire, in the units of the threshold.
+    pub value: f64,
+    pub location: AlertLocation,
+    pub fired_at: DateTime<Utc>,
+}
+
+/// Saved location of a fired rule in alert payloads.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct AlertLocation {
+    pub id: u64,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Evaluates alert rules periodically and delivers alerts of rules that start holding.
+///
+/// A rule fires once when its condition starts holding and is re-armed when it stops holding,
+/// so an ongoing condition is not alerted on every evaluation.
+pub struct AlertScheduler {
+    poll_interval: Duration,
+    database: Database,
+    http_client: Arc<HttpClient>,
+    delivery: Delivery,
+}
+
+impl AlertScheduler {
+    /// Most locations whose weather is fetched at once.
+    const MAX_CONCURRENT_FETCHES: usize = 8;
+
+    /// Creates a scheduler with given configuration.
+    ///
+    /// # Errors
+    /// Returns error if the webhook client can not be created.
+    pub fn new(
+        config: &AlertsConfig,
+        database: Database,
+        http_client: Arc<HttpClient>,
+    ) -> Result<Self, reqwest::Error> {
+        let destinations = WebhookDestinations::new(config);
+        // Redirects are not followed, as they could lead to destinations that are not allowed
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.delivery_timeout_ms))
+            .redirect(redirect::Policy::none())
+            .dns_resolver(Arc::new(destinations))
+            .build()?;
+
+        Ok(Self {
+            poll_interval: Duration::from_secs(config.poll_interval_seconds.max(1)),
+            database: database.clone(),
+            http_client,
+            delivery: Delivery {
+                client,
+                database,
+                retry: RetryPolicy::from(config),
+                destinations,
+            },
+        })
+    }
+
+    /// Evaluates the rules every poll interval, starting immediately. Never returns.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.poll_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            self.evaluate().await;
+        }
+    }
+
+    /// Evaluates every rule whose user configured a webhook once.
+    ///
+    /// Weather of each location is fetched once for all of its rules.
+    #[tracing::instrument(skip_all)]
+    pub async fn evaluate(&self) {
+        let Ok(rules) = self.database.list_scheduled_alert_rules().await else {
+            tracing::error!("reading alert rules failed");
+            return;
+        };
+
+        let mut by_location = BTreeMap::<u64, Vec<ScheduledAlertRule>>::new();
+        for rule in rules {
+            by_location.entry(rule.rule.location_id).or_default().push(rule);
+        }
+
+        futures::stream::iter(by_location.into_values())
+            .map(|rules| self.evaluate_location(rules))
+            .buffer_unordered(Self::MAX_CONCURRENT_FETCHES)
+            .collect::<Vec<()>>()
+            .await;
+    }
+
+    /// Evaluates the rules of a single location.
+    async fn evaluate_location(&self, rules: Vec<ScheduledAlertRule>) {
+        let Some(first) = rules.first() else {
+            return;
+        };
+        let (latitude, longitude) = (first.latitude, first.longitude);
+
+        let conditions = rules
+            .iter()
+            .filter_map(|r| AlertCondition::from_str(&r.rule.condition).ok().map(|c| (r, c)))
+            .collect::<Vec<_>>();
+
+        let current = if conditions.iter().any(|(_, c)| !c.is_forecast()) {
+            self.http_client
+                .get_weather_for_coordinates(latitude, longitude)
+                .await
+                .inspect_err(|e| tracing::warn!(error = %e, "fetching weather for alerts failed"))
+                .ok()
+        } else {
+            None
+        };
+
+        let forecast_hours = conditions
+            .iter()
+            .filter(|(_, c)| c.is_forecast())
+            .map(|(r, _)| r.rule.within_hours.unwrap_or(1))
+            .max();
+        let forecast = match forecast_hours {
+            Some(hours) => self
+                .http_client
+                .get_forecast_for_coordinates(latitude, longitude, hours)
+                .await
+                .inspect_err(|e| tracing::warn!(error = %e, "fetching forecast for alerts failed"))
+                .ok(),
+            None => None,
+        };
+
+        for (scheduled, condition) in conditions {
+            let rule = &scheduled.rule;
+            let evaluation = condition.evaluate(
+                rule.threshold,
+                rule.within_hours,
+                current.as_deref(),
+                forecast.as_deref(),
+            );
+
+            match evaluation {
+                Evaluation::Holds(value) if !rule.firing => {
+                    let fired_at = Utc::now();
+                    // Marked before delivery, so a slow webhook does not cause a second alert
+                    if self.database.set_alert_rule_firing(rule.id, true, Some(fired_at.timestamp()))
+                        .await
+                        .is_err()
+                    {
+                        tracing::error!(rule_id = rule.id, "marking alert rule as firing failed");
+                        continue;
+                    }
+
+                    let event = AlertEvent {
+                        id: generate_id(),
+                        rule_id: rule.id,
+                        condition,
+                        threshold: rule.threshold,
+                        within_hours: rule.within_hours,
+                        value,
+                        location: AlertLocation {
+                            id: rule.location_id,
+                            name: scheduled.location_name.clone(),
+                            latitude: scheduled.latitude,
+                            longitude: scheduled.longitude,
+                        },
+                        fired_at,
+                    };
+                    tracing::info!(rule_id = rule.id, alert_id = event.id, value, "alert rule fired");
+
+                    let delivery = self.delivery.clone();
+                    let scheduled = scheduled.clone();
+                    tokio::spawn(async move { delivery.deliver(&scheduled, &event).await });
+                }
+                Evaluation::Clear if rule.firing => {
+                    let result = self.database.set_alert_rule_firing(rule.id, false, None).await;
+                    if result.is_err() {
+                        tracing::error!(rule_id = rule.id, "re-arming alert rule failed");
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Sends alerts to webhooks and logs every attempt.
+#[derive(Clone)]
+struct Delivery {
+    client: reqwest::Client,
+    database: Database,
+    retry: RetryPolicy,
+    destinations: WebhookDestinations,
+}
+
+impl Delivery {
+    /// Posts the alert to the webhook of the rule, retrying on connection failures, timeouts,
+    /// `408`, `429` and `5xx` responses.
+    ///
+    /// Each attempt is signed with its own timestamp.
+    /// Webhooks pointing to destinations that are not allowed, e.g. set before they were refused
+    /// or whose host resolves differently since, are not called and the attempt is logged as failed.
+    async fn deliver(&self, scheduled: &ScheduledAlertRule, event: &AlertEvent) {
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!(error = %e, "serializing alert failed");
+                return;
+            }
+        };
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let timestamp = Utc::now().timestamp();
+            let result = match self.destinations.check(&scheduled.webhook.url).await {
+                Ok(()) => self
+                    .client
+                    .post(&scheduled.webhook.url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .header(ALERT_ID_HEADER, &event.id)
+                    .header(SIGNATURE_HEADER, sign(&scheduled.webhook.secret, timestamp, &body))
+                    .body(body.clone())
+                    .send()
+                    .await
+                    .map_err(|e| delivery_error(&e)),
+                Err(_) => Err(DESTINATION_NOT_ALLOWED),
+            };
+
+            // Only the kind of failure is logged, as the log is shown to the user and transport errors
+            // would reveal how hosts and ports behind the server respond
+            let (status, error) = match result {
+                Ok(response) => (Some(response.status()), None),
+                Err(error) => (None, Some(error.to_owned())),
+            };
+            let delivered = status.is_some_and(|s| s.is_success());
+
+            let log = AlertDelivery {
+                user_id: scheduled.user_id,
+                rule_id: event.rule_id,
+                event_id: event.id.clone(),
+                attempt,
+                status_code: status.map(|s| s.as_u16()),
+                error,
+                delivered,
+                attempted_at: timestamp,
+            };
+            if self.database.log_alert_delivery(&log).await.is_err() {
+                tracing::error!(alert_id = event.id, "logging alert delivery failed");
+            }
+
+            if delivered {
+                tracing::info!(alert_id = event.id, attempt, "alert delivered");
+                return;
+            }
+
+            let retryable = match status {
+                Some(status) => resilience::is_retryable(status) || status == StatusCode::REQUEST_TIMEOUT,
+                None => log.error.as_deref() != Some(DESTINATION_NOT_ALLOWED),
+            };
+            if !retryable || attempt > self.retry.max_retries {
+                tracing::warn!(alert_id = event.id, attempt, status = log.status_code, "alert delivery failed");
+                return;
+            }
+
+            let delay = self.retry.backoff(attempt);
+            tracing::debug!(alert_id = event.id, attempt, delay_ms = delay.as_millis() as u64, "retrying alert delivery");
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Error of delivery attempts to webhooks pointing to destinations that are not allowed.
+const DESTINATION_NOT_ALLOWED: &str = "destination not allowed";
+
+/// Returns the kind of a failed delivery attempt, as logged for the user.
+fn delivery_error(error: &reqwest::Error) -> &'static str {
+    if error.is_timeout() {
+        "timed out"
+    } else if error.is_connect() {
+        "connection failed"
+    } else {
+        "request failed"
+    }
+}
+
+/// Destinations webhooks may point to.
+///
+/// Only public addresses are allowed unless `allow_private_webhooks` is set, so users can not make the server
+/// call itself, services of its network or cloud metadata endpoints. It also resolves the hosts of
+/// the webhook client, so hosts resolving to other addresses after they are checked are refused too.
+#[derive(Clone, Copy)]
+pub struct WebhookDestinations {
+    allow_private: bool,
+}
+
+impl WebhookDestinations {
+    /// Creates the destinations allowed by given configuration.
+    #[must_use]
+    pub const fn new(config: &AlertsConfig) -> Self {
+        Self {
+            allow_private: config.allow_private_webhooks,
+        }
+    }
+
+    /// Returns whether webhooks may be called at the address.
+    #[must_use]
+    pub fn allows(self, ip: IpAddr) -> bool {
+        self.allow_private || is_public(ip)
+    }
+
+    /// Checks the webhook URL is an absolute `http` or `https` URL whose host is, or resolves only to,
+    /// allowed addresses.
+    ///
+    /// # Errors
+    /// Returns the reason the URL is refused.
+    pub async fn check(self, url: &str) -> Result<(), String> {
+        let url = match Url::parse(url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => url,
+            _ => return Err("URL needs to be an absolute http or https URL".to_owned()),
+        };
+
+        // Addresses are looked up without resolving, IPv6 ones without their brackets
+        let host = url.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+        let port = url.port_or_known_default().unwrap_or_default();
+        let Ok(addresses) = tokio::net::lookup_host((host, port)).await else {
+            return Err("URL host can not be resolved".to_owned());
+        };
+
+        let mut addresses = addresses.peekable();
+        if addresses.peek().is_none() || !addresses.all(|address| self.allows(address.ip())) {
+            return Err("URL needs to point to a public address".to_owned());
+        }
+
+        Ok(())
+    }
+}
+
+impl Resolve for WebhookDestinations {
+    /// Resolves the host to its allowed addresses, failing if there are none.
+    fn resolve(&self, name: Name) -> Resolving {
+        let destinations = *self;
+        Box::pin(async move {
+            let addresses = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| destinations.allows(address.ip()))
+                .collect::<Vec<SocketAddr>>();
+            if addresses.is_empty() {
+                return Err(DESTINATION_NOT_ALLOWED.into());
+            }
+
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Returns whether the address is public: not one `client_ip::is_private` matches, which includes
+/// link-local cloud metadata addresses such as `169.254.169.254`, and not in a reserved IPv4 block.
+///
+/// IPv6 addresses embedding an IPv4 address are classified by the IPv4 address, as they reach it
+/// through translation or tunneling.
+fn is_public(ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V6(ip) => embedded_ipv4(ip).map_or(IpAddr::V6(ip), IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    };
+
+    !client_ip::is_private(ip) && !matches!(ip, IpAddr::V4(ip) if is_reserved(ip))
+}
+
+/// Returns the IPv4 address embedded into the IPv6 address, for IPv4-mapped (`::ffff:0:0/96`),
+/// IPv4-compatible (`::/96`), NAT64 (`64:ff9b::/96`) and 6to4 (`2002::/16`) addresses.
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let octets = ip.octets();
+    let last = Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]);
+
+    match segments {
+        [0, 0, 0, 0, 0, 0 | 0xffff, ..] => Some(last),
+        [0x64, 0xff9b, 0, 0, 0, 0, ..] => Some(last),
+        [0x2002, ..] => Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5])),
+        _ => None,
+    }
+}
+
+/// Returns whether the IPv4 address is in a block reserved for special use: this network (`0.0.0.0/8`),
+/// IETF protocol assignments (`192.0.0.0/24`), benchmarking (`198.18.0.0/15`) or future use (`240.0.0.0/4`).
+fn is_reserved(ip: Ipv4Addr) -> bool {
+    match ip.octets() {
+        [0, ..] | [192, 0, 0, _] | [198, 18 | 19, ..] => true,
+        [first, ..] => first >= 240,
+    }
+}
+
+/// Returns the value of the signature header of a payload, see `SIGNATURE_HEADER`.
+///
+/// # Panics
+/// Never panics, HMAC accepts keys of any length.
+#[must_use]
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    format!("t={timestamp},v1={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Generates a random 256 bit webhook secret in hexadecimal.
+#[must_use]
+pub fn generate_secret() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+
+    hex::encode(bytes)
+}
+
+/// Generates a random 128 bit alert ID in hexadecimal.
+fn generate_id() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+
+    hex::encode(bytes)
+}
@@ -1,37 +1,249 @@
-use crate::authorization::{check_token, create_token};
-use crate::http_client::HttpClient;
-use crate::queries::SqlError;
-use crate::{password, queries};
-use poem::web::RemoteAddr;
+use crate::alerts::{self, AlertCondition, WebhookDestinations};
+use crate::audit::{self, AuditEventKind, AuditOutcome, Requester};
+use crate::authorization::{check_admin_token, create_token, user_id_from_token};
+use crate::backup::{self, BackupFile, Backups};
+use crate::canonical;
+use crate::client_ip::{self, ClientIp};
+use crate::config::DevLocation;
+use crate::history::{self, HistoryEntry};
+use crate::http_client::{self, GeolocationApiResponse, HttpClient, Sourced, WeatherApiResponse};
+use crate::live::LiveWeather;
+use crate::problem::{FieldError, Problem, ProblemBody, ProblemCode};
+use crate::queries::{
+    AlertDelivery, AlertRule, AuditEvent, AuditFilter, Database, NewAlertRule, NewLocation, Preferences,
+    SavedLocation, SqlError, Webhook,
+};
+use crate::readiness::{Readiness, ReadinessReport};
+use crate::units::{self, Units};
+use crate::password;
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use poem::web::Data;
+use poem::Body;
 use poem_openapi::auth::Bearer;
-use poem_openapi::payload::Json;
+use poem_openapi::param::{Header, Path, Query};
+use poem_openapi::payload::{Binary, EventStream, Json};
 use poem_openapi::{ApiResponse, Object, OpenApi, SecurityScheme};
-use sqlx::SqlitePool;
-use std::net::SocketAddr;
+use std::net::IpAddr;
 use std::str::FromStr;
-
-/// We hijack the debug_assertions compilation condition to enable replacing local IPs
-#[cfg(debug_assertions)]
-use {
-    rand::Rng,
-    std::net::{IpAddr, Ipv4Addr},
-};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::{Instant, MissedTickBehavior};
 
 /// Holds the state and defines the handlers of the API.
 pub struct Api {
-    /// HTTP client wrapping the foreing geolocation and the weather APIs.
-    http_client: HttpClient,
+    /// HTTP client wrapping the foreing geolocation and the weather APIs, shared with the alert scheduler.
+    http_client: Arc<HttpClient>,
     /// Database connection.
-    database: SqlitePool,
+    database: Database,
+    /// Dependency checks used by `ready`.
+    readiness: Readiness,
+    /// Weather feeds shared by `weather_stream` subscribers.
+    live: LiveWeather,
+    /// Backup facility used by `create_backup` and `list_backups`, shared with the backup schedule.
+    backups: Arc<Backups>,
+    /// Set when the server is shutting down, ends `weather_stream` subscriptions.
+    shutdown: watch::Receiver<bool>,
+    /// Location of callers with private addresses, which fail to be located without it.
+    dev_location: Option<DevLocation>,
+    /// Whether `register` refuses usernames mixing letters of commonly confused scripts.
+    reject_confusable_usernames: bool,
+    /// Destinations `set_webhook` allows webhooks to point to.
+    webhook_destinations: WebhookDestinations,
 }
 
 impl Api {
-    /// Creates an instance of the API with given HTTP client and the database connection.
+    /// Creates an instance of the API with given HTTP client, the database connection, the readiness checker,
+    /// the live weather feeds, the backup facility, the receiver of shutdown requests,
+    /// the location of callers with private addresses, whether confusable usernames are refused
+    /// and the destinations webhooks may point to.
     #[must_use]
-    pub const fn new(http_client: HttpClient, database: SqlitePool) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub const fn new(
+        http_client: Arc<HttpClient>,
+        database: Database,
+        readiness: Readiness,
+        live: LiveWeather,
+        backups: Arc<Backups>,
+        shutdown: watch::Receiver<bool>,
+        dev_location: Option<DevLocation>,
+        reject_confusable_usernames: bool,
+        webhook_destinations: WebhookDestinations,
+    ) -> Self {
         Self {
             http_client,
             database,
+            readiness,
+            live,
+            backups,
+            shutdown,
+            dev_location,
+            reject_confusable_usernames,
+            webhook_destinations,
+        }
+    }
+
+    /// Most locations a user can save.
+    const MAX_LOCATIONS: u64 = 50;
+
+    /// Most alert rules a user can create.
+    const MAX_ALERT_RULES: u64 = 50;
+
+    /// Most weather calls made at once for `weather_for_locations`.
+    const MAX_CONCURRENT_FETCHES: usize = 8;
+
+    /// Most audit events returned by a `list_audit_events` call.
+    const MAX_AUDIT_EVENTS: u32 = 1000;
+
+    /// Audit events read from the database at once by `export_audit_events`.
+    const AUDIT_EXPORT_PAGE_SIZE: u32 = 1000;
+
+    /// Returns the ID of the user the JWT token is issued for, if it is valid and the user is active.
+    ///
+    /// Tokens of disabled and deleted users are refused before they expire,
+    /// as are all tokens when the status of the user can not be read.
+    async fn authenticate(&self, authorization: &JwtAuthorization) -> Option<u64> {
+        let user_id = user_id_from_token(&authorization.0.token)?;
+
+        match self.database.is_user_active(user_id).await {
+            Ok(true) => Some(user_id),
+            Ok(false) => {
+                tracing::info!(user_id, "token of an inactive user refused");
+                None
+            }
+            Err(_) => {
+                tracing::error!(user_id, "reading the status of the user failed");
+                None
+            }
+        }
+    }
+
+    /// Validates the requested units and language and falls back to the preferences of the user
+    /// for the ones not requested.
+    ///
+    /// # Errors
+    /// Returns an `invalid_fields` problem if the language is not valid.
+    async fn resolve_units_and_lang(
+        &self,
+        user_id: u64,
+        units: Option<Units>,
+        lang: Option<String>,
+    ) -> Result<(Units, Option<String>), Problem> {
+        let lang = lang
+            .as_deref()
+            .map(units::normalize_lang)
+            .transpose()
+            .map_err(|message| Problem::invalid_fields(vec![FieldError::new("lang", &message)]))?;
+
+        let preferences = if units.is_some() && lang.is_some() {
+            Preferences::default()
+        } else {
+            self.preferences(user_id).await
+        };
+        let units = units
+            .or_else(|| preferences.units.as_deref().and_then(|u| Units::from_str(u).ok()))
+            .unwrap_or_default();
+
+        Ok((units, lang.or(preferences.lang)))
+    }
+
+    /// Returns the location weather calls of the user are for: the saved location with given ID,
+    /// the default location of the user or the location of the caller's IP address, in that order.
+    ///
+    /// # Errors
+    /// Returns a `not_found` problem if the user has no saved location with given ID,
+    /// an `internal_error` problem if it can not be read or the caller's address can not be determined,
+    /// a `private_address` problem if the address is private and no development location is configured
+    /// and the problem describing the failure of the geolocation API.
+    async fn resolve_location(
+        &self,
+        user_id: u64,
+        location_id: Option<u64>,
+        client_ip: ClientIp,
+    ) -> Result<ResolvedLocation, Problem> {
+        let saved_location = match location_id {
+            Some(location_id) => match self.database.get_location(user_id, location_id).await {
+                Ok(Some(l)) => Some(l),
+                Ok(None) => return Err(Problem::new(ProblemCode::NotFound, "Location does not exist.")),
+                Err(_) => {
+                    tracing::error!("reading the location failed");
+                    return Err(Problem::new(ProblemCode::InternalError, "Could not read the location."));
+                }
+            },
+            None => self.database.get_default_location(user_id)
+                .await
+                .unwrap_or_else(|_| {
+                    tracing::warn!(user_id, "reading the default location failed, locating the caller");
+                    None
+                }),
+        };
+
+        if let Some(saved_location) = saved_location {
+            return Ok(ResolvedLocation::Saved(saved_location));
+        }
+
+        let ClientIp(Some(ip)) = client_ip else {
+            return Err(Problem::new(ProblemCode::InternalError, "Could not fetch user IP."));
+        };
+
+        let ip = if client_ip::is_private(ip) {
+            match self.dev_location {
+                Some(DevLocation::Coordinates { latitude, longitude }) => {
+                    return Ok(ResolvedLocation::Configured { latitude, longitude });
+                }
+                Some(DevLocation::Ip { ip }) => ip,
+                None => return Err(Problem::new(
+                    ProblemCode::PrivateAddress,
+                    "The caller's address is private and can not be located, save a location or pass one instead.",
+                )),
+            }
+        } else {
+            ip
+        };
+
+        self.http_client
+            .get_coordinates_for_ip(&ip.to_string())
+            .await
+            .map(ResolvedLocation::Located)
+            .map_err(|e| upstream_failure_problem("geolocation", &e))
+    }
+
+    /// Fetches the weather at given coordinates and creates the response body.
+    ///
+    /// # Errors
+    /// Returns the problem describing the failure of the weather API.
+    async fn current_weather(
+        &self,
+        coordinates: Coordinates<'_>,
+        units: Units,
+        lang: Option<String>,
+    ) -> Result<WeatherResponseBody, Problem> {
+        let (latitude, longitude) = coordinates.latitude_and_longitude();
+        let response = self
+            .http_client
+            .get_localized_weather_for_coordinates(latitude, longitude, lang.as_deref())
+            .await
+            .map_err(|e| upstream_failure_problem("weather", &e))?;
+
+        // Localized condition texts are not stored, so the history has a single language
+        if lang.is_none() {
+            history::record_current(&self.database, latitude, longitude, &response).await;
+        }
+
+        Ok(WeatherResponseBody::from_sourced(response, coordinates, units, lang))
+    }
+
+    /// Returns the preferences of the user, or no preferences if they can not be read,
+    /// so weather requests do not fail because of them.
+    async fn preferences(&self, user_id: u64) -> Preferences {
+        match self.database.get_preferences(user_id).await {
+            Ok(preferences) => preferences.unwrap_or_default(),
+            Err(_) => {
+                tracing::warn!(user_id, "reading preferences failed, using defaults");
+                Preferences::default()
+            }
         }
     }
 }
@@ -44,39 +256,85 @@ impl Api {
     ///
     /// # Returns
     /// `200 Success` on every call
-    #[oai(path = "/health_check", method = "get")]
+    #[oai(path = "/health_check", method = "get", operation_id = "health_check")]
     pub async fn health_check(&self) -> HealthResponse {
         HealthResponse::Alive
     }
 
+    /// Checks whether the server can serve requests.
+    ///
+    /// Unlike `health_check`, checks the database is reachable and migrated and, if enabled
+    /// in configuration, probes the geolocation and weather APIs. Upstream probe results are cached.
+    ///
+    /// # Returns
+    /// `200 Success` with a report of each dependency if every critical dependency is up.
+    ///
+    /// `503 Service Unavailable` with a report of each dependency if a critical dependency is down.
+    #[oai(path = "/ready", method = "get", operation_id = "ready")]
+    #[tracing::instrument(skip_all)]
+    pub async fn ready(&self) -> ReadyResponse {
+        let report = self.readiness.check(&self.database, &self.http_client).await;
+
+        if report.ready {
+            ReadyResponse::Ready(Json(report))
+        } else {
+            tracing::warn!("a critical dependency is down");
+            ReadyResponse::NotReady(Json(report))
+        }
+    }
+
     /// Registers a user.
     ///
     /// Password is hashed with Argon2 before getting persisted.
+    /// Every attempt is recorded in the audit log with its outcome.
     /// 
     /// Client credentials have following restrictions:
     /// - Username can be 6..=24 characters long and can only contain
     /// letters, numbers, dot and underscore.
     /// - Password can be 8..=32 characters long and can only contain letters, numbers and symbols
     /// ~!@$%^&*()_-+={\[\}\]|:',.?/
+    /// - Username can not mix Latin, Greek and Cyrillic letters, if `accounts.reject_confusable_usernames` is set.
+    ///
+    /// Usernames and emails are compared in their canonical forms, Unicode NFKC normalized and case folded,
+    /// so a user can not be registered twice with variants of the same identity.
     /// 
     /// # Returns
     /// `201 Created` with the created user's ID on success.
     ///
+    /// `400 Bad Request` with every invalid field if credentials are not valid.
+    ///
     /// `409 Conflict` if user already exists.
     ///
     /// `500 Internal Server Error` if the database operation fails.
-    #[oai(path = "/register", method = "post")]
-    pub async fn register(&self, body: Json<RegisterBody>) -> RegisterResponse {
+    #[oai(path = "/register", method = "post", operation_id = "register")]
+    #[tracing::instrument(skip_all)]
+    pub async fn register(
+        &self,
+        client_ip: Data<&ClientIp>,
+        /// Client software, recorded in the audit log.
+        #[oai(name = "User-Agent")]
+        user_agent: Header<Option<String>>,
+        body: Json<RegisterBody>,
+    ) -> RegisterResponse {
+        let requester = Requester { ip: client_ip.0 .0, user_agent: user_agent.0 };
         let credentials = match RegisterCredentials::try_from(body.0) {
             Ok(c) => c,
-            Err(e) => return RegisterResponse::InvalidCredentials(
-                ResponseMessage::new(&format!("Invalid credentials: {e}")).into_json()
-            ),
+            Err(errors) => {
+                audit::record(&self.database, AuditEventKind::Registration, AuditOutcome::Failure, None, &requester)
+                    .await;
+                return RegisterResponse::InvalidCredentials(Problem::validation(errors).into_json());
+            }
         };
 
+        if self.reject_confusable_usernames && canonical::mixes_confusable_scripts(&credentials.username) {
+            audit::record(&self.database, AuditEventKind::Registration, AuditOutcome::Failure, None, &requester)
+                .await;
+            let errors = vec![FieldError::new("username", CONFUSABLE_USERNAME_MESSAGE)];
+            return RegisterResponse::InvalidCredentials(Problem::validation(errors).into_json());
+        }
+
         let password_hash = password::hash(&credentials.password);
-        let user_id = match queries::register_user(
-            &self.database,
+        let user_id = match self.database.register_user(
             &credentials.username,
             &credentials.email,
             &password_hash,
@@ -84,16 +342,28 @@ impl Api {
         .await
         {
             Ok(i) => i,
-            Err(SqlError::UniqueConstraintViolation) => return RegisterResponse::AlreadyRegistered(
-                ResponseMessage::new("A user with given credentials already exists.")
-                    .into_json()
-            ),
-            Err(SqlError::Other) => return RegisterResponse::RegistrationFailed(
-                ResponseMessage::new("Registration failed . Try again.")
-                    .into_json()
-            ),
+            Err(SqlError::UniqueConstraintViolation) => {
+                audit::record(&self.database, AuditEventKind::Registration, AuditOutcome::Failure, None, &requester)
+                    .await;
+                return RegisterResponse::AlreadyRegistered(
+                    Problem::new(ProblemCode::AlreadyRegistered, "A user with given credentials already exists.")
+                        .into_json()
+                );
+            }
+            Err(SqlError::Other | SqlError::Unsupported) => {
+                tracing::error!("persisting the user failed");
+                audit::record(&self.database, AuditEventKind::Registration, AuditOutcome::Failure, None, &requester)
+                    .await;
+                return RegisterResponse::RegistrationFailed(
+                    Problem::new(ProblemCode::InternalError, "Registration failed. Try again.")
+                        .into_json()
+                );
+            }
         };
 
+        tracing::info!(user_id, "user registered");
+        audit::record(&self.database, AuditEventKind::Registration, AuditOutcome::Success, Some(user_id), &requester)
+            .await;
         RegisterResponse::Registered(Json(RegisterResponseBody { user_id }))
     }
 
@@ -103,296 +373,2826 @@ impl Api {
     /// If the user does not exist with given identifier, the password is still hashed and
     /// compared against a placeholder hash as a measure against timing attacks.
     ///
+    /// Logins and the tokens issued by them are recorded in the audit log, failed logins with the user
+    /// the identifier matched, if any.
+    ///
     /// # Returns
     /// `200 Success` and a JWT token if passwords match.
     ///
     /// `404 Not Found` if such user does not exist or password do not match.
     ///
     /// `500 Internal Server Error` if JWT token creation fails.
-    #[oai(path = "/login", method = "post")]
-    pub async fn login(&self, body: Json<LoginBody>) -> LoginResponse {
+    #[oai(path = "/login", method = "post", operation_id = "login")]
+    #[tracing::instrument(skip_all)]
+    pub async fn login(
+        &self,
+        client_ip: Data<&ClientIp>,
+        /// Client software, recorded in the audit log.
+        #[oai(name = "User-Agent")]
+        user_agent: Header<Option<String>>,
+        body: Json<LoginBody>,
+    ) -> LoginResponse {
+        let requester = Requester { ip: client_ip.0 .0, user_agent: user_agent.0 };
         let (user_id, password_hash) =
-            queries::get_user_id_and_password_by_username_or_email(&self.database, &body.identifier, &body.identifier).await;
+            self.database.get_user_id_and_password_by_username_or_email(&body.identifier, &body.identifier).await;
+        // ID 0 stands for no matching user
+        let matched_user = (user_id != 0).then_some(user_id);
 
         let password_match = password::validate(body.password.clone(), password_hash).await;
         let Ok(token) = create_token(user_id) else {
+            tracing::error!("token creation failed");
+            let outcome = if password_match { AuditOutcome::Success } else { AuditOutcome::Failure };
+            audit::record(&self.database, AuditEventKind::Login, outcome, matched_user, &requester).await;
+            if password_match {
+                audit::record(&self.database, AuditEventKind::TokenIssuance, AuditOutcome::Failure, matched_user, &requester)
+                    .await;
+            }
             return LoginResponse::CouldNotCreateToken(
-                ResponseMessage::new("Login failed.").into_json()
+                Problem::new(ProblemCode::InternalError, "Login failed.").into_json()
             );
         };
 
         if password_match {
+            tracing::info!(user_id, "user logged in");
+            audit::record(&self.database, AuditEventKind::Login, AuditOutcome::Success, matched_user, &requester).await;
+            audit::record(&self.database, AuditEventKind::TokenIssuance, AuditOutcome::Success, matched_user, &requester)
+                .await;
             LoginResponse::LoggedIn(Json(LoginResponseBody { token }))
         } else {
+            tracing::info!("login failed");
+            audit::record(&self.database, AuditEventKind::Login, AuditOutcome::Failure, matched_user, &requester).await;
             LoginResponse::WrongCredentials(
-                ResponseMessage::new("Username/email or password is wrong.").into_json()
+                Problem::new(ProblemCode::WrongCredentials, "Username/email or password is wrong.").into_json()
             )
         }
     }
 
     /// Returns weather information for the caller.
-    /// Location of the user is determined with their IP address.
     ///
-    /// An HTTP call to a geolocation API with the caller's IP is made to get their coordinates.
+    /// Location is the saved location with ID `location` if given, otherwise the default saved location
+    /// of the user, otherwise determined with the caller's IP address: an HTTP call to a geolocation API
+    /// with the caller's IP is made to get their coordinates.
     /// Then the weather information for that coordinate is obtained
     /// with an HTTP call to a weather API.
+    /// Configured providers are tried in order for both calls,
+    /// the providers that served the response are included in it.
+    ///
+    /// Values are converted to `units` and condition text is in `lang`, if the weather provider
+    /// supports it. Either falls back to the preferences of the user, then to `metric` units
+    /// and the language of the provider.
     ///
     /// Requires a valid JWT token.
     ///
     /// # Returns
     /// `200 Success` with the weather information on success.
     ///
+    /// `400 Bad Request` if `units` or `lang` is not valid.
+    ///
     /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
     ///
-    /// `500 Internal Server Error` if the call to foreign APIs fail.
-    #[oai(path = "/weather", method = "get")]
+    /// `404 Not Found` if the user has no saved location with ID `location`.
+    ///
+    /// `422 Unprocessable Entity` if the caller has to be located but their address is private
+    /// and no development location is configured.
+    ///
+    /// `500 Internal Server Error` if the caller's address can not be determined or reading saved locations fails.
+    ///
+    /// `502 Bad Gateway` if a foreign API fails or responds with an error.
+    ///
+    /// `503 Service Unavailable` if a foreign API is rate limiting, out of quota or its circuit is open.
+    ///
+    /// `504 Gateway Timeout` if a foreign API does not respond in time.
+    #[oai(path = "/weather", method = "get", operation_id = "weather")]
+    #[tracing::instrument(skip_all)]
     pub async fn weather(
         &self,
         authorization: JwtAuthorization,
-        ip: &RemoteAddr,
+        client_ip: Data<&ClientIp>,
+        /// ID of a saved location to return the weather of.
+        location: Query<Option<u64>>,
+        /// Unit system of the values, `metric`, `imperial` or `si`.
+        units: Query<Option<Units>>,
+        /// Language of the condition text, e.g. `fr` or `zh_tw`.
+        lang: Query<Option<String>>,
     ) -> WeatherResponse {
-        if !check_token(&authorization.0.token) {
+        let Some(user_id) = self.authenticate(&authorization).await else {
             return WeatherResponse::Unauthorized(
-                ResponseMessage::new("Unauthorized access.").into_json()
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
             );
-        }
-
-        let ip_string = match ip.as_socket_addr() {
-            Some(addr) => get_ip_string(addr),
-            None => return WeatherResponse::GeolocationQueryFailed(
-                ResponseMessage::new("Could not fetch user IP.").into_json()
-            ),
         };
 
-        let Ok(response) = self.http_client.get_coordinates_for_ip(&ip_string).await else {
-            return WeatherResponse::GeolocationQueryFailed(
-                ResponseMessage::new("Could not fetch user location.").into_json()
-            );
+        let (units, lang) = match self.resolve_units_and_lang(user_id, units.0, lang.0).await {
+            Ok(r) => r,
+            Err(problem) => return WeatherResponse::InvalidRequest(problem.into_json()),
         };
 
-        let Ok(response) = self
-            .http_client
-            .get_weather_for_coordinates(response.latitude, response.longitude)
-            .await
-        else {
-            return WeatherResponse::WeatherQueryFailed(
-                ResponseMessage::new("Could not fetch weather information.").into_json()
+        match self.resolve_location(user_id, location.0, **client_ip).await {
+            Ok(resolved) => match self.current_weather(resolved.coordinates(), units, lang).await {
+                Ok(body) => WeatherResponse::Success(Json(Box::new(body))),
+                Err(problem) => WeatherResponse::from_problem(problem),
+            },
+            Err(problem) => WeatherResponse::from_problem(problem),
+        }
+    }
+
+    /// Returns the current weather at every saved location of the caller, in the order they are saved.
+    ///
+    /// Weather of the locations is fetched concurrently. Failure of one location does not fail the others,
+    /// its entry carries the problem instead of the weather.
+    ///
+    /// `units` and `lang` are handled as in `weather`.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `200 Success` with an entry for each saved location.
+    ///
+    /// `400 Bad Request` if `units` or `lang` is not valid.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `500 Internal Server Error` if reading saved locations fails.
+    #[oai(path = "/weather/locations", method = "get", operation_id = "weather_for_locations")]
+    #[tracing::instrument(skip_all)]
+    pub async fn weather_for_locations(
+        &self,
+        authorization: JwtAuthorization,
+        /// Unit system of the values, `metric`, `imperial` or `si`.
+        units: Query<Option<Units>>,
+        /// Language of the condition text, e.g. `fr` or `zh_tw`.
+        lang: Query<Option<String>>,
+    ) -> LocationsWeatherResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return LocationsWeatherResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
             );
         };
 
-        let response_body = WeatherResponseBody {
-            temperature: response.current.temp_c,
-            feels_like: response.current.feelslike_c,
-            condition: response.current.condition.text,
-            last_updated: response.current.last_updated,
+        let (units, lang) = match self.resolve_units_and_lang(user_id, units.0, lang.0).await {
+            Ok(r) => r,
+            Err(problem) => return LocationsWeatherResponse::InvalidRequest(problem.into_json()),
         };
 
-        WeatherResponse::Success(Json(response_body))
-    }
-}
+        let Ok(locations) = self.database.list_locations(user_id).await else {
+            tracing::error!("reading locations failed");
+            return LocationsWeatherResponse::Failed(
+                Problem::new(ProblemCode::InternalError, "Could not read locations.").into_json()
+            );
+        };
 
-/// Information used in `register` request body.
-/// 
-/// For credentials restrictions, see `Api::register`
-#[derive(serde::Serialize, Object)]
-pub struct RegisterBody {
-    /// User's username.
-    pub username: String,
-    /// User's email.
-    pub email: String,
-    /// User's password.
-    pub password: String,
-}
+        let entries = futures::stream::iter(locations)
+            .map(|location| {
+                let lang = lang.clone();
+                async move {
+                    let result = self.current_weather(Coordinates::Saved(&location), units, lang).await;
+                    let (weather, problem) = match result {
+                        Ok(body) => (Some(Box::new(body)), None),
+                        Err(problem) => (None, Some(problem)),
+                    };
 
-/// Used to validate credentials are valid.
-struct RegisterCredentials {
-    /// User's username.
-    username: String,
-    /// User's email.
-    email: String,
-    /// User's password.
-    password: String,
-}
+                    LocationWeather {
+                        location: LocationBody::from(location),
+                        weather,
+                        problem,
+                    }
+                }
+            })
+            .buffered(Self::MAX_CONCURRENT_FETCHES)
+            .collect::<Vec<_>>()
+            .await;
 
-impl TryFrom<RegisterBody> for RegisterCredentials {
-    type Error = String;
+        LocationsWeatherResponse::Success(Json(entries))
+    }
 
-    fn try_from(RegisterBody { username, email, password }: RegisterBody) -> Result<Self, Self::Error> {
-        if !(6usize..=24usize).contains(&username.len()) {
-            let error_message = "Username needs to be at least 6 and at most 24 characters".to_owned();
-            return Err(error_message);
-        }
+    /// Returns the hourly weather observations at a location from `from` to `to`, inclusive, in UTC.
+    ///
+    /// The location is resolved as in `weather`. Observations are served from the database where it has
+    /// every hour of a day, the other days are fetched from the weather providers and stored.
+    /// Observations are returned oldest first in pages of `page_size`, defaults to 24 and is capped at 168.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `200 Success` with the requested page of observations.
+    ///
+    /// `400 Bad Request` if `from` is after `to`, the range is longer than 7 days, `to` is in the future
+    /// or `page` or `page_size` is 0.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `404 Not Found` if the user has no saved location with ID `location`.
+    ///
+    /// `422 Unprocessable Entity` if the caller has to be located but their address is private
+    /// and no development location is configured.
+    ///
+    /// `500 Internal Server Error` if the caller's address can not be determined or reading saved locations fails.
+    ///
+    /// `502 Bad Gateway` if a foreign API fails or responds with an error.
+    ///
+    /// `503 Service Unavailable` if a foreign API is rate limiting, out of quota or its circuit is open.
+    ///
+    /// `504 Gateway Timeout` if a foreign API does not respond in time.
+    #[oai(path = "/weather/history", method = "get", operation_id = "weather_history")]
+    #[tracing::instrument(skip_all)]
+    // Every query parameter is an argument of the handler
+    #[allow(clippy::too_many_arguments)]
+    pub async fn weather_history(
+        &self,
+        authorization: JwtAuthorization,
+        client_ip: Data<&ClientIp>,
+        /// First day of the range, in `YYYY-MM-DD` form.
+        from: Query<NaiveDate>,
+        /// Last day of the range, in `YYYY-MM-DD` form.
+        to: Query<NaiveDate>,
+        /// ID of a saved location to return the history of.
+        location: Query<Option<u64>>,
+        /// Unit system of the values, `metric`, `imperial` or `si`.
+        units: Query<Option<Units>>,
+        /// Number of the page to return, starting from 1.
+        page: Query<Option<u32>>,
+        /// Most observations in a page.
+        page_size: Query<Option<u32>>,
+    ) -> HistoryResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return HistoryResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
 
-        if username.chars().any(|c| !c.is_alphanumeric() && !['.', '_'].contains(&c)) {
-            let error_message = "Username can only contain letters, numbers, dots and underscores".to_owned();
-            return Err(error_message);
+        let (from, to) = (from.0, to.0);
+        let (page, page_size) = (page.0.unwrap_or(1), page_size.0.unwrap_or(24).min(168));
+        let errors = validate_history_query(from, to, page, page_size);
+        if !errors.is_empty() {
+            return HistoryResponse::InvalidRequest(Problem::invalid_fields(errors).into_json());
         }
 
-        let Ok(email) = email_address::EmailAddress::from_str(&email) else {
-            let error_message = "Username can only contain letters, numbers, dots and underscores".to_owned();
-            return Err(error_message);
+        let units = match self.resolve_units_and_lang(user_id, units.0, None).await {
+            Ok((units, _)) => units,
+            Err(problem) => return HistoryResponse::from_problem(problem),
         };
 
-        if !(8usize..=32usize).contains(&password.len()) {
-            let error_message = "Password needs to be at least 8 and at most 32 characters".to_owned();
-            return Err(error_message);
-        }
+        let resolved = match self.resolve_location(user_id, location.0, **client_ip).await {
+            Ok(resolved) => resolved,
+            Err(problem) => return HistoryResponse::from_problem(problem),
+        };
+        let coordinates = resolved.coordinates();
+        let (latitude, longitude) = coordinates.latitude_and_longitude();
 
-        let allowed_chars = "~!@$%^&*()_-+={[}]|:',.?/";
-        if password.chars().any(|c| !c.is_alphanumeric() && !allowed_chars.chars().any(|symbol| symbol.eq(&c))) {
-            let error_message = format!("Username can only contain letters, numbers and symbols {allowed_chars}");
-            return Err(error_message);
-        }
+        let entries = match history::observations(&self.database, &self.http_client, latitude, longitude, from, to).await {
+            Ok(entries) => entries,
+            Err(e) => return HistoryResponse::from_problem(upstream_failure_problem("weather", &e)),
+        };
 
-        let credentials = RegisterCredentials { username, email: email.email(), password };
+        let total = entries.len();
+        let observations = entries
+            .into_iter()
+            .skip((page as usize - 1) * page_size as usize)
+            .take(page_size as usize)
+            .map(|entry| ObservationBody::new(entry, units))
+            .collect();
 
-        Ok(credentials)
+        HistoryResponse::Success(Json(HistoryResponseBody {
+            units,
+            latitude,
+            longitude,
+            saved_location_id: match coordinates {
+                Coordinates::Saved(location) => Some(location.id),
+                Coordinates::Located(_) | Coordinates::Configured { .. } => None,
+            },
+            from,
+            to,
+            page,
+            page_size,
+            total: total as u64,
+            observations,
+        }))
     }
-}
-
-/// Information used in `login` request body.
-#[derive(serde::Serialize, Object)]
-pub struct LoginBody {
-    /// Can either be user's `username` or `email`.
-    pub identifier: String,
-    /// User's password
-    pub password: String,
-}
 
-/// Describes authorization used in `weather` request.
-#[derive(SecurityScheme)]
-#[oai(ty = "bearer")]
-pub struct JwtAuthorization(Bearer);
+    /// Streams the current weather as Server-Sent Events, sending an event whenever the weather is updated.
+    ///
+    /// The location, `units` and `lang` are resolved once, as in `weather`. Subscribers of the same coordinates
+    /// and language share a single call to the weather API per poll interval. The latest weather is sent
+    /// as soon as it is known, failed calls are retried on the next poll without ending the stream.
+    ///
+    /// Requires a valid JWT token. The status of the user is checked again every poll interval,
+    /// and the stream ends once they are disabled or deleted.
+    ///
+    /// # Returns
+    /// `200 Success` with an event stream of `WeatherResponseBody` objects.
+    ///
+    /// `400 Bad Request` if `units` or `lang` is not valid.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `404 Not Found` if the user has no saved location with ID `location`.
+    ///
+    /// `422 Unprocessable Entity` if the caller has to be located but their address is private
+    /// and no development location is configured.
+    ///
+    /// `500 Internal Server Error` if the caller's address can not be determined or reading saved locations fails.
+    ///
+    /// `502 Bad Gateway`, `503 Service Unavailable` or `504 Gateway Timeout` if locating the caller fails.
+    #[oai(path = "/weather/stream", method = "get", operation_id = "weather_stream")]
+    #[tracing::instrument(skip_all)]
+    pub async fn weather_stream(
+        &self,
+        authorization: JwtAuthorization,
+        client_ip: Data<&ClientIp>,
+        /// ID of a saved location to stream the weather of.
+        location: Query<Option<u64>>,
+        /// Unit system of the values, `metric`, `imperial` or `si`.
+        units: Query<Option<Units>>,
+        /// Language of the condition text, e.g. `fr` or `zh_tw`.
+        lang: Query<Option<String>>,
+    ) -> WeatherStreamResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return WeatherStreamResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
 
-/// Response of `health_check` call.
-#[derive(ApiResponse)]
-pub enum HealthResponse {
-    /// Returned on all calls
-    #[oai(status = 200)]
-    Alive,
-}
+        let (units, lang) = match self.resolve_units_and_lang(user_id, units.0, lang.0).await {
+            Ok(r) => r,
+            Err(problem) => return WeatherStreamResponse::from_problem(problem),
+        };
 
-/// Response of `register` call.
-#[derive(ApiResponse)]
-pub enum RegisterResponse {
-    /// Returned when registration succeeds.
-    #[oai(status = 201)]
-    Registered(Json<RegisterResponseBody>),
-    /// Returned when registration credentials are not valid.
-    #[oai(status = 400)]
-    InvalidCredentials(ResponseBody),
-    /// Returned when user with same credentials exists.
-    #[oai(status = 409)]
-    AlreadyRegistered(ResponseBody),
-    /// Returned when persisting the user fails.
-    #[oai(status = 500)]
-    RegistrationFailed(ResponseBody),
-}
+        let resolved = match self.resolve_location(user_id, location.0, **client_ip).await {
+            Ok(resolved) => resolved,
+            Err(problem) => return WeatherStreamResponse::from_problem(problem),
+        };
 
-/// Body of `register` call success response.
-#[derive(serde::Deserialize, Object)]
-pub struct RegisterResponseBody {
-    /// ID of registered user.
-    pub user_id: u64,
-}
+        let (latitude, longitude) = resolved.coordinates().latitude_and_longitude();
+        let receiver = self.live.subscribe(latitude, longitude, lang.clone());
+        let mut shutdown = self.shutdown.clone();
+        let database = self.database.clone();
+        let status_interval = self.live.poll_interval();
+        tracing::info!(user_id, "weather stream subscribed");
 
-/// Response of `login` call.
-#[derive(ApiResponse)]
-pub enum LoginResponse {
-    /// Returned when user successfully logs in.
-    #[oai(status = 200)]
-    LoggedIn(Json<LoginResponseBody>),
-    /// Returned when such user does not exist or password does not match.
-    #[oai(status = 404)]
-    WrongCredentials(ResponseBody),
-    /// Returned when JWT token creation fails.
-    #[oai(status = 500)]
-    CouldNotCreateToken(ResponseBody),
-}
+        let updates = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.changed().await.ok()?;
+            let latest = receiver.borrow_and_update().clone();
+            Some((latest, receiver))
+        })
+        .filter_map(futures::future::ready)
+        .map(move |response| {
+            let response = Sourced { provider: response.provider, value: response.value.clone() };
+            WeatherResponseBody::from_sourced(response, resolved.coordinates(), units, lang.clone())
+        })
+        .take_until(async move {
+            tokio::select! {
+                _ = shutdown.wait_for(|shutting_down| *shutting_down) => {}
+                () = wait_until_inactive(&database, user_id, status_interval) => {}
+            }
+        });
 
-/// Body of `login` call success response.
-#[derive(serde::Deserialize, Object)]
-pub struct LoginResponseBody {
-    /// Created JWT token.
-    pub token: String,
-}
+        WeatherStreamResponse::Success(EventStream::new(updates.boxed()).keep_alive(self.live.keep_alive()))
+    }
 
-/// Response of `weather` call.
-#[derive(ApiResponse)]
-pub enum WeatherResponse {
-    /// Returned when weather information is successfully obtained.
-    #[oai(status = 200)]
-    Success(Json<WeatherResponseBody>),
-    /// Returned when no token is provided or provided token is invalid.
-    #[oai(status = 401)]
-    Unauthorized(ResponseBody),
-    /// Returned when call to geolocation API fails.
-    #[oai(status = 500)]
-    GeolocationQueryFailed(ResponseBody),
-    /// Returned when call to weather API fails.
-    #[oai(status = 500)]
-    WeatherQueryFailed(ResponseBody),
-}
+    /// Returns the saved locations of the caller, in the order they are saved.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `200 Success` with the saved locations.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/locations", method = "get", operation_id = "list_locations")]
+    #[tracing::instrument(skip_all)]
+    pub async fn list_locations(&self, authorization: JwtAuthorization) -> LocationsResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return LocationsResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
 
-/// Body of `weather` call success response.
-#[derive(serde::Deserialize, Object)]
-pub struct WeatherResponseBody {
-    temperature: f64,
-    feels_like: f64,
-    condition: String,
-    last_updated: String,
-}
+        match self.database.list_locations(user_id).await {
+            Ok(locations) => LocationsResponse::Success(Json(
+                locations.into_iter().map(LocationBody::from).collect()
+            )),
+            Err(_) => {
+                tracing::error!("reading locations failed");
+                LocationsResponse::Failed(
+                    Problem::new(ProblemCode::InternalError, "Could not read locations.").into_json()
+                )
+            }
+        }
+    }
 
-/// A response body serializable to JSON by poem-openapi
-pub type ResponseBody = Json<ResponseMessage>;
+    /// Saves a named location for the caller.
+    ///
+    /// If `default` is set, the location replaces the previous default location of the caller.
+    ///
+    /// Location names can be 1..=64 characters long, latitudes must be within -90..=90
+    /// and longitudes within -180..=180 degrees.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `201 Created` with the saved location.
+    ///
+    /// `400 Bad Request` with every invalid field if the location is not valid.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `409 Conflict` if the caller has a location with the same name or has saved as many locations as allowed.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/locations", method = "post", operation_id = "create_location")]
+    #[tracing::instrument(skip_all)]
+    pub async fn create_location(
+        &self,
+        authorization: JwtAuthorization,
+        body: Json<NewLocationBody>,
+    ) -> LocationResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return LocationResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
 
-/// A response body that used to return generic messages to the caller, usually errors
-#[derive(Object)]
-pub struct ResponseMessage {
-    message: String,
-}
+        let location = match NewLocation::try_from(body.0) {
+            Ok(l) => l,
+            Err(errors) => return LocationResponse::InvalidLocation(
+                Problem::invalid_fields(errors).into_json()
+            ),
+        };
 
-impl ResponseMessage {
-    /// Creates error message from given string
-    fn new(message: &str) -> Self {
-        Self {
-            message: message.to_owned()
+        match self.database.create_location(user_id, &location, Self::MAX_LOCATIONS).await {
+            Ok(Some(location_id)) => {
+                tracing::info!(user_id, location_id, "location saved");
+                LocationResponse::Created(Json(LocationBody::new(location_id, location)))
+            }
+            Ok(None) => LocationResponse::Conflict(
+                Problem::new(
+                    ProblemCode::LocationLimitReached,
+                    &format!("At most {} locations can be saved.", Self::MAX_LOCATIONS),
+                )
+                .into_json()
+            ),
+            Err(SqlError::UniqueConstraintViolation) => LocationResponse::Conflict(
+                Problem::new(ProblemCode::LocationExists, "A location with given name already exists.").into_json()
+            ),
+            Err(SqlError::Other | SqlError::Unsupported) => location_query_failed(),
         }
     }
 
-    /// Converts message into a poem-openapi JSON serializable type
-    const fn into_json(self) -> ResponseBody {
-        Json(self)
-    }
-}
-
-/// Returns IP string for given `SocketAddr`.
-///
-/// Only exist so it can be overridden in tests with a version that returns a random IP string
-/// from a range that does not belong to local network.
-#[cfg(not(debug_assertions))]
-fn get_ip_string(address: &SocketAddr) -> String {
-    address.ip().to_string()
-}
-
-/// In tests, clients are always local, so IP address is always loopback
-/// The API we are using does not like that, so we make up an IP
-#[cfg(debug_assertions)]
-fn get_ip_string(address: &SocketAddr) -> String {
-    let mut ip = address.ip();
+    /// Returns the saved location of the caller with given ID.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `200 Success` with the saved location.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `404 Not Found` if the caller has no location with given ID.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/locations/:id", method = "get", operation_id = "get_location")]
+    #[tracing::instrument(skip_all)]
+    pub async fn get_location(&self, authorization: JwtAuthorization, id: Path<u64>) -> LocationResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return LocationResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
+
+        match self.database.get_location(user_id, id.0).await {
+            Ok(Some(location)) => LocationResponse::Success(Json(LocationBody::from(location))),
+            Ok(None) => location_not_found(),
+            Err(_) => location_query_failed(),
+        }
+    }
+
+    /// Replaces the saved location of the caller with given ID.
+    ///
+    /// Restrictions of `create_location` apply.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `200 Success` with the saved location.
+    ///
+    /// `400 Bad Request` with every invalid field if the location is not valid.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `404 Not Found` if the caller has no location with given ID.
+    ///
+    /// `409 Conflict` if the caller has another location with the same name.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/locations/:id", method = "put", operation_id = "update_location")]
+    #[tracing::instrument(skip_all)]
+    pub async fn update_location(
+        &self,
+        authorization: JwtAuthorization,
+        id: Path<u64>,
+        body: Json<NewLocationBody>,
+    ) -> LocationResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return LocationResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
+
+        let location = match NewLocation::try_from(body.0) {
+            Ok(l) => l,
+            Err(errors) => return LocationResponse::InvalidLocation(
+                Problem::invalid_fields(errors).into_json()
+            ),
+        };
+
+        match self.database.update_location(user_id, id.0, &location).await {
+            Ok(true) => {
+                tracing::info!(user_id, location_id = id.0, "location updated");
+                LocationResponse::Success(Json(LocationBody::new(id.0, location)))
+            }
+            Ok(false) => location_not_found(),
+            Err(SqlError::UniqueConstraintViolation) => LocationResponse::Conflict(
+                Problem::new(ProblemCode::LocationExists, "A location with given name already exists.").into_json()
+            ),
+            Err(SqlError::Other | SqlError::Unsupported) => location_query_failed(),
+        }
+    }
+
+    /// Deletes the saved location of the caller with given ID.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `204 No Content` if the location is deleted.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `404 Not Found` if the caller has no location with given ID.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/locations/:id", method = "delete", operation_id = "delete_location")]
+    #[tracing::instrument(skip_all)]
+    pub async fn delete_location(&self, authorization: JwtAuthorization, id: Path<u64>) -> LocationResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return LocationResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
+
+        match self.database.delete_location(user_id, id.0).await {
+            Ok(true) => {
+                tracing::info!(user_id, location_id = id.0, "location deleted");
+                LocationResponse::Deleted
+            }
+            Ok(false) => location_not_found(),
+            Err(_) => location_query_failed(),
+        }
+    }
+
+    /// Returns the webhook alerts of the caller are delivered to, with the secret their payloads are signed with.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `200 Success` with the webhook.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `404 Not Found` if the caller has not configured a webhook.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/alerts/webhook", method = "get", operation_id = "get_webhook")]
+    #[tracing::instrument(skip_all)]
+    pub async fn get_webhook(&self, authorization: JwtAuthorization) -> WebhookResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return WebhookResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
+
+        match self.database.get_webhook(user_id).await {
+            Ok(Some(webhook)) => WebhookResponse::Success(Json(WebhookBody::from(webhook))),
+            Ok(None) => WebhookResponse::NotFound(
+                Problem::new(ProblemCode::NotFound, "No webhook is configured.").into_json()
+            ),
+            Err(_) => webhook_query_failed(),
+        }
+    }
+
+    /// Sets the webhook alerts of the caller are delivered to.
+    ///
+    /// Alerts are posted as JSON to `url`, which needs to be an `http` or `https` URL pointing to a public address,
+    /// unless `alerts.allow_private_webhooks` is set. Redirects of the webhook are not followed.
+    /// A signing secret is generated for the first webhook of the caller and kept when the URL is replaced.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `200 Success` with the webhook and its secret.
+    ///
+    /// `400 Bad Request` if `url` is not valid.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/alerts/webhook", method = "put", operation_id = "set_webhook")]
+    #[tracing::instrument(skip_all)]
+    pub async fn set_webhook(&self, authorization: JwtAuthorization, body: Json<NewWebhookBody>) -> WebhookResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return WebhookResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
+
+        if let Err(message) = self.webhook_destinations.check(&body.url).await {
+            return WebhookResponse::InvalidRequest(
+                Problem::invalid_fields(vec![FieldError::new("url", &message)]).into_json()
+            );
+        }
+
+        let secret = match self.database.get_webhook(user_id).await {
+            Ok(Some(webhook)) => webhook.secret,
+            Ok(None) => alerts::generate_secret(),
+            Err(_) => return webhook_query_failed(),
+        };
+        let webhook = Webhook {
+            url: body.0.url,
+            secret,
+        };
+
+        match self.database.set_webhook(user_id, &webhook).await {
+            Ok(()) => {
+                tracing::info!(user_id, "webhook set");
+                WebhookResponse::Success(Json(WebhookBody::from(webhook)))
+            }
+            Err(_) => webhook_query_failed(),
+        }
+    }
+
+    /// Deletes the webhook of the caller, alert rules are not evaluated until a webhook is set again.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `204 No Content` if the webhook is deleted.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `404 Not Found` if the caller has not configured a webhook.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/alerts/webhook", method = "delete", operation_id = "delete_webhook")]
+    #[tracing::instrument(skip_all)]
+    pub async fn delete_webhook(&self, authorization: JwtAuthorization) -> WebhookResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return WebhookResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
+
+        match self.database.delete_webhook(user_id).await {
+            Ok(true) => {
+                tracing::info!(user_id, "webhook deleted");
+                WebhookResponse::Deleted
+            }
+            Ok(false) => WebhookResponse::NotFound(
+                Problem::new(ProblemCode::NotFound, "No webhook is configured.").into_json()
+            ),
+            Err(_) => webhook_query_failed(),
+        }
+    }
+
+    /// Returns the alert rules of the caller, in the order they are created.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `200 Success` with the alert rules.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/alerts/rules", method = "get", operation_id = "list_alert_rules")]
+    #[tracing::instrument(skip_all)]
+    pub async fn list_alert_rules(&self, authorization: JwtAuthorization) -> AlertRulesResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return AlertRulesResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
+
+        match self.database.list_alert_rules(user_id).await {
+            Ok(rules) => AlertRulesResponse::Success(Json(
+                rules.into_iter().filter_map(|r| AlertRuleBody::try_from(r).ok()).collect()
+            )),
+            Err(_) => {
+                tracing::error!("reading alert rules failed");
+                AlertRulesResponse::Failed(
+                    Problem::new(ProblemCode::InternalError, "Could not read alert rules.").into_json()
+                )
+            }
+        }
+    }
+
+    /// Creates an alert rule evaluated against the weather at a saved location of the caller.
+    ///
+    /// Rules are evaluated periodically while the caller has a webhook. A rule fires once when its condition
+    /// starts holding, an alert is posted to the webhook, and fires again only after the condition stops holding.
+    ///
+    /// `threshold` is in °C for temperature, km/h for wind speed and percent chance of rain for
+    /// `precipitation_expected`, which defaults to 50. `within_hours`, between 1 and 24, is required
+    /// for `precipitation_expected` and not allowed for other conditions.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `201 Created` with the alert rule.
+    ///
+    /// `400 Bad Request` with every invalid field if the rule is not valid.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `409 Conflict` if the caller has created as many alert rules as allowed.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/alerts/rules", method = "post", operation_id = "create_alert_rule")]
+    #[tracing::instrument(skip_all)]
+    pub async fn create_alert_rule(
+        &self,
+        authorization: JwtAuthorization,
+        body: Json<NewAlertRuleBody>,
+    ) -> AlertRuleResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return AlertRuleResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
+
+        let location_id = body.location_id;
+        let (rule, mut errors) = match NewAlertRule::try_from(body.0) {
+            Ok(rule) => (Some(rule), Vec::new()),
+            Err(errors) => (None, errors),
+        };
+        match self.database.get_location(user_id, location_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => errors.push(FieldError::new("location_id", "Location does not exist")),
+            Err(_) => return alert_rule_query_failed(),
+        }
+        let Some(rule) = rule.filter(|_| errors.is_empty()) else {
+            return AlertRuleResponse::InvalidRule(Problem::invalid_fields(errors).into_json());
+        };
+
+        match self.database.create_alert_rule(user_id, &rule, Self::MAX_ALERT_RULES).await {
+            Ok(Some(rule_id)) => {
+                tracing::info!(user_id, rule_id, "alert rule created");
+                let created = AlertRule {
+                    id: rule_id,
+                    location_id: rule.location_id,
+                    condition: rule.condition,
+                    threshold: rule.threshold,
+                    within_hours: rule.within_hours,
+                    firing: false,
+                    last_fired_at: None,
+                };
+                match AlertRuleBody::try_from(created) {
+                    Ok(body) => AlertRuleResponse::Created(Json(body)),
+                    Err(()) => alert_rule_query_failed(),
+                }
+            }
+            Ok(None) => AlertRuleResponse::Conflict(
+                Problem::new(
+                    ProblemCode::AlertRuleLimitReached,
+                    &format!("At most {} alert rules can be created.", Self::MAX_ALERT_RULES),
+                )
+                .into_json()
+            ),
+            Err(_) => alert_rule_query_failed(),
+        }
+    }
+
+    /// Deletes the alert rule of the caller with given ID.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `204 No Content` if the alert rule is deleted.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `404 Not Found` if the caller has no alert rule with given ID.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/alerts/rules/:id", method = "delete", operation_id = "delete_alert_rule")]
+    #[tracing::instrument(skip_all)]
+    pub async fn delete_alert_rule(&self, authorization: JwtAuthorization, id: Path<u64>) -> AlertRuleResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return AlertRuleResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
+
+        match self.database.delete_alert_rule(user_id, id.0).await {
+            Ok(true) => {
+                tracing::info!(user_id, rule_id = id.0, "alert rule deleted");
+                AlertRuleResponse::Deleted
+            }
+            Ok(false) => AlertRuleResponse::NotFound(
+                Problem::new(ProblemCode::NotFound, "Alert rule does not exist.").into_json()
+            ),
+            Err(_) => alert_rule_query_failed(),
+        }
+    }
+
+    /// Returns the latest attempts to deliver alerts to the webhook of the caller, newest first.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `200 Success` with at most `limit` attempts, defaults to 50 and is capped at 200.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/alerts/deliveries", method = "get", operation_id = "list_alert_deliveries")]
+    #[tracing::instrument(skip_all)]
+    pub async fn list_alert_deliveries(
+        &self,
+        authorization: JwtAuthorization,
+        /// Most attempts to return.
+        limit: Query<Option<u32>>,
+    ) -> AlertDeliveriesResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return AlertDeliveriesResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
+
+        let limit = limit.0.unwrap_or(50).min(200);
+        match self.database.list_alert_deliveries(user_id, limit).await {
+            Ok(deliveries) => AlertDeliveriesResponse::Success(Json(
+                deliveries.into_iter().map(AlertDeliveryBody::from).collect()
+            )),
+            Err(_) => {
+                tracing::error!("reading alert deliveries failed");
+                AlertDeliveriesResponse::Failed(
+                    Problem::new(ProblemCode::InternalError, "Could not read alert deliveries.").into_json()
+                )
+            }
+        }
+    }
+
+    /// Returns the default unit system and language of the caller.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `200 Success` with the preferences, missing ones are not set.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `404 Not Found` if the user of the token does not exist.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/preferences", method = "get", operation_id = "get_preferences")]
+    #[tracing::instrument(skip_all)]
+    pub async fn get_preferences(&self, authorization: JwtAuthorization) -> PreferencesResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return PreferencesResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
+
+        match self.database.get_preferences(user_id).await {
+            Ok(Some(preferences)) => PreferencesResponse::Success(Json(PreferencesBody::from(preferences))),
+            Ok(None) => PreferencesResponse::UserNotFound(
+                Problem::new(ProblemCode::NotFound, "User does not exist.").into_json()
+            ),
+            Err(_) => {
+                tracing::error!("reading preferences failed");
+                PreferencesResponse::Failed(
+                    Problem::new(ProblemCode::InternalError, "Could not read preferences.").into_json()
+                )
+            }
+        }
+    }
+
+    /// Replaces the default unit system and language of the caller,
+    /// used by `weather` when the request does not specify them.
+    ///
+    /// Requires a valid JWT token.
+    ///
+    /// # Returns
+    /// `200 Success` with the stored preferences.
+    ///
+    /// `400 Bad Request` if `units` or `lang` is not valid.
+    ///
+    /// `401 Unauthorized` if no JWT token is attached or attached token is invalid.
+    ///
+    /// `404 Not Found` if the user of the token does not exist.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/preferences", method = "put", operation_id = "set_preferences")]
+    #[tracing::instrument(skip_all)]
+    pub async fn set_preferences(
+        &self,
+        authorization: JwtAuthorization,
+        body: Json<PreferencesBody>,
+    ) -> PreferencesResponse {
+        let Some(user_id) = self.authenticate(&authorization).await else {
+            return PreferencesResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        };
+
+        let lang = match body.lang.as_deref().map(units::normalize_lang).transpose() {
+            Ok(l) => l,
+            Err(message) => return PreferencesResponse::InvalidRequest(
+                Problem::invalid_fields(vec![FieldError::new("lang", &message)]).into_json()
+            ),
+        };
+        let preferences = Preferences {
+            units: body.units.map(|u| u.as_str().to_owned()),
+            lang,
+        };
+
+        match self.database.set_preferences(user_id, &preferences).await {
+            Ok(true) => {
+                tracing::info!(user_id, "preferences updated");
+                PreferencesResponse::Success(Json(PreferencesBody::from(preferences)))
+            }
+            Ok(false) => PreferencesResponse::UserNotFound(
+                Problem::new(ProblemCode::NotFound, "User does not exist.").into_json()
+            ),
+            Err(_) => {
+                tracing::error!("persisting preferences failed");
+                PreferencesResponse::Failed(
+                    Problem::new(ProblemCode::InternalError, "Could not save preferences.").into_json()
+                )
+            }
+        }
+    }
+
+    /// Takes a backup of the database while the server keeps serving requests.
+    ///
+    /// The oldest backups beyond the configured retention are removed afterwards.
+    ///
+    /// Requires the administrator token set by the `ADMIN_TOKEN` environment variable.
+    ///
+    /// # Returns
+    /// `201 Created` with the backup.
+    ///
+    /// `401 Unauthorized` if no token is attached or attached token is not the administrator token.
+    ///
+    /// `501 Not Implemented` if the database is not a SQLite database.
+    ///
+    /// `500 Internal Server Error` if writing the backup fails.
+    #[oai(path = "/admin/backups", method = "post", operation_id = "create_backup")]
+    #[tracing::instrument(skip_all)]
+    pub async fn create_backup(&self, authorization: AdminAuthorization) -> BackupResponse {
+        if !check_admin_token(&authorization.0.token) {
+            return BackupResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        }
+
+        match self.backups.create().await {
+            Ok(backup) => BackupResponse::Created(Json(BackupBody::from(backup))),
+            Err(backup::Error::Unsupported) => BackupResponse::NotSupported(
+                Problem::new(ProblemCode::NotSupported, "Backups are only supported for SQLite databases.").into_json()
+            ),
+            Err(e) => {
+                tracing::error!(error = %e, "backup failed");
+                BackupResponse::Failed(Problem::new(ProblemCode::InternalError, "Could not back up the database.").into_json())
+            }
+        }
+    }
+
+    /// Returns the backups in the backup directory, newest first.
+    ///
+    /// Requires the administrator token set by the `ADMIN_TOKEN` environment variable.
+    ///
+    /// # Returns
+    /// `200 Success` with the backups.
+    ///
+    /// `401 Unauthorized` if no token is attached or attached token is not the administrator token.
+    ///
+    /// `500 Internal Server Error` if the backup directory can not be read.
+    #[oai(path = "/admin/backups", method = "get", operation_id = "list_backups")]
+    #[tracing::instrument(skip_all)]
+    pub async fn list_backups(&self, authorization: AdminAuthorization) -> BackupsResponse {
+        if !check_admin_token(&authorization.0.token) {
+            return BackupsResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        }
+
+        match self.backups.list().await {
+            Ok(backups) => BackupsResponse::Success(Json(backups.into_iter().map(BackupBody::from).collect())),
+            Err(e) => {
+                tracing::error!(error = %e, "listing backups failed");
+                BackupsResponse::Failed(Problem::new(ProblemCode::InternalError, "Could not list backups.").into_json())
+            }
+        }
+    }
+
+    /// Returns the events of the audit log matching the filters, newest first.
+    ///
+    /// Pages are continued by passing the `id` of the last event of a page as `before`.
+    ///
+    /// Requires the administrator token set by the `ADMIN_TOKEN` environment variable.
+    ///
+    /// # Returns
+    /// `200 Success` with the events.
+    ///
+    /// `400 Bad Request` if `ip` is not an IP address, `from` is after `to` or `limit` is not between 1 and 1000.
+    ///
+    /// `401 Unauthorized` if no token is attached or attached token is not the administrator token.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/admin/audit", method = "get", operation_id = "list_audit_events")]
+    #[tracing::instrument(skip_all)]
+    // Every query parameter is an argument of the handler
+    #[allow(clippy::too_many_arguments)]
+    pub async fn list_audit_events(
+        &self,
+        authorization: AdminAuthorization,
+        /// ID of the user the events are about.
+        user_id: Query<Option<u64>>,
+        /// Kind of the events.
+        event: Query<Option<AuditEventKind>>,
+        /// Outcome of the events.
+        outcome: Query<Option<AuditOutcome>>,
+        /// Address of the client the events are requested by.
+        ip: Query<Option<String>>,
+        /// Time the events occurred at or after.
+        from: Query<Option<DateTime<Utc>>>,
+        /// Time the events occurred before.
+        to: Query<Option<DateTime<Utc>>>,
+        /// ID of the event to return the events older than.
+        before: Query<Option<u64>>,
+        /// Most events to return, defaults to 100.
+        limit: Query<Option<u32>>,
+    ) -> AuditEventsResponse {
+        if !check_admin_token(&authorization.0.token) {
+            return AuditEventsResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        }
+
+        let limit = limit.0.unwrap_or(100);
+        let filter = audit_filter(user_id.0, event.0, outcome.0, ip.0.as_deref(), from.0, to.0);
+        let mut filter = match (filter, (1..=Self::MAX_AUDIT_EVENTS).contains(&limit)) {
+            (Ok(filter), true) => filter,
+            (filter, limit_valid) => {
+                let mut errors = filter.err().unwrap_or_default();
+                if !limit_valid {
+                    errors.push(FieldError::new("limit", "Limit needs to be between 1 and 1000"));
+                }
+                return AuditEventsResponse::InvalidRequest(Problem::invalid_fields(errors).into_json());
+            }
+        };
+        filter.before_id = before.0;
+
+        match self.database.list_audit_events(&filter, limit).await {
+            Ok(events) => AuditEventsResponse::Success(Json(events.into_iter().map(AuditEventBody::from).collect())),
+            Err(_) => {
+                tracing::error!("listing audit events failed");
+                AuditEventsResponse::Failed(
+                    Problem::new(ProblemCode::InternalError, "Could not list audit events.").into_json()
+                )
+            }
+        }
+    }
+
+    /// Exports every event of the audit log matching the filters as JSON Lines, newest first.
+    ///
+    /// Lines are `AuditEventBody` objects. Events are read from the database in pages as the response is sent,
+    /// if reading a page fails the response ends early.
+    ///
+    /// Requires the administrator token set by the `ADMIN_TOKEN` environment variable.
+    ///
+    /// # Returns
+    /// `200 Success` with the events in `application/x-ndjson` form.
+    ///
+    /// `400 Bad Request` if `ip` is not an IP address or `from` is after `to`.
+    ///
+    /// `401 Unauthorized` if no token is attached or attached token is not the administrator token.
+    ///
+    /// `500 Internal Server Error` if the database operation fails.
+    #[oai(path = "/admin/audit/export", method = "get", operation_id = "export_audit_events")]
+    #[tracing::instrument(skip_all)]
+    // Every query parameter is an argument of the handler
+    #[allow(clippy::too_many_arguments)]
+    pub async fn export_audit_events(
+        &self,
+        authorization: AdminAuthorization,
+        /// ID of the user the events are about.
+        user_id: Query<Option<u64>>,
+        /// Kind of the events.
+        event: Query<Option<AuditEventKind>>,
+        /// Outcome of the events.
+        outcome: Query<Option<AuditOutcome>>,
+        /// Address of the client the events are requested by.
+        ip: Query<Option<String>>,
+        /// Time the events occurred at or after.
+        from: Query<Option<DateTime<Utc>>>,
+        /// Time the events occurred before.
+        to: Query<Option<DateTime<Utc>>>,
+    ) -> AuditExportResponse {
+        if !check_admin_token(&authorization.0.token) {
+            return AuditExportResponse::Unauthorized(
+                Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json()
+            );
+        }
+
+        let filter = match audit_filter(user_id.0, event.0, outcome.0, ip.0.as_deref(), from.0, to.0) {
+            Ok(filter) => filter,
+            Err(errors) => return AuditExportResponse::InvalidRequest(Problem::invalid_fields(errors).into_json()),
+        };
+
+        // The first page is read before responding, so failing to read the log is not a truncated export
+        let Ok(first_page) = self.database.list_audit_events(&filter, Self::AUDIT_EXPORT_PAGE_SIZE).await else {
+            tracing::error!("exporting audit events failed");
+            return AuditExportResponse::Failed(
+                Problem::new(ProblemCode::InternalError, "Could not export audit events.").into_json()
+            );
+        };
+
+        let database = self.database.clone();
+        let pages = futures::stream::try_unfold(Some((filter, first_page)), move |state| {
+            let database = database.clone();
+            async move {
+                let Some((filter, page)) = state else {
+                    return Ok(None);
+                };
+                if page.is_empty() {
+                    return Ok(None);
+                }
+
+                let mut lines = Vec::new();
+                for event in &page {
+                    serde_json::to_writer(&mut lines, &AuditEventBody::from(event.clone()))?;
+                    lines.push(b'\n');
+                }
+
+                if page.len() < Self::AUDIT_EXPORT_PAGE_SIZE as usize {
+                    return Ok(Some((lines, None)));
+                }
+                let filter = AuditFilter { before_id: page.last().map(|e| e.id), ..filter };
+                let Ok(next_page) = database.list_audit_events(&filter, Self::AUDIT_EXPORT_PAGE_SIZE).await else {
+                    tracing::error!("exporting audit events failed, ending the export early");
+                    return Err(std::io::Error::other("reading audit events failed"));
+                };
+
+                Ok(Some((lines, Some((filter, next_page)))))
+            }
+        });
+
+        AuditExportResponse::Success(Binary(Body::from_bytes_stream(pages)))
+    }
+}
+
+
+/// Information used in `register` request body.
+/// 
+/// For credentials restrictions, see `Api::register`
+#[derive(serde::Serialize, Object)]
+pub struct RegisterBody {
+    /// User's username.
+    pub username: String,
+    /// User's email.
+    pub email: String,
+    /// User's password.
+    pub password: String,
+}
+
+/// Used to validate credentials are valid.
+pub(crate) struct RegisterCredentials {
+    /// User's username.
+    pub(crate) username: String,
+    /// User's email.
+    pub(crate) email: String,
+    /// User's password.
+    pub(crate) password: String,
+}
+
+impl TryFrom<RegisterBody> for RegisterCredentials {
+    /// Every invalid field, with the first restriction it violates.
+    type Error = Vec<FieldError>;
+
+    fn try_from(RegisterBody { username, email, password }: RegisterBody) -> Result<Self, Self::Error> {
+        let mut errors = Vec::new();
+
+        if let Err(message) = validate_username(&username) {
+            errors.push(FieldError::new("username", &message));
+        }
+
+        let email = email_address::EmailAddress::from_str(&email);
+        if email.is_err() {
+            errors.push(FieldError::new("email", "Email needs to be a valid email address"));
+        }
+
+        if let Err(message) = validate_password(&password) {
+            errors.push(FieldError::new("password", &message));
+        }
+
+        match email {
+            Ok(email) if errors.is_empty() => Ok(RegisterCredentials { username, email: email.email(), password }),
+            _ => Err(errors),
+        }
+    }
+}
+
+/// Message of the error refusing a username that mixes letters of commonly confused scripts.
+///
+/// Shared with the command-line interface, which refuses the same usernames.
+pub(crate) const CONFUSABLE_USERNAME_MESSAGE: &str = "Username can not mix letters of different scripts";
+
+/// Checks the username satisfies the restrictions described in `Api::register`.
+fn validate_username(username: &str) -> Result<(), String> {
+    if !(6usize..=24usize).contains(&username.len()) {
+        let error_message = "Username needs to be at least 6 and at most 24 characters".to_owned();
+        return Err(error_message);
+    }
+
+    if username.chars().any(|c| !c.is_alphanumeric() && !['.', '_'].contains(&c)) {
+        let error_message = "Username can only contain letters, numbers, dots and underscores".to_owned();
+        return Err(error_message);
+    }
+
+    Ok(())
+}
+
+/// Checks the password satisfies the restrictions described in `Api::register`.
+///
+/// Shared with the command-line interface so passwords set by operators follow the same rules.
+pub(crate) fn validate_password(password: &str) -> Result<(), String> {
+    if !(8usize..=32usize).contains(&password.len()) {
+        let error_message = "Password needs to be at least 8 and at most 32 characters".to_owned();
+        return Err(error_message);
+    }
+
+    let allowed_chars = "~!@$%^&*()_-+={[}]|:',.?/";
+    if password.chars().any(|c| !c.is_alphanumeric() && !allowed_chars.chars().any(|symbol| symbol.eq(&c))) {
+        let error_message = format!("Password can only contain letters, numbers and symbols {allowed_chars}");
+        return Err(error_message);
+    }
+
+    Ok(())
+}
+
+/// Information used in `login` request body.
+#[derive(serde::Serialize, Object)]
+pub struct LoginBody {
+    /// Can either be user's `username` or `email`.
+    pub identifier: String,
+    /// User's password
+    pub password: String,
+}
+
+/// Describes authorization used in `weather` request.
+#[derive(SecurityScheme)]
+#[oai(ty = "bearer")]
+pub struct JwtAuthorization(Bearer);
+
+/// Describes authorization used in administration requests, the token set by `ADMIN_TOKEN`.
+#[derive(SecurityScheme)]
+#[oai(ty = "bearer")]
+pub struct AdminAuthorization(Bearer);
+
+/// Response of `health_check` call.
+///
+/// Never fails, so it has no problem responses.
+#[derive(ApiResponse)]
+pub enum HealthResponse {
+    /// Returned on all calls
+    #[oai(status = 200)]
+    Alive,
+}
+
+/// Response of `ready` call.
+///
+/// `503 Service Unavailable` carries the dependency report instead of a problem,
+/// so probes and operators can see which dependency is down.
+#[derive(ApiResponse)]
+pub enum ReadyResponse {
+    /// Returned when every critical dependency is up.
+    #[oai(status = 200)]
+    Ready(Json<ReadinessReport>),
+    /// Returned when a critical dependency is down.
+    #[oai(status = 503)]
+    NotReady(Json<ReadinessReport>),
+}
+
+/// Response of `register` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "RegisterResponse::invalid_request")]
+pub enum RegisterResponse {
+    /// Returned when registration succeeds.
+    #[oai(status = 201)]
+    Registered(Json<RegisterResponseBody>),
+    /// Returned when registration credentials are not valid, with `invalid_credentials` code
+    /// and every invalid field, or when the body is malformed, with `invalid_request` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidCredentials(ProblemBody),
+    /// Returned when user with same credentials exists, with `already_registered` code.
+    #[oai(status = 409, content_type = "application/problem+json")]
+    AlreadyRegistered(ProblemBody),
+    /// Returned when persisting the user fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    RegistrationFailed(ProblemBody),
+}
+
+impl RegisterResponse {
+    /// Converts errors of parsing the request into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        Self::InvalidCredentials(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+}
+
+/// Body of `register` call success response.
+#[derive(serde::Deserialize, Object)]
+pub struct RegisterResponseBody {
+    /// ID of registered user.
+    pub user_id: u64,
+}
+
+/// Response of `login` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "LoginResponse::invalid_request")]
+pub enum LoginResponse {
+    /// Returned when user successfully logs in.
+    #[oai(status = 200)]
+    LoggedIn(Json<LoginResponseBody>),
+    /// Returned when the body is malformed, with `invalid_request` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when such user does not exist or password does not match, with `wrong_credentials` code.
+    #[oai(status = 404, content_type = "application/problem+json")]
+    WrongCredentials(ProblemBody),
+    /// Returned when JWT token creation fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    CouldNotCreateToken(ProblemBody),
+}
+
+impl LoginResponse {
+    /// Converts errors of parsing the request into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+}
+
+/// Body of `login` call success response.
+#[derive(serde::Deserialize, Object)]
+pub struct LoginResponseBody {
+    /// Created JWT token.
+    pub token: String,
+}
+
+/// Response of `weather` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "WeatherResponse::invalid_request")]
+pub enum WeatherResponse {
+    /// Returned when weather information is successfully obtained.
+    #[oai(status = 200)]
+    Success(Json<Box<WeatherResponseBody>>),
+    /// Returned when the request is malformed, with `invalid_request` code,
+    /// or `lang` is not valid, with `invalid_fields` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is invalid, with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the user has no saved location with requested ID, with `not_found` code.
+    #[oai(status = 404, content_type = "application/problem+json")]
+    LocationNotFound(ProblemBody),
+    /// Returned when the caller's address is private and no development location is configured,
+    /// with `private_address` code.
+    #[oai(status = 422, content_type = "application/problem+json")]
+    PrivateAddress(ProblemBody),
+    /// Returned when the caller's address can not be determined or reading saved locations fails,
+    /// with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    InternalError(ProblemBody),
+    /// Returned when a foreign API fails or responds with an error, with `upstream_error` code.
+    #[oai(status = 502, content_type = "application/problem+json")]
+    BadGateway(ProblemBody),
+    /// Returned when a foreign API is rate limiting, out of quota or its circuit is open,
+    /// with `upstream_unavailable` code.
+    #[oai(status = 503, content_type = "application/problem+json")]
+    UpstreamUnavailable(ProblemBody),
+    /// Returned when a foreign API does not respond in time, with `upstream_timeout` code.
+    #[oai(status = 504, content_type = "application/problem+json")]
+    GatewayTimeout(ProblemBody),
+}
+
+impl WeatherResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+
+    /// Returns the response matching a problem created by `resolve_location` or `current_weather`.
+    fn from_problem(problem: Problem) -> Self {
+        match problem.code {
+            ProblemCode::InvalidFields => Self::InvalidRequest(problem.into_json()),
+            ProblemCode::NotFound => Self::LocationNotFound(problem.into_json()),
+            ProblemCode::PrivateAddress => Self::PrivateAddress(problem.into_json()),
+            ProblemCode::InternalError => Self::InternalError(problem.into_json()),
+            ProblemCode::UpstreamTimeout => Self::GatewayTimeout(problem.into_json()),
+            ProblemCode::UpstreamUnavailable => Self::UpstreamUnavailable(problem.into_json()),
+            _ => Self::BadGateway(problem.into_json()),
+        }
+    }
+}
+
+/// Logs the failure of the call to a foreign API and returns the problem describing it.
+///
+/// `api` is either `geolocation` or `weather`.
+/// Details of the error are only logged, as they may reveal the configuration of the server.
+fn upstream_failure_problem(api: &str, error: &http_client::Error) -> Problem {
+    let (status, code) = match error {
+        http_client::Error::UnexpectedStatus { status, code, .. } => (Some(status.as_u16()), code.as_deref()),
+        http_client::Error::ProviderError { code, .. } => (None, code.as_deref()),
+        _ => (None, None),
+    };
+    tracing::warn!(api, kind = error.kind(), upstream_status = status, upstream_code = code, error = %error, "{api} query failed");
+
+    if error.is_timeout() {
+        Problem::new(ProblemCode::UpstreamTimeout, &format!("The {api} API did not respond in time."))
+    } else if error.is_unavailable() {
+        Problem::new(ProblemCode::UpstreamUnavailable, &format!("The {api} API is temporarily unavailable."))
+    } else {
+        Problem::new(ProblemCode::UpstreamError, &format!("Could not fetch {api} information."))
+    }
+}
+
+/// Checks the status of the user with given ID every interval and returns once they are no longer active.
+///
+/// Also returns when the status can not be read, as `authenticate` refuses tokens then.
+async fn wait_until_inactive(database: &Database, user_id: u64, interval: Duration) {
+    let mut interval = tokio::time::interval_at(Instant::now() + interval, interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        match database.is_user_active(user_id).await {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::info!(user_id, "weather stream of an inactive user ended");
+                return;
+            }
+            Err(_) => {
+                tracing::error!(user_id, "reading the status of the user failed");
+                return;
+            }
+        }
+    }
+}
+
+/// Returns the `404 Not Found` response of location calls.
+fn location_not_found() -> LocationResponse {
+    LocationResponse::NotFound(Problem::new(ProblemCode::NotFound, "Location does not exist.").into_json())
+}
+
+/// Logs the failure of a location query and returns the `500 Internal Server Error` response of location calls.
+fn location_query_failed() -> LocationResponse {
+    tracing::error!("location query failed");
+    LocationResponse::Failed(Problem::new(ProblemCode::InternalError, "Location operation failed.").into_json())
+}
+
+/// Logs the failure of a webhook query and returns the `500 Internal Server Error` response of webhook calls.
+fn webhook_query_failed() -> WebhookResponse {
+    tracing::error!("webhook query failed");
+    WebhookResponse::Failed(Problem::new(ProblemCode::InternalError, "Webhook operation failed.").into_json())
+}
+
+/// Logs the failure of an alert rule query and returns the `500 Internal Server Error` response
+/// of alert rule calls.
+fn alert_rule_query_failed() -> AlertRuleResponse {
+    tracing::error!("alert rule query failed");
+    AlertRuleResponse::Failed(Problem::new(ProblemCode::InternalError, "Alert rule operation failed.").into_json())
+}
+
+/// Location weather calls are for, with where it comes from.
+enum ResolvedLocation {
+    /// Location of the caller, located by a geolocation provider.
+    Located(Sourced<GeolocationApiResponse>),
+    /// Location saved by the caller.
+    Saved(SavedLocation),
+    /// Development location of a caller with a private address.
+    Configured { latitude: f64, longitude: f64 },
+}
+
+impl ResolvedLocation {
+    /// Returns the coordinates of the location.
+    const fn coordinates(&self) -> Coordinates<'_> {
+        match *self {
+            Self::Located(ref geolocation) => Coordinates::Located(geolocation),
+            Self::Saved(ref location) => Coordinates::Saved(location),
+            Self::Configured { latitude, longitude } => Coordinates::Configured { latitude, longitude },
+        }
+    }
+}
+
+/// Coordinates the weather is fetched for, with where they come from.
+#[derive(Clone, Copy)]
+enum Coordinates<'a> {
+    /// Coordinates of the caller, located by a geolocation provider.
+    Located(&'a Sourced<GeolocationApiResponse>),
+    /// Coordinates of a location saved by the caller.
+    Saved(&'a SavedLocation),
+    /// Development coordinates of a caller with a private address.
+    Configured { latitude: f64, longitude: f64 },
+}
+
+impl Coordinates<'_> {
+    /// Returns the latitude and the longitude.
+    const fn latitude_and_longitude(self) -> (f64, f64) {
+        match self {
+            Self::Located(geolocation) => (geolocation.value.latitude, geolocation.value.longitude),
+            Self::Saved(location) => (location.latitude, location.longitude),
+            Self::Configured { latitude, longitude } => (latitude, longitude),
+        }
+    }
+}
+
+/// Body of `weather` call success response.
+///
+/// Optional fields are missing if the weather provider does not report them.
+/// Units of values depend on `units`:
+/// - `metric`: °C, km/h, hPa, mm and km
+/// - `imperial`: °F, mph, inHg, inches and miles
+/// - `si`: K, m/s, Pa, mm and m
+#[derive(serde::Deserialize, Object)]
+pub struct WeatherResponseBody {
+    /// Unit system of the values.
+    pub units: Units,
+    /// Language the condition text is requested in, if any.
+    #[serde(default)]
+    pub lang: Option<String>,
+    /// Temperature.
+    pub temperature: f64,
+    /// Felt temperature.
+    pub feels_like: f64,
+    /// Description of the weather condition.
+    pub condition: String,
+    /// Code of the weather condition in the scheme of the weather provider.
+    #[serde(default)]
+    pub condition_code: Option<u16>,
+    /// URL of the weather condition icon.
+    #[serde(default)]
+    pub condition_icon: Option<String>,
+    /// Whether the sun is up.
+    #[serde(default)]
+    pub is_day: Option<bool>,
+    /// Wind at 10 meters above ground.
+    pub wind: Wind,
+    /// Relative humidity in percent.
+    #[serde(default)]
+    pub humidity: Option<u8>,
+    /// Air pressure at sea level.
+    #[serde(default)]
+    pub pressure: Option<f64>,
+    /// Precipitation.
+    #[serde(default)]
+    pub precipitation: Option<f64>,
+    /// Cloud cover in percent.
+    #[serde(default)]
+    pub cloud_cover: Option<u8>,
+    /// Visibility.
+    #[serde(default)]
+    pub visibility: Option<f64>,
+    /// UV index.
+    #[serde(default)]
+    pub uv_index: Option<f64>,
+    /// Air quality, if reported by the weather provider.
+    #[serde(default)]
+    pub air_quality: Option<AirQualityReport>,
+    /// Time the weather provider last updated the information, with the UTC offset of the location.
+    ///
+    /// Missing if the weather provider reports neither the time nor the timezone of the location.
+    #[serde(default)]
+    pub last_updated: Option<DateTime<FixedOffset>>,
+    /// Location the weather information is for.
+    pub location: LocationReport,
+    /// Providers that served the response.
+    pub provenance: Provenance,
+}
+
+impl WeatherResponseBody {
+    /// Creates the response body from the response of the weather provider at given coordinates,
+    /// reporting the providers that served them.
+    fn from_sourced(
+        response: Sourced<WeatherApiResponse>,
+        coordinates: Coordinates<'_>,
+        units: Units,
+        lang: Option<String>,
+    ) -> Self {
+        let provenance = Provenance {
+            geolocation: match coordinates {
+                Coordinates::Located(geolocation) => Some(geolocation.provider.to_owned()),
+                Coordinates::Saved(_) | Coordinates::Configured { .. } => None,
+            },
+            weather: response.provider.to_owned(),
+        };
+        let location = LocationReport::new(&response, coordinates);
+
+        Self::new(response.value, location, units, lang, provenance)
+    }
+
+    /// Creates the response body from the response of the weather provider,
+    /// converting values to given units.
+    fn new(
+        response: WeatherApiResponse,
+        location: LocationReport,
+        units: Units,
+        lang: Option<String>,
+        provenance: Provenance,
+    ) -> Self {
+        let last_updated = response.last_updated();
+        let current = response.current;
+        Self {
+            units,
+            lang,
+            temperature: units.temperature(current.temp_c),
+            feels_like: units.temperature(current.feelslike_c),
+            condition: current.condition.text,
+            condition_code: current.condition.code,
+            condition_icon: current.condition.icon.map(|icon| {
+                // weatherapi.com returns protocol relative URLs
+                if icon.starts_with("//") { format!("https:{icon}") } else { icon }
+            }),
+            is_day: current.is_day.map(|d| d != 0),
+            wind: Wind {
+                speed: current.wind_kph.map(|s| units.speed(s)),
+                gust: current.gust_kph.map(|s| units.speed(s)),
+                degree: current.wind_degree,
+                direction: current.wind_dir,
+            },
+            humidity: current.humidity,
+            pressure: current.pressure_mb.map(|p| units.pressure(p)),
+            precipitation: current.precip_mm.map(|p| units.precipitation(p)),
+            cloud_cover: current.cloud,
+            visibility: current.vis_km.map(|v| units.distance(v)),
+            uv_index: current.uv,
+            air_quality: current.air_quality.map(|a| AirQualityReport {
+                co: a.co,
+                no2: a.no2,
+                o3: a.o3,
+                so2: a.so2,
+                pm2_5: a.pm2_5,
+                pm10: a.pm10,
+                us_epa_index: a.us_epa_index,
+                gb_defra_index: a.gb_defra_index,
+            }),
+            last_updated,
+            location,
+            provenance,
+        }
+    }
+}
+
+/// Location information in `weather` call response.
+///
+/// Details reported by the weather provider take precedence over the ones reported by the geolocation provider,
+/// coordinates of saved locations take precedence over both.
+#[derive(serde::Deserialize, Object)]
+pub struct LocationReport {
+    /// Name of the place, usually a city.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Region, state or province.
+    #[serde(default)]
+    pub region: Option<String>,
+    /// Country.
+    #[serde(default)]
+    pub country: Option<String>,
+    /// Latitude in degrees.
+    pub latitude: f64,
+    /// Longitude in degrees.
+    pub longitude: f64,
+    /// IANA timezone name, e.g. `Europe/Istanbul`.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Current time at the location, with its UTC offset.
+    #[serde(default)]
+    pub local_time: Option<DateTime<FixedOffset>>,
+    /// ID of the saved location, if the weather is for one.
+    #[serde(default)]
+    pub saved_location_id: Option<u64>,
+}
+
+impl LocationReport {
+    /// Merges the location details reported by the weather provider with the coordinates they are for.
+    fn new(weather: &WeatherApiResponse, coordinates: Coordinates<'_>) -> Self {
+        let location = &weather.location;
+        let local_time = weather
+            .utc_offset()
+            .map(|offset| Utc::now().with_timezone(&offset));
+
+        match coordinates {
+            Coordinates::Located(geolocation) => Self {
+                name: location.name.clone().or_else(|| geolocation.city.clone()),
+                region: location.region.clone().or_else(|| geolocation.region.clone()),
+                country: location.country.clone().or_else(|| geolocation.country.clone()),
+                latitude: location.lat.unwrap_or(geolocation.latitude),
+                longitude: location.lon.unwrap_or(geolocation.longitude),
+                timezone: location.tz_id.clone().or_else(|| geolocation.timezone.clone()),
+                local_time,
+                saved_location_id: None,
+            },
+            Coordinates::Saved(saved) => Self {
+                name: location.name.clone(),
+                region: location.region.clone(),
+                country: location.country.clone(),
+                latitude: saved.latitude,
+                longitude: saved.longitude,
+                timezone: location.tz_id.clone(),
+                local_time,
+                saved_location_id: Some(saved.id),
+            },
+            Coordinates::Configured { latitude, longitude } => Self {
+                name: location.name.clone(),
+                region: location.region.clone(),
+                country: location.country.clone(),
+                latitude,
+                longitude,
+                timezone: location.tz_id.clone(),
+                local_time,
+                saved_location_id: None,
+            },
+        }
+    }
+}
+
+/// Wind information in `weather` call response.
+#[derive(serde::Deserialize, Object)]
+pub struct Wind {
+    /// Speed.
+    #[serde(default)]
+    pub speed: Option<f64>,
+    /// Speed of gusts.
+    #[serde(default)]
+    pub gust: Option<f64>,
+    /// Direction the wind blows from, in degrees clockwise from north.
+    #[serde(default)]
+    pub degree: Option<u16>,
+    /// Direction the wind blows from, as a 16-point compass direction, e.g. `NNW`.
+    #[serde(default)]
+    pub direction: Option<String>,
+}
+
+/// Air quality information in `weather` call response.
+#[derive(serde::Deserialize, Object)]
+pub struct AirQualityReport {
+    /// Carbon monoxide in μg/m³.
+    #[serde(default)]
+    pub co: Option<f64>,
+    /// Nitrogen dioxide in μg/m³.
+    #[serde(default)]
+    pub no2: Option<f64>,
+    /// Ozone in μg/m³.
+    #[serde(default)]
+    pub o3: Option<f64>,
+    /// Sulphur dioxide in μg/m³.
+    #[serde(default)]
+    pub so2: Option<f64>,
+    /// Particulate matter smaller than 2.5 microns in μg/m³.
+    #[serde(default)]
+    pub pm2_5: Option<f64>,
+    /// Particulate matter smaller than 10 microns in μg/m³.
+    #[serde(default)]
+    pub pm10: Option<f64>,
+    /// US EPA index, from 1 (good) to 6 (hazardous).
+    #[serde(default)]
+    pub us_epa_index: Option<u8>,
+    /// UK DEFRA index, from 1 (low) to 10 (very high).
+    #[serde(default)]
+    pub gb_defra_index: Option<u8>,
+}
+
+/// Names of the providers that served a weather response, as written in configuration.
+#[derive(serde::Deserialize, Object)]
+pub struct Provenance {
+    /// Provider that located the caller, missing if the weather is for a saved or the development location.
+    #[serde(default)]
+    pub geolocation: Option<String>,
+    /// Provider of the weather information.
+    pub weather: String,
+}
+
+/// Default unit system and language of a user, in `get_preferences` and `set_preferences` calls.
+#[derive(serde::Serialize, serde::Deserialize, Object)]
+pub struct PreferencesBody {
+    /// Unit system used when `weather` is called without `units`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<Units>,
+    /// Language used when `weather` is called without `lang`, e.g. `fr` or `zh_tw`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+}
+
+impl From<Preferences> for PreferencesBody {
+    fn from(preferences: Preferences) -> Self {
+        Self {
+            units: preferences.units.and_then(|u| Units::from_str(&u).ok()),
+            lang: preferences.lang,
+        }
+    }
+}
+
+/// Response of `get_preferences` and `set_preferences` calls.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "PreferencesResponse::invalid_request")]
+pub enum PreferencesResponse {
+    /// Returned with the preferences of the user.
+    #[oai(status = 200)]
+    Success(Json<PreferencesBody>),
+    /// Returned when the body is malformed, with `invalid_request` code,
+    /// or `lang` is not valid, with `invalid_fields` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is invalid, with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the user of the token does not exist, with `not_found` code.
+    #[oai(status = 404, content_type = "application/problem+json")]
+    UserNotFound(ProblemBody),
+    /// Returned when the database operation fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    Failed(ProblemBody),
+}
+
+impl PreferencesResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+}
+
+/// A location saved by the user, in location calls.
+#[derive(serde::Deserialize, Object)]
+pub struct LocationBody {
+    /// ID of the location.
+    pub id: u64,
+    /// Name of the location, unique per user.
+    pub name: String,
+    /// Latitude in degrees.
+    pub latitude: f64,
+    /// Longitude in degrees.
+    pub longitude: f64,
+    /// Whether `weather` uses the location when called without `location`.
+    pub default: bool,
+}
+
+impl LocationBody {
+    /// Creates the body of a location saved with given ID.
+    fn new(id: u64, location: NewLocation) -> Self {
+        Self {
+            id,
+            name: location.name,
+            latitude: location.latitude,
+            longitude: location.longitude,
+            default: location.is_default,
+        }
+    }
+}
+
+impl From<SavedLocation> for LocationBody {
+    fn from(location: SavedLocation) -> Self {
+        Self {
+            id: location.id,
+            name: location.name,
+            latitude: location.latitude,
+            longitude: location.longitude,
+            default: location.is_default,
+        }
+    }
+}
+
+/// Body of `create_location` and `update_location` calls.
+#[derive(serde::Serialize, Object)]
+pub struct NewLocationBody {
+    /// Name of the location, between 1 and 64 characters.
+    pub name: String,
+    /// Latitude in degrees, between -90 and 90.
+    pub latitude: f64,
+    /// Longitude in degrees, between -180 and 180.
+    pub longitude: f64,
+    /// Whether the location becomes the default location of the user.
+    #[oai(default)]
+    #[serde(default)]
+    pub default: bool,
+}
+
+impl TryFrom<NewLocationBody> for NewLocation {
+    type Error = Vec<FieldError>;
+
+    /// Validates every field of the body.
+    ///
+    /// # Errors
+    /// Returns an error for each field that does not satisfy the restrictions.
+    fn try_from(body: NewLocationBody) -> Result<Self, Self::Error> {
+        let mut errors = Vec::new();
+
+        let name = body.name.trim();
+        if !(1..=64).contains(&name.chars().count()) {
+            errors.push(FieldError::new("name", "Name needs to be between 1 and 64 characters long"));
+        }
+        if !(-90.0..=90.0).contains(&body.latitude) {
+            errors.push(FieldError::new("latitude", "Latitude needs to be between -90 and 90"));
+        }
+        if !(-180.0..=180.0).contains(&body.longitude) {
+            errors.push(FieldError::new("longitude", "Longitude needs to be between -180 and 180"));
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            latitude: body.latitude,
+            longitude: body.longitude,
+            is_default: body.default,
+        })
+    }
+}
+
+/// Response of location calls.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "LocationResponse::invalid_request")]
+pub enum LocationResponse {
+    /// Returned with the location.
+    #[oai(status = 200)]
+    Success(Json<LocationBody>),
+    /// Returned with the location when it is saved.
+    #[oai(status = 201)]
+    Created(Json<LocationBody>),
+    /// Returned when the location is deleted.
+    #[oai(status = 204)]
+    Deleted,
+    /// Returned when the request is malformed, with `invalid_request` code,
+    /// or the location is not valid, with `invalid_fields` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidLocation(ProblemBody),
+    /// Returned when no token is provided or provided token is invalid, with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the user has no location with given ID, with `not_found` code.
+    #[oai(status = 404, content_type = "application/problem+json")]
+    NotFound(ProblemBody),
+    /// Returned when the user has a location with the same name, with `location_exists` code,
+    /// or has saved as many locations as allowed, with `location_limit_reached` code.
+    #[oai(status = 409, content_type = "application/problem+json")]
+    Conflict(ProblemBody),
+    /// Returned when the database operation fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    Failed(ProblemBody),
+}
+
+impl LocationResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidLocation(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+}
+
+/// Response of `list_locations` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "LocationsResponse::invalid_request")]
+pub enum LocationsResponse {
+    /// Returned with the saved locations of the user.
+    #[oai(status = 200)]
+    Success(Json<Vec<LocationBody>>),
+    /// Returned when the request is malformed, with `invalid_request` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is invalid, with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the database operation fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    Failed(ProblemBody),
+}
+
+impl LocationsResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+}
+
+/// Weather at a saved location, in `weather_for_locations` call response.
+#[derive(serde::Deserialize, Object)]
+pub struct LocationWeather {
+    /// The saved location.
+    pub location: LocationBody,
+    /// Weather at the location, missing if it could not be fetched.
+    #[serde(default)]
+    pub weather: Option<Box<WeatherResponseBody>>,
+    /// Why the weather could not be fetched, missing if it is fetched.
+    #[serde(default)]
+    pub problem: Option<Problem>,
+}
+
+/// Response of `weather_for_locations` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "LocationsWeatherResponse::invalid_request")]
+pub enum LocationsWeatherResponse {
+    /// Returned with the weather at every saved location of the user.
+    #[oai(status = 200)]
+    Success(Json<Vec<LocationWeather>>),
+    /// Returned when the request is malformed, with `invalid_request` code,
+    /// or `lang` is not valid, with `invalid_fields` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is invalid, with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when reading saved locations fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    Failed(ProblemBody),
+}
+
+impl LocationsWeatherResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+}
+
+/// Response of `weather_stream` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "WeatherStreamResponse::invalid_request")]
+pub enum WeatherStreamResponse {
+    /// Returned with the stream of weather updates.
+    #[oai(status = 200)]
+    Success(EventStream<BoxStream<'static, WeatherResponseBody>>),
+    /// Returned when the request is malformed, with `invalid_request` code,
+    /// or `lang` is not valid, with `invalid_fields` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is invalid, with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the user has no saved location with requested ID, with `not_found` code.
+    #[oai(status = 404, content_type = "application/problem+json")]
+    LocationNotFound(ProblemBody),
+    /// Returned when the caller's address is private and no development location is configured,
+    /// with `private_address` code.
+    #[oai(status = 422, content_type = "application/problem+json")]
+    PrivateAddress(ProblemBody),
+    /// Returned when the caller's address can not be determined or reading saved locations fails,
+    /// with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    InternalError(ProblemBody),
+    /// Returned when the geolocation API fails or responds with an error, with `upstream_error` code.
+    #[oai(status = 502, content_type = "application/problem+json")]
+    BadGateway(ProblemBody),
+    /// Returned when the geolocation API is rate limiting, out of quota or its circuit is open,
+    /// with `upstream_unavailable` code.
+    #[oai(status = 503, content_type = "application/problem+json")]
+    UpstreamUnavailable(ProblemBody),
+    /// Returned when the geolocation API does not respond in time, with `upstream_timeout` code.
+    #[oai(status = 504, content_type = "application/problem+json")]
+    GatewayTimeout(ProblemBody),
+}
+
+impl WeatherStreamResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+
+    /// Returns the response matching a problem created by `resolve_units_and_lang` or `resolve_location`.
+    fn from_problem(problem: Problem) -> Self {
+        match problem.code {
+            ProblemCode::InvalidFields => Self::InvalidRequest(problem.into_json()),
+            ProblemCode::NotFound => Self::LocationNotFound(problem.into_json()),
+            ProblemCode::PrivateAddress => Self::PrivateAddress(problem.into_json()),
+            ProblemCode::InternalError => Self::InternalError(problem.into_json()),
+            ProblemCode::UpstreamTimeout => Self::GatewayTimeout(problem.into_json()),
+            ProblemCode::UpstreamUnavailable => Self::UpstreamUnavailable(problem.into_json()),
+            _ => Self::BadGateway(problem.into_json()),
+        }
+    }
+}
+
+/// Builds the filter of `list_audit_events` and `export_audit_events` calls, returning the problems with it.
+fn audit_filter(
+    user_id: Option<u64>,
+    event: Option<AuditEventKind>,
+    outcome: Option<AuditOutcome>,
+    ip: Option<&str>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<AuditFilter, Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    // Parsed so the address matches the form it is recorded in, e.g. without leading zeros
+    let client_ip = ip.map(IpAddr::from_str).transpose().unwrap_or_else(|_| {
+        errors.push(FieldError::new("ip", "IP needs to be a valid IPv4 or IPv6 address"));
+        None
+    });
+    if matches!((from, to), (Some(from), Some(to)) if from > to) {
+        errors.push(FieldError::new("from", "From needs to be before to"));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(AuditFilter {
+        user_id,
+        event: event.map(|e| e.as_str().to_owned()),
+        outcome: outcome.map(|o| o.as_str().to_owned()),
+        client_ip: client_ip.map(|ip| ip.to_string()),
+        from: from.map(|from| from.timestamp()),
+        to: to.map(|to| to.timestamp()),
+        before_id: None,
+    })
+}
+
+/// Checks the range and the page of a `weather_history` call, returning the problems with them.
+fn validate_history_query(from: NaiveDate, to: NaiveDate, page: u32, page_size: u32) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    if from > to {
+        errors.push(FieldError::new("from", "From needs to be on or before to"));
+    } else if (to - from).num_days() >= i64::from(history::MAX_DAYS) {
+        errors.push(FieldError::new("to", &format!("Range can span at most {} days", history::MAX_DAYS)));
+    }
+    if to > Utc::now().date_naive() {
+        errors.push(FieldError::new("to", "To can not be in the future"));
+    }
+    if page == 0 {
+        errors.push(FieldError::new("page", "Page needs to be at least 1"));
+    }
+    if page_size == 0 {
+        errors.push(FieldError::new("page_size", "Page size needs to be at least 1"));
+    }
+
+    errors
+}
+
+/// Body of `weather_history` call success response.
+///
+/// Units of values depend on `units` as in `weather` call response.
+#[derive(serde::Deserialize, Object)]
+pub struct HistoryResponseBody {
+    /// Unit system of the values.
+    pub units: Units,
+    /// Latitude the history is for.
+    pub latitude: f64,
+    /// Longitude the history is for.
+    pub longitude: f64,
+    /// ID of the saved location the history is for, missing if the caller is located by their IP address.
+    #[serde(default)]
+    pub saved_location_id: Option<u64>,
+    /// First day of the range.
+    pub from: NaiveDate,
+    /// Last day of the range.
+    pub to: NaiveDate,
+    /// Number of the returned page, starting from 1.
+    pub page: u32,
+    /// Most observations in a page.
+    pub page_size: u32,
+    /// Number of observations in the whole range.
+    pub total: u64,
+    /// Hourly observations of the page, oldest first.
+    pub observations: Vec<ObservationBody>,
+}
+
+/// An hourly observation, in `weather_history` call response.
+///
+/// Optional fields are missing if the weather provider does not report them.
+#[derive(serde::Deserialize, Object)]
+pub struct ObservationBody {
+    /// Start of the hour observed.
+    pub time: DateTime<Utc>,
+    pub temperature: f64,
+    #[serde(default)]
+    pub feels_like: Option<f64>,
+    /// Weather condition in English, e.g. `Partly cloudy`.
+    #[serde(default)]
+    pub condition: Option<String>,
+    /// Code of the condition in the scheme of the provider.
+    #[serde(default)]
+    pub condition_code: Option<u16>,
+    #[serde(default)]
+    pub wind_speed: Option<f64>,
+    /// Relative humidity in percent.
+    #[serde(default)]
+    pub humidity: Option<u8>,
+    #[serde(default)]
+    pub pressure: Option<f64>,
+    #[serde(default)]
+    pub precipitation: Option<f64>,
+    /// Cloud cover in percent.
+    #[serde(default)]
+    pub cloud_cover: Option<u8>,
+    #[serde(default)]
+    pub uv_index: Option<f64>,
+    /// Weather provider that reported the observation, as written in configuration.
+    pub provider: String,
+    /// Whether the observation was served from the database rather than fetched for this call.
+    pub stored: bool,
+}
+
+impl ObservationBody {
+    /// Creates the observation body, converting values to given units.
+    fn new(entry: HistoryEntry, units: Units) -> Self {
+        let observation = entry.observation;
+        Self {
+            time: DateTime::from_timestamp(observation.observed_at, 0).unwrap_or_default(),
+            temperature: units.temperature(observation.temperature_c),
+            feels_like: observation.feels_like_c.map(|t| units.temperature(t)),
+            condition: observation.condition,
+            condition_code: observation.condition_code,
+            wind_speed: observation.wind_kph.map(|s| units.speed(s)),
+            humidity: observation.humidity,
+            pressure: observation.pressure_mb.map(|p| units.pressure(p)),
+            precipitation: observation.precip_mm.map(|p| units.precipitation(p)),
+            cloud_cover: observation.cloud,
+            uv_index: observation.uv,
+            provider: observation.provider,
+            stored: entry.stored,
+        }
+    }
+}
+
+/// Response of `weather_history` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "HistoryResponse::invalid_request")]
+pub enum HistoryResponse {
+    /// Returned with the requested page of observations.
+    #[oai(status = 200)]
+    Success(Json<HistoryResponseBody>),
+    /// Returned when the request is malformed, with `invalid_request` code,
+    /// or the range or the page is not valid, with `invalid_fields` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is invalid, with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the user has no saved location with requested ID, with `not_found` code.
+    #[oai(status = 404, content_type = "application/problem+json")]
+    LocationNotFound(ProblemBody),
+    /// Returned when the caller's address is private and no development location is configured,
+    /// with `private_address` code.
+    #[oai(status = 422, content_type = "application/problem+json")]
+    PrivateAddress(ProblemBody),
+    /// Returned when the caller's address can not be determined or reading saved locations fails,
+    /// with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    InternalError(ProblemBody),
+    /// Returned when a foreign API fails or responds with an error, with `upstream_error` code.
+    #[oai(status = 502, content_type = "application/problem+json")]
+    BadGateway(ProblemBody),
+    /// Returned when a foreign API is rate limiting, out of quota or its circuit is open,
+    /// with `upstream_unavailable` code.
+    #[oai(status = 503, content_type = "application/problem+json")]
+    UpstreamUnavailable(ProblemBody),
+    /// Returned when a foreign API does not respond in time, with `upstream_timeout` code.
+    #[oai(status = 504, content_type = "application/problem+json")]
+    GatewayTimeout(ProblemBody),
+}
+
+impl HistoryResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+
+    /// Returns the response matching a problem created by `resolve_location` or `upstream_failure_problem`.
+    fn from_problem(problem: Problem) -> Self {
+        match problem.code {
+            ProblemCode::InvalidFields => Self::InvalidRequest(problem.into_json()),
+            ProblemCode::NotFound => Self::LocationNotFound(problem.into_json()),
+            ProblemCode::PrivateAddress => Self::PrivateAddress(problem.into_json()),
+            ProblemCode::InternalError => Self::InternalError(problem.into_json()),
+            ProblemCode::UpstreamTimeout => Self::GatewayTimeout(problem.into_json()),
+            ProblemCode::UpstreamUnavailable => Self::UpstreamUnavailable(problem.into_json()),
+            _ => Self::BadGateway(problem.into_json()),
+        }
+    }
+}
+
+/// Body of `set_webhook` call.
+#[derive(serde::Serialize, Object)]
+pub struct NewWebhookBody {
+    /// URL alerts are posted to.
+    pub url: String,
+}
+
+/// Webhook of a user, in webhook calls.
+#[derive(serde::Deserialize, Object)]
+pub struct WebhookBody {
+    /// URL alerts are posted to.
+    pub url: String,
+    /// Key of the HMAC-SHA256 signatures in the `X-Alert-Signature` header of alerts.
+    pub secret: String,
+}
+
+impl From<Webhook> for WebhookBody {
+    fn from(webhook: Webhook) -> Self {
+        Self {
+            url: webhook.url,
+            secret: webhook.secret,
+        }
+    }
+}
+
+/// Response of webhook calls.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "WebhookResponse::invalid_request")]
+pub enum WebhookResponse {
+    /// Returned with the webhook.
+    #[oai(status = 200)]
+    Success(Json<WebhookBody>),
+    /// Returned when the webhook is deleted.
+    #[oai(status = 204)]
+    Deleted,
+    /// Returned when the request is malformed, with `invalid_request` code,
+    /// or the URL is not valid, with `invalid_fields` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is invalid, with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the user has not configured a webhook, with `not_found` code.
+    #[oai(status = 404, content_type = "application/problem+json")]
+    NotFound(ProblemBody),
+    /// Returned when the database operation fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    Failed(ProblemBody),
+}
+
+impl WebhookResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+}
+
+/// Body of `create_alert_rule` call.
+#[derive(serde::Serialize, Object)]
+pub struct NewAlertRuleBody {
+    /// ID of the saved location the rule is evaluated at.
+    pub location_id: u64,
+    /// Condition that fires the rule.
+    pub condition: AlertCondition,
+    /// Threshold of the condition, in °C, km/h or percent chance of rain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>,
+    /// Hours ahead `precipitation_expected` looks at, between 1 and 24.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub within_hours: Option<u8>,
+}
+
+impl TryFrom<NewAlertRuleBody> for NewAlertRule {
+    type Error = Vec<FieldError>;
+
+    /// Validates every field of the body, except that the location exists.
+    ///
+    /// # Errors
+    /// Returns an error for each field that does not satisfy the restrictions.
+    fn try_from(body: NewAlertRuleBody) -> Result<Self, Self::Error> {
+        let mut errors = Vec::new();
+
+        let threshold = match (body.condition, body.threshold) {
+            (AlertCondition::PrecipitationExpected, None) => Some(50.0),
+            (AlertCondition::PrecipitationExpected, Some(t)) if !(0.0..=100.0).contains(&t) => {
+                errors.push(FieldError::new("threshold", "Chance of rain needs to be between 0 and 100"));
+                None
+            }
+            (_, Some(t)) if !t.is_finite() => {
+                errors.push(FieldError::new("threshold", "Threshold needs to be a finite number"));
+                None
+            }
+            (_, Some(t)) => Some(t),
+            (_, None) => {
+                errors.push(FieldError::new("threshold", "Threshold is required for this condition"));
+                None
+            }
+        };
+
+        match (body.condition.is_forecast(), body.within_hours) {
+            (true, Some(hours)) if (1..=24).contains(&hours) => {}
+            (true, _) => errors.push(FieldError::new("within_hours", "Hours need to be between 1 and 24")),
+            (false, Some(_)) => errors.push(FieldError::new("within_hours", "Hours are only allowed for forecast conditions")),
+            (false, None) => {}
+        }
+
+        match threshold {
+            Some(threshold) if errors.is_empty() => Ok(Self {
+                location_id: body.location_id,
+                condition: body.condition.as_str().to_owned(),
+                threshold,
+                within_hours: body.within_hours,
+            }),
+            _ => Err(errors),
+        }
+    }
+}
+
+/// An alert rule of a user, in alert rule calls.
+#[derive(serde::Deserialize, Object)]
+pub struct AlertRuleBody {
+    /// ID of the alert rule.
+    pub id: u64,
+    /// ID of the saved location the rule is evaluated at.
+    pub location_id: u64,
+    /// Condition that fires the rule.
+    pub condition: AlertCondition,
+    /// Threshold of the condition, in °C, km/h or percent chance of rain.
+    pub threshold: f64,
+    /// Hours ahead `precipitation_expected` looks at.
+    #[serde(default)]
+    pub within_hours: Option<u8>,
+    /// Whether the condition held at the last evaluation.
+    pub firing: bool,
+    /// Time the rule last fired at.
+    #[serde(default)]
+    pub last_fired_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<AlertRule> for AlertRuleBody {
+    type Error = ();
+
+    /// Fails if the stored condition is unknown, which the database schema prevents.
+    fn try_from(rule: AlertRule) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: rule.id,
+            location_id: rule.location_id,
+            condition: AlertCondition::from_str(&rule.condition)?,
+            threshold: rule.threshold,
+            within_hours: rule.within_hours,
+            firing: rule.firing,
+            last_fired_at: rule.last_fired_at.and_then(|t| DateTime::from_timestamp(t, 0)),
+        })
+    }
+}
+
+/// Response of `create_alert_rule` and `delete_alert_rule` calls.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "AlertRuleResponse::invalid_request")]
+pub enum AlertRuleResponse {
+    /// Returned with the alert rule when it is created.
+    #[oai(status = 201)]
+    Created(Json<AlertRuleBody>),
+    /// Returned when the alert rule is deleted.
+    #[oai(status = 204)]
+    Deleted,
+    /// Returned when the request is malformed, with `invalid_request` code,
+    /// or the rule is not valid, with `invalid_fields` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRule(ProblemBody),
+    /// Returned when no token is provided or provided token is invalid, with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the user has no alert rule with given ID, with `not_found` code.
+    #[oai(status = 404, content_type = "application/problem+json")]
+    NotFound(ProblemBody),
+    /// Returned when the user has created as many alert rules as allowed, with `alert_rule_limit_reached` code.
+    #[oai(status = 409, content_type = "application/problem+json")]
+    Conflict(ProblemBody),
+    /// Returned when the database operation fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    Failed(ProblemBody),
+}
+
+impl AlertRuleResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidRule(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+}
+
+/// Response of `list_alert_rules` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "AlertRulesResponse::invalid_request")]
+pub enum AlertRulesResponse {
+    /// Returned with the alert rules of the user.
+    #[oai(status = 200)]
+    Success(Json<Vec<AlertRuleBody>>),
+    /// Returned when the request is malformed, with `invalid_request` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is invalid, with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the database operation fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    Failed(ProblemBody),
+}
+
+impl AlertRulesResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+}
+
+/// An attempt to deliver an alert, in `list_alert_deliveries` call response.
+#[derive(serde::Deserialize, Object)]
+pub struct AlertDeliveryBody {
+    /// ID of the alert rule that fired.
+    pub rule_id: u64,
+    /// ID of the alert, same for every attempt to deliver it.
+    pub alert_id: String,
+    /// Number of the attempt, starting from 1.
+    pub attempt: u32,
+    /// Status the webhook responded with, missing if it did not respond.
+    #[serde(default)]
+    pub status: Option<u16>,
+    /// Kind of failure if the webhook did not respond: `timed out`, `connection failed`, `request failed`
+    /// or `destination not allowed`.
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Whether the webhook accepted the alert with a `2xx` status.
+    pub delivered: bool,
+    /// Time of the attempt.
+    pub attempted_at: DateTime<Utc>,
+}
+
+impl From<AlertDelivery> for AlertDeliveryBody {
+    fn from(delivery: AlertDelivery) -> Self {
+        Self {
+            rule_id: delivery.rule_id,
+            alert_id: delivery.event_id,
+            attempt: delivery.attempt,
+            status: delivery.status_code,
+            error: delivery.error,
+            delivered: delivery.delivered,
+            attempted_at: DateTime::from_timestamp(delivery.attempted_at, 0).unwrap_or_default(),
+        }
+    }
+}
+
+/// Response of `list_alert_deliveries` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "AlertDeliveriesResponse::invalid_request")]
+pub enum AlertDeliveriesResponse {
+    /// Returned with the delivery attempts.
+    #[oai(status = 200)]
+    Success(Json<Vec<AlertDeliveryBody>>),
+    /// Returned when the request is malformed, with `invalid_request` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is invalid, with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the database operation fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    Failed(ProblemBody),
+}
+
+impl AlertDeliveriesResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+}
+
+/// A backup of the database.
+#[derive(serde::Deserialize, Object)]
+pub struct BackupBody {
+    /// Name of the backup file in the backup directory, given to the `backup restore` command.
+    pub name: String,
+    /// Size of the backup in bytes.
+    pub size: u64,
+    /// Time the backup is taken at.
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<BackupFile> for BackupBody {
+    fn from(backup: BackupFile) -> Self {
+        Self {
+            name: backup.name,
+            size: backup.size,
+            created_at: backup.created_at,
+        }
+    }
+}
+
+/// Response of `create_backup` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "BackupResponse::invalid_request")]
+pub enum BackupResponse {
+    /// Returned with the backup taken.
+    #[oai(status = 201)]
+    Created(Json<BackupBody>),
+    /// Returned when the request is malformed, with `invalid_request` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is not the administrator token,
+    /// with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when writing the backup fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    Failed(ProblemBody),
+    /// Returned when the database backend does not support backups, with `not_supported` code.
+    #[oai(status = 501, content_type = "application/problem+json")]
+    NotSupported(ProblemBody),
+}
+
+impl BackupResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+}
+
+/// Response of `list_backups` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "BackupsResponse::invalid_request")]
+pub enum BackupsResponse {
+    /// Returned with the backups, newest first.
+    #[oai(status = 200)]
+    Success(Json<Vec<BackupBody>>),
+    /// Returned when the request is malformed, with `invalid_request` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is not the administrator token,
+    /// with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the backup directory can not be read, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    Failed(ProblemBody),
+}
+
+impl BackupsResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
+
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
+}
+
+/// An event of the audit log, in `list_audit_events` call response and lines of `export_audit_events`.
+#[derive(serde::Serialize, serde::Deserialize, Object)]
+pub struct AuditEventBody {
+    /// ID of the event, increasing in the order events are recorded.
+    pub id: u64,
+    /// ID of the user the event is about, missing if no user is identified.
+    #[serde(default)]
+    pub user_id: Option<u64>,
+    pub event: AuditEventKind,
+    pub outcome: AuditOutcome,
+    /// Address of the client, missing for events of the command-line interface.
+    #[serde(default)]
+    pub client_ip: Option<String>,
+    /// `User-Agent` header of the request.
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Time of the event.
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl From<AuditEvent> for AuditEventBody {
+    fn from(event: AuditEvent) -> Self {
+        Self {
+            id: event.id,
+            user_id: event.user_id,
+            // Both are restricted to known names by the database
+            event: AuditEventKind::from_str(&event.event).unwrap_or(AuditEventKind::Login),
+            outcome: AuditOutcome::from_str(&event.outcome).unwrap_or(AuditOutcome::Failure),
+            client_ip: event.client_ip,
+            user_agent: event.user_agent,
+            occurred_at: DateTime::from_timestamp(event.occurred_at, 0).unwrap_or_default(),
+        }
+    }
+}
+
+/// Response of `list_audit_events` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "AuditEventsResponse::invalid_request")]
+pub enum AuditEventsResponse {
+    /// Returned with the events, newest first.
+    #[oai(status = 200)]
+    Success(Json<Vec<AuditEventBody>>),
+    /// Returned when the request is malformed, with `invalid_request` code,
+    /// or a filter is not valid, with `invalid_fields` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is not the administrator token,
+    /// with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the database operation fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    Failed(ProblemBody),
+}
 
-    if ip.is_loopback() || ip.is_multicast() {
-        const IP_BLOCK_SIZE: u32 = 2_097_152;
-        let range_start = [78u8, 160u8, 0u8, 0u8];
-        let offset: [u8; 4] = rand::thread_rng().gen_range(0..IP_BLOCK_SIZE).to_be_bytes();
+impl AuditEventsResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
 
-        ip = IpAddr::V4(Ipv4Addr::new(
-            range_start[0] + offset[0],
-            range_start[1] + offset[1],
-            range_start[2] + offset[2],
-            range_start[3] + offset[3],
-        ));
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
     }
+}
+
+/// Response of `export_audit_events` call.
+#[derive(ApiResponse)]
+#[oai(bad_request_handler = "AuditExportResponse::invalid_request")]
+pub enum AuditExportResponse {
+    /// Returned with the events as JSON Lines, newest first.
+    #[oai(status = 200, content_type = "application/x-ndjson")]
+    Success(Binary<Body>),
+    /// Returned when the request is malformed, with `invalid_request` code,
+    /// or a filter is not valid, with `invalid_fields` code.
+    #[oai(status = 400, content_type = "application/problem+json")]
+    InvalidRequest(ProblemBody),
+    /// Returned when no token is provided or provided token is not the administrator token,
+    /// with `unauthorized` code.
+    #[oai(status = 401, content_type = "application/problem+json")]
+    Unauthorized(ProblemBody),
+    /// Returned when the database operation fails, with `internal_error` code.
+    #[oai(status = 500, content_type = "application/problem+json")]
+    Failed(ProblemBody),
+}
+
+impl AuditExportResponse {
+    /// Converts errors of parsing the request, including a missing `Authorization` header,
+    /// into a problem response.
+    fn invalid_request(error: poem::Error) -> Self {
+        if error.status() == poem::http::StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized(Problem::new(ProblemCode::Unauthorized, "Unauthorized access.").into_json());
+        }
 
-    ip.to_string()
+        Self::InvalidRequest(Problem::new(ProblemCode::InvalidRequest, &error.to_string()).into_json())
+    }
 }
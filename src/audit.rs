@@ -0,0 +1,142 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use chrono::Utc;
+use poem_openapi::Enum;
+
+use crate::queries::{Database, NewAuditEvent};
+
+/// Longest user agent kept in the audit log, longer ones are truncated.
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// Kind of an event in the audit log.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum AuditEventKind {
+    /// A user is registered through the API or the command-line interface.
+    Registration,
+    /// A user logs in, failing if the credentials are wrong.
+    Login,
+    /// A JWT token is issued to a user, on login or through the command-line interface.
+    TokenIssuance,
+    /// The password of a user is reset through the command-line interface.
+    PasswordReset,
+    /// A user is disabled through the command-line interface.
+    Disabling,
+    /// A disabled user is enabled through the command-line interface.
+    Enabling,
+    /// A user is deleted through the command-line interface, kept for the retention period.
+    Deletion,
+    /// A deleted user is restored through the command-line interface.
+    Restoration,
+    /// A user is deleted permanently, once the retention period passes or immediately through
+    /// the command-line interface.
+    Purge,
+}
+
+impl AuditEventKind {
+    /// Returns the name of the event as written in requests and the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Registration => "registration",
+            Self::Login => "login",
+            Self::TokenIssuance => "token_issuance",
+            Self::PasswordReset => "password_reset",
+            Self::Disabling => "disabling",
+            Self::Enabling => "enabling",
+            Self::Deletion => "deletion",
+            Self::Restoration => "restoration",
+            Self::Purge => "purge",
+        }
+    }
+}
+
+impl FromStr for AuditEventKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "registration" => Ok(Self::Registration),
+            "login" => Ok(Self::Login),
+            "token_issuance" => Ok(Self::TokenIssuance),
+            "password_reset" => Ok(Self::PasswordReset),
+            "disabling" => Ok(Self::Disabling),
+            "enabling" => Ok(Self::Enabling),
+            "deletion" => Ok(Self::Deletion),
+            "restoration" => Ok(Self::Restoration),
+            "purge" => Ok(Self::Purge),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Whether the operation an audit event records succeeded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    /// Returns the name of the outcome as written in requests and the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+        }
+    }
+}
+
+impl FromStr for AuditOutcome {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "success" => Ok(Self::Success),
+            "failure" => Ok(Self::Failure),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Client an audited operation is requested by, both missing for the command-line interface.
+#[derive(Debug, Default, Clone)]
+pub struct Requester {
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+/// Appends an event that occurred now to the audit log.
+///
+/// Failures are only logged, so they do not fail the operation being audited.
+pub async fn record(
+    database: &Database,
+    event: AuditEventKind,
+    outcome: AuditOutcome,
+    user_id: Option<u64>,
+    requester: &Requester,
+) {
+    let user_agent = requester.user_agent.as_deref().map(|user_agent| {
+        user_agent
+            .chars()
+            .take(MAX_USER_AGENT_LENGTH)
+            .collect::<String>()
+    });
+    let event = NewAuditEvent {
+        user_id,
+        event: event.as_str().to_owned(),
+        outcome: outcome.as_str().to_owned(),
+        client_ip: requester.ip.map(|ip| ip.to_string()),
+        user_agent,
+        occurred_at: Utc::now().timestamp(),
+    };
+
+    if database.log_audit_event(&event).await.is_err() {
+        tracing::error!(event = event.event, outcome = event.outcome, "recording audit event failed");
+    }
+}
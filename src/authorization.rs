@@ -1,6 +1,7 @@
 use std::sync::OnceLock;
 use chrono::Utc;
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use sha2::{Digest, Sha256};
 
 /// Static storage for JWT keys.
 static JWT_KEYS: OnceLock<Keys> = OnceLock::new();
@@ -27,7 +28,35 @@ pub fn create_token(user_id: u64) -> Result<String, jsonwebtoken::errors::Error>
 #[must_use]
 /// Checks if the given token is issued with this server's key.
 pub fn check_token(token: &str) -> bool {
-    jsonwebtoken::decode::<TokenBody>(token, &Keys::get().decoding, &Validation::default()).is_ok()
+    user_id_from_token(token).is_some()
+}
+
+#[must_use]
+/// Returns the user ID in the given token if it is issued with this server's key and not expired.
+pub fn user_id_from_token(token: &str) -> Option<u64> {
+    jsonwebtoken::decode::<TokenBody>(token, &Keys::get().decoding, &Validation::default())
+        .ok()
+        .map(|data| data.claims.user_id)
+}
+
+#[must_use]
+/// Checks if the given token is the administrator token set by the `ADMIN_TOKEN` environment variable.
+///
+/// Always fails if the variable is not set or empty, so administration endpoints are disabled by default.
+/// Digests of the tokens are compared, so the time taken does not reveal how much of the token matches.
+pub fn check_admin_token(token: &str) -> bool {
+    let Some(admin_token) = std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()) else {
+        return false;
+    };
+
+    let expected = Sha256::digest(admin_token.as_bytes());
+    let given = Sha256::digest(token.as_bytes());
+
+    expected
+        .iter()
+        .zip(given.iter())
+        .fold(0, |difference, (a, b)| difference | (a ^ b))
+        == 0
 }
 
 /// Represents the claim section of JWT token.
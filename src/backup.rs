@@ -0,0 +1,304 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection};
+use sqlx::{Connection, Row};
+use tokio::sync::Mutex;
+use tokio::time::MissedTickBehavior;
+
+use crate::config::{BackupConfig, Config};
+use crate::queries::{Database, SqlError, SQLITE_MIGRATOR};
+
+/// Prefix of backup file names, followed by the UTC time the backup is taken at.
+const FILE_PREFIX: &str = "backup-";
+/// Extension of backup file names.
+const FILE_EXTENSION: &str = ".db";
+/// Format of the time in backup file names, sorting names sorts backups by time.
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Takes online backups of the database to a directory and removes the oldest ones beyond the retention.
+///
+/// Backups are consistent snapshots written with `VACUUM INTO`, so they can be taken while the server runs.
+pub struct Backups {
+    database: Database,
+    directory: PathBuf,
+    interval: Duration,
+    retention: usize,
+    /// Held while a backup is written and old ones are removed, so concurrent backups do not interleave.
+    lock: Mutex<()>,
+}
+
+/// A backup in the backup directory.
+#[derive(Debug, Clone)]
+pub struct BackupFile {
+    /// Name of the file in the backup directory.
+    pub name: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Time the backup is taken at.
+    pub created_at: DateTime<Utc>,
+}
+
+/// Outcome of a restore.
+#[derive(Debug)]
+pub struct Restored {
+    /// Path the replaced database file is moved to, if there was one.
+    pub previous: Option<PathBuf>,
+    /// Migrations that are not applied to the backup, applied when the server next connects to the database.
+    pub pending_migrations: usize,
+}
+
+impl Backups {
+    /// Creates the backup facility of given database with given configuration.
+    #[must_use]
+    pub fn new(config: &BackupConfig, database: Database) -> Self {
+        Self {
+            database,
+            directory: config.directory.clone(),
+            interval: Duration::from_secs(config.interval_seconds.max(1)),
+            retention: config.retention.max(1),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Writes a backup of the database to the backup directory, then removes backups beyond the retention.
+    ///
+    /// # Errors
+    /// Returns error if the database backend does not support backups, or writing the backup fails.
+    /// Failing to remove old backups is only logged.
+    pub async fn create(&self) -> Result<BackupFile, Error> {
+        let _guard = self.lock.lock().await;
+        tokio::fs::create_dir_all(&self.directory).await?;
+
+        let created_at = Utc::now();
+        let name = format!("{FILE_PREFIX}{}{FILE_EXTENSION}", created_at.format(TIME_FORMAT));
+        let path = self.directory.join(&name);
+
+        // Written under another name first, so interrupted backups are not listed or restored
+        let partial = with_suffix(&path, ".partial");
+        self.database.backup(&partial).await.map_err(|e| match e {
+            SqlError::Unsupported => Error::Unsupported,
+            _ => Error::Database,
+        })?;
+        tokio::fs::rename(&partial, &path).await?;
+        let size = tokio::fs::metadata(&path).await?.len();
+        tracing::info!(backup = name, size, "database backed up");
+
+        self.prune().await;
+
+        Ok(BackupFile { name, size, created_at })
+    }
+
+    /// Returns the backups in the backup directory, newest first.
+    ///
+    /// # Errors
+    /// Returns error if the directory exists but can not be read.
+    pub async fn list(&self) -> Result<Vec<BackupFile>, Error> {
+        let mut entries = match tokio::fs::read_dir(&self.directory).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut backups = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(created_at) = backup_time(&name) else {
+                continue;
+            };
+            let size = entry.metadata().await?.len();
+            backups.push(BackupFile { name, size, created_at });
+        }
+        backups.sort_by(|a, b| b.name.cmp(&a.name));
+
+        Ok(backups)
+    }
+
+    /// Takes a backup every interval, starting one interval after it is called. Never returns.
+    ///
+    /// Failures are logged and the next backup is attempted at the next interval.
+    pub async fn run(&self) {
+        let start = tokio::time::Instant::now() + self.interval;
+        let mut interval = tokio::time::interval_at(start, self.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.create().await {
+                tracing::error!(error = %e, "scheduled backup failed");
+            }
+        }
+    }
+
+    /// Removes the oldest backups beyond the retention.
+    async fn prune(&self) {
+        let backups = match self.list().await {
+            Ok(backups) => backups,
+            Err(e) => {
+                tracing::warn!(error = %e, "listing backups to remove failed");
+                return;
+            }
+        };
+
+        for backup in backups.iter().skip(self.retention) {
+            if let Err(e) = tokio::fs::remove_file(self.directory.join(&backup.name)).await {
+                tracing::warn!(backup = backup.name, error = %e, "removing old backup failed");
+            }
+        }
+    }
+}
+
+/// Replaces the configured SQLite database with a backup, after checking the backup can be used.
+///
+/// The backup needs to pass the integrity check and have only migrations embedded into the binary applied,
+/// with matching checksums. Migrations applied after the backup was taken are applied on the next connection.
+/// The replaced database file and its journal files are kept next to it with a `.pre-restore-<time>` suffix,
+/// and moved back if the backup can not be swapped in.
+///
+/// The server needs to be stopped while restoring, as open connections keep using the replaced file.
+///
+/// # Errors
+/// Returns error if the database is not a SQLite file, the backup is invalid or replacing the file fails.
+pub async fn restore(config: &Config, source: &Path) -> Result<Restored, Error> {
+    let database_url = config.database_url();
+    if !database_url.starts_with("sqlite:") {
+        return Err(Error::Unsupported);
+    }
+    let target = SqliteConnectOptions::from_str(&database_url)
+        .map_err(|_| Error::Unsupported)?
+        .get_filename()
+        .to_owned();
+
+    let pending_migrations = validate(source).await?;
+
+    // Copied next to the target first, so the swap is a rename within a directory
+    let staging = with_suffix(&target, ".restoring");
+    tokio::fs::copy(source, &staging).await?;
+
+    let mut moved = Vec::new();
+    let previous = if tokio::fs::try_exists(&target).await? {
+        let suffix = format!(".pre-restore-{}", Utc::now().format(TIME_FORMAT));
+        for journal in ["", "-wal", "-shm"] {
+            let file = with_suffix(&target, journal);
+            let kept = with_suffix(&file, &suffix);
+            let renamed = match tokio::fs::try_exists(&file).await {
+                Ok(true) => tokio::fs::rename(&file, &kept).await,
+                Ok(false) => continue,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = renamed {
+                return Err(roll_back(e, &staging, moved).await);
+            }
+            moved.push((file, kept));
+        }
+        Some(with_suffix(&target, &suffix))
+    } else {
+        None
+    };
+
+    if let Err(e) = tokio::fs::rename(&staging, &target).await {
+        return Err(roll_back(e, &staging, moved).await);
+    }
+
+    Ok(Restored { previous, pending_migrations })
+}
+
+/// Moves the files of the replaced database back after replacing it failed with given error,
+/// and returns the error to report.
+///
+/// The files that can not be moved back are listed in the error, so they can be moved back by hand.
+async fn roll_back(error: std::io::Error, staging: &Path, moved: Vec<(PathBuf, PathBuf)>) -> Error {
+    let _ = tokio::fs::remove_file(staging).await;
+
+    let mut kept = Vec::new();
+    for (file, renamed) in moved {
+        if let Err(e) = tokio::fs::rename(&renamed, &file).await {
+            tracing::error!(file = %renamed.display(), error = %e, "moving back replaced database file failed");
+            kept.push(renamed.display().to_string());
+        }
+    }
+
+    if kept.is_empty() {
+        Error::Io(error)
+    } else {
+        Error::Incomplete { error, kept }
+    }
+}
+
+/// Checks the file at given path is an intact SQLite database migrated by a version of this binary,
+/// and returns the number of migrations not applied to it.
+async fn validate(path: &Path) -> Result<usize, Error> {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(true)
+        .create_if_missing(false);
+    let mut connection = SqliteConnection::connect_with(&options)
+        .await
+        .map_err(|_| Error::Invalid("the file is not a readable SQLite database".to_owned()))?;
+
+    let integrity = sqlx::query_scalar::<_, String>("PRAGMA integrity_check")
+        .fetch_one(&mut connection)
+        .await
+        .map_err(|_| Error::Invalid("the file is not a readable SQLite database".to_owned()))?;
+    if integrity != "ok" {
+        return Err(Error::Invalid(format!("the integrity check failed: {integrity}")));
+    }
+
+    // The table is managed by sqlx, so the query is not checked at compile time
+    let applied = sqlx::query("SELECT version, success, checksum FROM _sqlx_migrations ORDER BY version")
+        .fetch_all(&mut connection)
+        .await
+        .map_err(|_| Error::Invalid("the database has no migration history".to_owned()))?;
+    connection.close().await.ok();
+
+    for row in &applied {
+        let version = row.get::<i64, &str>("version");
+        if !row.get::<bool, &str>("success") {
+            return Err(Error::Invalid(format!("migration {version} is not applied successfully")));
+        }
+
+        let Some(migration) = SQLITE_MIGRATOR.iter().find(|m| m.version == version) else {
+            return Err(Error::Invalid(format!(
+                "migration {version} is unknown, the backup is taken by a newer version of the server"
+            )));
+        };
+        if *migration.checksum != *row.get::<Vec<u8>, &str>("checksum") {
+            return Err(Error::Invalid(format!("migration {version} differs from the one of this version")));
+        }
+    }
+
+    Ok(SQLITE_MIGRATOR.iter().count() - applied.len())
+}
+
+/// Returns the time a backup is taken at from its file name, if it is the name of a backup.
+fn backup_time(name: &str) -> Option<DateTime<Utc>> {
+    let time = name.strip_prefix(FILE_PREFIX)?.strip_suffix(FILE_EXTENSION)?;
+
+    NaiveDateTime::parse_from_str(time, TIME_FORMAT)
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+/// Appends a suffix to the file name of a path.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Errors of taking and restoring backups.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("backups are only supported for SQLite databases")]
+    Unsupported,
+    #[error("the database failed to write the backup")]
+    Database,
+    #[error("invalid backup, {0}")]
+    Invalid(String),
+    #[error("file operation failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("file operation failed: {error}, the replaced database is kept at {}", .kept.join(", "))]
+    Incomplete { error: std::io::Error, kept: Vec<String> },
+}
@@ -0,0 +1,132 @@
+use unicode_normalization::UnicodeNormalization;
+
+use crate::queries::{Database, SqlError};
+
+/// Returns the canonical form of a username: NFKC normalized and case folded.
+///
+/// Usernames with the same canonical form belong to a single user, so `Alice`, `alice` and `ａｌｉｃｅ` are one.
+#[must_use]
+pub fn username(username: &str) -> String {
+    fold(username)
+}
+
+/// Returns the canonical form of an email: NFKC normalized, with the local part case folded
+/// and the domain lowercased.
+///
+/// The local part is folded although it is case-sensitive by the standard, as mail providers treat it
+/// case-insensitively and accounts differing only in its case would be indistinguishable to people.
+#[must_use]
+pub fn email(email: &str) -> String {
+    let email = email.nfkc().collect::<String>();
+
+    match email.rsplit_once('@') {
+        Some((local, domain)) => format!("{}@{}", fold(local), domain.to_lowercase()),
+        None => fold(&email),
+    }
+}
+
+/// Returns whether the text has letters of more than one of the Latin, Greek and Cyrillic scripts,
+/// whose many look-alike letters let a username pass for another, e.g. `pаypal` with a Cyrillic `а`.
+#[must_use]
+pub fn mixes_confusable_scripts(text: &str) -> bool {
+    let mut scripts = text.nfkc().filter(|c| c.is_alphabetic()).filter_map(confusable_script);
+
+    scripts
+        .next()
+        .is_some_and(|first| scripts.any(|script| script != first))
+}
+
+/// A user whose canonical username or email is taken by an older user.
+#[derive(Debug)]
+pub struct Collision {
+    pub user_id: u64,
+    /// `username` or `email`.
+    pub field: &'static str,
+    /// The identity as the user registered it.
+    pub value: String,
+}
+
+/// Stores the canonical forms of users registered before they were stored, oldest user first,
+/// and returns the users whose canonical forms collide with an older user's.
+///
+/// Colliding forms are left missing until an operator resolves the collision, e.g. by deleting one of the users,
+/// and the user logs in with the colliding identity exactly as registered meanwhile. Forms of collisions resolved
+/// since the last call are stored, so the returned collisions are the ones still unresolved.
+///
+/// # Errors
+/// Returns error if any database operation fails, other than storing a colliding form.
+pub async fn backfill(database: &Database) -> Result<Vec<Collision>, SqlError> {
+    let mut collisions = Vec::new();
+
+    for user in database.list_users_without_canonical_identity().await? {
+        let stored = database.set_canonical_username(user.id, &username(&user.username)).await;
+        if let Some(collision) = collision(stored, user.id, "username", &user.username)? {
+            collisions.push(collision);
+        }
+
+        let stored = database.set_canonical_email(user.id, &email(&user.email)).await;
+        if let Some(collision) = collision(stored, user.id, "email", &user.email)? {
+            collisions.push(collision);
+        }
+    }
+
+    Ok(collisions)
+}
+
+/// Turns the result of storing a canonical form into the collision it reveals, if any.
+fn collision(
+    stored: Result<(), SqlError>,
+    user_id: u64,
+    field: &'static str,
+    value: &str,
+) -> Result<Option<Collision>, SqlError> {
+    match stored {
+        Ok(()) => Ok(None),
+        Err(SqlError::UniqueConstraintViolation) => Ok(Some(Collision {
+            user_id,
+            field,
+            value: value.to_owned(),
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+/// Scripts whose letters are commonly confused with each other.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ConfusableScript {
+    Latin,
+    Greek,
+    Cyrillic,
+}
+
+/// Returns which of the commonly confused scripts a letter is of, if any.
+fn confusable_script(letter: char) -> Option<ConfusableScript> {
+    match letter {
+        'A'..='Z'
+        | 'a'..='z'
+        | '\u{00C0}'..='\u{024F}'
+        | '\u{1E00}'..='\u{1EFF}'
+        | '\u{2C60}'..='\u{2C7F}'
+        | '\u{A720}'..='\u{A7FF}'
+        | '\u{AB30}'..='\u{AB6F}' => Some(ConfusableScript::Latin),
+        '\u{0370}'..='\u{03FF}' | '\u{1F00}'..='\u{1FFF}' => Some(ConfusableScript::Greek),
+        '\u{0400}'..='\u{052F}' | '\u{1C80}'..='\u{1C8F}' | '\u{2DE0}'..='\u{2DFF}' | '\u{A640}'..='\u{A69F}' => {
+            Some(ConfusableScript::Cyrillic)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the NFKC normalized, case folded form of text.
+///
+/// Folding is lowercasing, plus the foldings lowercasing does not apply: `ß` to `ss` and final `ς` to `σ`.
+/// The text is normalized again afterwards, as lowercasing can yield text that is not NFKC normalized.
+fn fold(text: &str) -> String {
+    text.nfkc()
+        .collect::<String>()
+        .to_lowercase()
+        .replace('ß', "ss")
+        .replace('ς', "σ")
+        .nfkc()
+        .collect()
+}
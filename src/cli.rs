@@ -0,0 +1,522 @@
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use chrono::Utc;
+use clap::{Args, Parser, Subcommand};
+
+use crate::api::{validate_password, RegisterBody, RegisterCredentials, CONFUSABLE_USERNAME_MESSAGE};
+use crate::accounts::AccountPurge;
+use crate::audit::{self, AuditEventKind, AuditOutcome, Requester};
+use crate::authorization::create_token;
+use crate::config::{Config, WeatherProvider};
+use crate::queries::SqlError;
+use crate::{backup, canonical, logging, password};
+
+/// Command-line arguments of the server binary.
+///
+/// Running the binary without a subcommand starts the server, same as `serve`.
+#[derive(Parser)]
+#[command(version, about = "Weather information API and its administration tools")]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Cli {
+    /// Reads the configuration file, installs the log subscriber and executes the parsed command.
+    ///
+    /// # Errors
+    /// Returns error if reading the configuration, installing the log subscriber
+    /// or executing the command fails.
+    pub async fn run(self) -> Result<(), anyhow::Error> {
+        let config = Config::read().context("could not read config")?;
+        let _log_guard = logging::init(&config.log, &config.telemetry)
+            .context("could not initialize logging")?;
+
+        self.execute(&config).await
+    }
+
+    /// Executes the parsed command with given configuration.
+    ///
+    /// # Errors
+    /// Returns error if executing the command fails.
+    pub async fn execute(self, config: &Config) -> Result<(), anyhow::Error> {
+        match self.command.unwrap_or(Command::Serve) {
+            Command::Serve => serve(config).await,
+            Command::Migrate => migrate(config).await,
+            Command::User(UserCommand::Create(args)) => create_user(config, args).await,
+            Command::User(UserCommand::List) => list_users(config).await,
+            Command::User(UserCommand::Delete { user_id, now }) => delete_user(config, user_id, now).await,
+            Command::User(UserCommand::Restore { user_id }) => restore_user(config, user_id).await,
+            Command::User(UserCommand::Disable { user_id }) => set_user_disabled(config, user_id, true).await,
+            Command::User(UserCommand::Enable { user_id }) => set_user_disabled(config, user_id, false).await,
+            Command::User(UserCommand::Purge) => purge_users(config).await,
+            Command::User(UserCommand::ResetPassword(args)) => reset_password(config, args).await,
+            Command::User(UserCommand::Collisions) => list_collisions(config).await,
+            Command::Token(TokenCommand::Issue { user_id }) => issue_token(config, user_id).await,
+            Command::Backup(BackupCommand::Restore { backup }) => restore_backup(config, &backup).await,
+            Command::Config(ConfigCommand::Check) => check_config(config),
+        }
+    }
+}
+
+/// Top level subcommands.
+#[derive(Subcommand)]
+enum Command {
+    /// Starts the server.
+    Serve,
+    /// Creates the database if needed and applies pending migrations.
+    Migrate,
+    /// Manages users.
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Manages JWT tokens.
+    #[command(subcommand)]
+    Token(TokenCommand),
+    /// Manages database backups.
+    #[command(subcommand)]
+    Backup(BackupCommand),
+    /// Inspects the configuration.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+/// Subcommands of `user`.
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Registers a user, applying the same credential restrictions as the API.
+    Create(CreateUserArgs),
+    /// Lists registered users with their status.
+    List,
+    /// Deletes the user with given ID, who can be restored until the retention period passes.
+    Delete {
+        /// ID of the user to delete.
+        user_id: u64,
+        /// Deletes the user permanently instead, with every row that belongs to them.
+        #[arg(long)]
+        now: bool,
+    },
+    /// Restores a deleted user that is not yet permanently deleted.
+    Restore {
+        /// ID of the user to restore.
+        user_id: u64,
+    },
+    /// Disables the user with given ID, refusing their logins and tokens.
+    Disable {
+        /// ID of the user to disable.
+        user_id: u64,
+    },
+    /// Enables a disabled user.
+    Enable {
+        /// ID of the user to enable.
+        user_id: u64,
+    },
+    /// Permanently deletes users deleted longer than the retention period ago.
+    Purge,
+    /// Replaces the password of a user.
+    ResetPassword(ResetPasswordArgs),
+    /// Lists users whose canonical username or email collides with an older user's.
+    Collisions,
+}
+
+/// Arguments of `user create`.
+#[derive(Args)]
+struct CreateUserArgs {
+    /// User's username.
+    #[arg(long)]
+    username: String,
+    /// User's email.
+    #[arg(long)]
+    email: String,
+    /// User's password. Read from standard input if omitted, so it does not end up in shell history.
+    #[arg(long)]
+    password: Option<String>,
+}
+
+/// Arguments of `user reset-password`.
+#[derive(Args)]
+struct ResetPasswordArgs {
+    /// Can either be user's username or email.
+    identifier: String,
+    /// New password. Read from standard input if omitted, so it does not end up in shell history.
+    #[arg(long)]
+    password: Option<String>,
+}
+
+/// Subcommands of `token`.
+#[derive(Subcommand)]
+enum TokenCommand {
+    /// Issues a JWT token for the user with given ID.
+    Issue {
+        /// ID of the user the token is issued for.
+        user_id: u64,
+    },
+}
+
+/// Subcommands of `backup`.
+#[derive(Subcommand)]
+enum BackupCommand {
+    /// Replaces the database with a backup, the server needs to be stopped.
+    Restore {
+        /// Path of the backup, or name of a backup in the backup directory.
+        backup: PathBuf,
+    },
+}
+
+/// Subcommands of `config`.
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Checks the configuration file and the required environment variables.
+    Check,
+}
+
+/// Starts the server and runs it until it stops.
+async fn serve(config: &Config) -> Result<(), anyhow::Error> {
+    let server = crate::setup(config)
+        .await
+        .context("server initialization failed")?;
+    server.serve().await.context("server execution interrupted")?;
+
+    Ok(())
+}
+
+/// Connects to the database, which applies pending migrations, and stores missing canonical usernames
+/// and emails, printing the users whose canonical forms collide with an older user's.
+async fn migrate(config: &Config) -> Result<(), anyhow::Error> {
+    let database = crate::database(config).await?;
+    let collisions = canonical::backfill(&database).await;
+    database.close().await;
+
+    let Ok(collisions) = collisions else {
+        bail!("storing canonical identities failed");
+    };
+    print_collisions(&collisions);
+
+    // The URL is not printed, as it may include credentials
+    if config.database.url.is_some() {
+        println!("database is up to date");
+    } else {
+        println!("database `{}` is up to date", config.database_name);
+    }
+
+    Ok(())
+}
+
+/// Stores the canonical forms of resolved collisions and prints the users whose canonical forms still collide
+/// with an older user's.
+async fn list_collisions(config: &Config) -> Result<(), anyhow::Error> {
+    let database = crate::database(config).await?;
+    let collisions = canonical::backfill(&database).await;
+    database.close().await;
+
+    let Ok(collisions) = collisions else {
+        bail!("storing canonical identities failed");
+    };
+    if collisions.is_empty() {
+        println!("no canonical identities collide");
+    }
+    print_collisions(&collisions);
+
+    Ok(())
+}
+
+/// Prints the users whose canonical forms collide with an older user's, one per line.
+fn print_collisions(collisions: &[canonical::Collision]) {
+    for collision in collisions {
+        println!(
+            "user {} collides on {} `{}`, it logs in with it only as registered until resolved",
+            collision.user_id, collision.field, collision.value
+        );
+    }
+}
+
+/// Validates the credentials and persists the user, recording the attempt in the audit log.
+async fn create_user(config: &Config, args: CreateUserArgs) -> Result<(), anyhow::Error> {
+    let password = password_or_stdin(args.password)?;
+    let body = RegisterBody {
+        username: args.username,
+        email: args.email,
+        password,
+    };
+    let credentials = match RegisterCredentials::try_from(body) {
+        Ok(c) => c,
+        Err(errors) => bail!(
+            "invalid credentials: {}",
+            errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    if config.accounts.reject_confusable_usernames && canonical::mixes_confusable_scripts(&credentials.username) {
+        bail!("invalid credentials: username: {CONFUSABLE_USERNAME_MESSAGE}");
+    }
+
+    let database = crate::database(config).await?;
+    let password_hash = password::hash(&credentials.password);
+    let result = database.register_user(
+        &credentials.username,
+        &credentials.email,
+        &password_hash,
+    )
+    .await;
+    let (outcome, user_id) = match result {
+        Ok(user_id) => (AuditOutcome::Success, Some(user_id)),
+        Err(_) => (AuditOutcome::Failure, None),
+    };
+    audit::record(&database, AuditEventKind::Registration, outcome, user_id, &Requester::default()).await;
+    database.close().await;
+
+    match result {
+        Ok(user_id) => println!("created user {user_id}"),
+        Err(SqlError::UniqueConstraintViolation) => {
+            bail!("a user with given credentials already exists")
+        }
+        Err(SqlError::Other | SqlError::Unsupported) => bail!("persisting the user failed"),
+    }
+
+    Ok(())
+}
+
+/// Prints registered users, one per line.
+async fn list_users(config: &Config) -> Result<(), anyhow::Error> {
+    let database = crate::database(config).await?;
+    let users = database.list_users().await;
+    database.close().await;
+
+    let Ok(users) = users else {
+        bail!("listing users failed");
+    };
+
+    println!("{:>8}  {:<24}  {:<8}  email", "id", "username", "status");
+    for user in users {
+        let status = if user.deleted_at.is_some() {
+            "deleted"
+        } else if user.disabled_at.is_some() {
+            "disabled"
+        } else {
+            "active"
+        };
+        println!("{:>8}  {:<24}  {:<8}  {}", user.id, user.username, status, user.email);
+    }
+
+    Ok(())
+}
+
+/// Marks the user with given ID as deleted, or deletes them permanently if `now` is set.
+async fn delete_user(config: &Config, user_id: u64, now: bool) -> Result<(), anyhow::Error> {
+    let database = crate::database(config).await?;
+    let deleted = if now {
+        database.delete_user(user_id).await
+    } else {
+        match database.get_user_by_id(user_id).await {
+            // Deleting again would postpone the permanent deletion
+            Ok(Some(user)) if user.deleted_at.is_some() => Ok(true),
+            Ok(Some(_)) => database.set_user_deleted(user_id, Some(Utc::now().timestamp())).await,
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    };
+    let event = if now { AuditEventKind::Purge } else { AuditEventKind::Deletion };
+    let outcome = if matches!(deleted, Ok(true)) { AuditOutcome::Success } else { AuditOutcome::Failure };
+    audit::record(&database, event, outcome, Some(user_id), &Requester::default()).await;
+    database.close().await;
+
+    match deleted {
+        Ok(true) if now => println!("permanently deleted user {user_id}"),
+        Ok(true) => println!(
+            "deleted user {user_id}, permanently deleted after {} days",
+            config.accounts.deletion_retention_days
+        ),
+        Ok(false) => bail!("no user with ID {user_id}"),
+        Err(_) => bail!("deleting the user failed"),
+    }
+
+    Ok(())
+}
+
+/// Clears the deletion of the user with given ID.
+async fn restore_user(config: &Config, user_id: u64) -> Result<(), anyhow::Error> {
+    let database = crate::database(config).await?;
+    let restored = database.set_user_deleted(user_id, None).await;
+    let outcome = if matches!(restored, Ok(true)) { AuditOutcome::Success } else { AuditOutcome::Failure };
+    audit::record(&database, AuditEventKind::Restoration, outcome, Some(user_id), &Requester::default()).await;
+    database.close().await;
+
+    match restored {
+        Ok(true) => println!("restored user {user_id}"),
+        Ok(false) => bail!("no user with ID {user_id}"),
+        Err(_) => bail!("restoring the user failed"),
+    }
+
+    Ok(())
+}
+
+/// Disables or enables the user with given ID.
+async fn set_user_disabled(config: &Config, user_id: u64, disabled: bool) -> Result<(), anyhow::Error> {
+    let database = crate::database(config).await?;
+    let disabled_at = disabled.then(|| Utc::now().timestamp());
+    let updated = database.set_user_disabled(user_id, disabled_at).await;
+    let event = if disabled { AuditEventKind::Disabling } else { AuditEventKind::Enabling };
+    let outcome = if matches!(updated, Ok(true)) { AuditOutcome::Success } else { AuditOutcome::Failure };
+    audit::record(&database, event, outcome, Some(user_id), &Requester::default()).await;
+    database.close().await;
+
+    match updated {
+        Ok(true) if disabled => println!("disabled user {user_id}"),
+        Ok(true) => println!("enabled user {user_id}"),
+        Ok(false) => bail!("no user with ID {user_id} that is not deleted"),
+        Err(_) => bail!("updating the user failed"),
+    }
+
+    Ok(())
+}
+
+/// Permanently deletes the users past the retention period.
+async fn purge_users(config: &Config) -> Result<(), anyhow::Error> {
+    let database = crate::database(config).await?;
+    let purged = AccountPurge::new(&config.accounts, database.clone()).purge().await;
+    database.close().await;
+
+    match purged {
+        Ok(purged) => println!("permanently deleted {purged} users"),
+        Err(_) => bail!("purging deleted users failed"),
+    }
+
+    Ok(())
+}
+
+/// Validates and hashes the new password and replaces the user's password with it, recording the reset
+/// in the audit log.
+async fn reset_password(config: &Config, args: ResetPasswordArgs) -> Result<(), anyhow::Error> {
+    let password = password_or_stdin(args.password)?;
+    if let Err(e) = validate_password(&password) {
+        bail!("invalid password: {e}");
+    }
+
+    let database = crate::database(config).await?;
+    let password_hash = password::hash(&password);
+    let updated = database.update_password_by_username_or_email(
+        &args.identifier,
+        &args.identifier,
+        &password_hash,
+    )
+    .await;
+    let (outcome, user_id) = match updated {
+        Ok(Some(user_id)) => (AuditOutcome::Success, Some(user_id)),
+        Ok(None) | Err(_) => (AuditOutcome::Failure, None),
+    };
+    audit::record(&database, AuditEventKind::PasswordReset, outcome, user_id, &Requester::default()).await;
+    database.close().await;
+
+    match updated {
+        Ok(Some(_)) => println!("password of `{}` is reset", args.identifier),
+        Ok(None) => bail!("no user with username or email `{}`", args.identifier),
+        Err(_) => bail!("resetting the password failed"),
+    }
+
+    Ok(())
+}
+
+/// Issues a token for an existing user and prints it, recording the issuance in the audit log.
+async fn issue_token(config: &Config, user_id: u64) -> Result<(), anyhow::Error> {
+    let database = crate::database(config).await?;
+    let user = database.get_user_by_id(user_id).await;
+
+    match user {
+        Ok(Some(user)) if user.is_active() => {}
+        Ok(Some(_)) => {
+            database.close().await;
+            bail!("user {user_id} is disabled or deleted")
+        }
+        Ok(None) => {
+            database.close().await;
+            bail!("no user with ID {user_id}")
+        }
+        Err(_) => {
+            database.close().await;
+            bail!("looking up the user failed")
+        }
+    }
+
+    let token = create_token(user_id);
+    let outcome = if token.is_ok() { AuditOutcome::Success } else { AuditOutcome::Failure };
+    audit::record(&database, AuditEventKind::TokenIssuance, outcome, Some(user_id), &Requester::default()).await;
+    database.close().await;
+
+    let token = token.context("token creation failed")?;
+    println!("{token}");
+
+    Ok(())
+}
+
+/// Validates the backup and swaps it in place of the database.
+async fn restore_backup(config: &Config, backup: &Path) -> Result<(), anyhow::Error> {
+    let source = if backup.exists() {
+        backup.to_owned()
+    } else {
+        config.backup.directory.join(backup)
+    };
+
+    let restored = backup::restore(config, &source)
+        .await
+        .with_context(|| format!("restoring `{}` failed", source.display()))?;
+
+    println!("database is restored from `{}`", source.display());
+    if let Some(previous) = restored.previous {
+        println!("replaced database is kept at `{}`", previous.display());
+    }
+    if restored.pending_migrations > 0 {
+        println!("{} migrations will be applied on next start", restored.pending_migrations);
+    }
+
+    Ok(())
+}
+
+/// Reports the configuration, failing if any required environment variable is missing.
+///
+/// Reaching this function means the configuration file is already read and parsed successfully.
+fn check_config(config: &Config) -> Result<(), anyhow::Error> {
+    println!("port: {}", config.port);
+    println!("database_name: {}", config.database_name);
+
+    let mut required = vec!["JWT_SECRET"];
+    if config
+        .upstream
+        .weather
+        .iter()
+        .any(|entry| entry.provider == WeatherProvider::Weatherapi)
+    {
+        required.push("WEATHER_API_KEY");
+    }
+
+    let missing = required
+        .into_iter()
+        .filter(|name| !matches!(std::env::var(name), Ok(value) if !value.is_empty()))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        bail!("missing environment variables: {}", missing.join(", "));
+    }
+
+    println!("configuration is valid");
+
+    Ok(())
+}
+
+/// Returns the given password, or reads one line from standard input if none is given.
+fn password_or_stdin(password: Option<String>) -> Result<String, anyhow::Error> {
+    if let Some(password) = password {
+        return Ok(password);
+    }
+
+    eprintln!("password:");
+    let mut line = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .context("could not read password from standard input")?;
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
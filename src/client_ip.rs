@@ -0,0 +1,178 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use ipnet::IpNet;
+use poem::http::HeaderMap;
+use poem::{Endpoint, Request, Response};
+
+use crate::config::{ForwardingHeader, ProxyConfig};
+
+/// Address of the client a request is made by, available to handlers through request data.
+///
+/// Missing if the connection has no IP address, e.g. it is made over a Unix socket.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+/// Reverse proxies whose forwarding headers are trusted.
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+    header: ForwardingHeader,
+}
+
+impl TrustedProxies {
+    /// Creates the list of trusted proxies from configuration.
+    #[must_use]
+    pub fn new(config: &ProxyConfig) -> Self {
+        Self {
+            networks: config.trusted.clone(),
+            header: config.header,
+        }
+    }
+
+    /// Returns whether the address belongs to a trusted proxy.
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(&ip))
+    }
+
+    /// Returns the address of the client that made a request received from given peer.
+    ///
+    /// Only the configured header is used, and only if the peer is a trusted proxy, as proxies pass other
+    /// forwarding headers sent by clients through. The chain of forwarding addresses is walked from the nearest
+    /// hop, skipping trusted proxies, so clients can not spoof their address by sending the header themselves.
+    /// The walk stops at an unknown or obfuscated hop, returning the last known one.
+    #[must_use]
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let peer = peer.to_canonical();
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let chain = match self.header {
+            ForwardingHeader::Forwarded => forwarded(headers),
+            ForwardingHeader::XForwardedFor => x_forwarded_for(headers),
+            ForwardingHeader::XRealIp => x_real_ip(headers),
+        };
+        let Some(chain) = chain else {
+            return peer;
+        };
+
+        let mut client = peer;
+        for hop in chain.into_iter().rev() {
+            let Some(ip) = hop else { break };
+            client = ip;
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+/// Returns whether the address can not be geolocated: loopback, private (RFC 1918), shared (RFC 6598),
+/// link-local, unique local (RFC 4193), unspecified, broadcast or multicast.
+#[must_use]
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || (first == 100 && (64..128).contains(&second))
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// Middleware function that resolves the client address of the request and attaches it as `ClientIp`.
+///
+/// The address is also recorded to the `client_ip` field of the request span.
+///
+/// # Errors
+/// Never fails, errors of the inner endpoint are converted to responses.
+pub async fn assign_client_ip<E: Endpoint>(
+    next: Arc<E>,
+    mut request: Request,
+    proxies: Arc<TrustedProxies>,
+) -> poem::Result<Response> {
+    let peer = request.remote_addr().as_socket_addr().map(SocketAddr::ip);
+    let client_ip = peer.map(|peer| proxies.client_ip(peer, request.headers()));
+
+    if let Some(ip) = client_ip {
+        tracing::Span::current().record("client_ip", tracing::field::display(ip));
+    }
+    request.extensions_mut().insert(ClientIp(client_ip));
+
+    Ok(next.get_response(request).await)
+}
+
+/// Returns the `for` addresses of `Forwarded` headers, from the farthest hop to the nearest.
+///
+/// Hops without a `for` parameter or with an obfuscated one are `None`.
+fn forwarded(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let chain = header_elements(headers, "forwarded")
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, node)| parse_node(node))
+        })
+        .collect::<Vec<_>>();
+
+    (!chain.is_empty()).then_some(chain)
+}
+
+/// Returns the addresses of `X-Forwarded-For` headers, from the farthest hop to the nearest.
+fn x_forwarded_for(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let chain = header_elements(headers, "x-forwarded-for")
+        .map(parse_node)
+        .collect::<Vec<_>>();
+
+    (!chain.is_empty()).then_some(chain)
+}
+
+/// Returns the address of the `X-Real-IP` header as a single hop.
+fn x_real_ip(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let value = headers.get("x-real-ip")?.to_str().ok()?;
+
+    Some(vec![parse_node(value)])
+}
+
+/// Returns the comma separated elements of every header with given name, in order.
+fn header_elements<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|element| !element.is_empty())
+}
+
+/// Parses a node as written in forwarding headers: an address optionally quoted and with a port,
+/// IPv6 addresses with a port being enclosed in brackets.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Some(rest) = node.strip_prefix('[') {
+        let (address, _) = rest.split_once(']')?;
+        return address.parse::<IpAddr>().ok().map(|ip| ip.to_canonical());
+    }
+
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|address| address.ip()))
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
@@ -1,13 +1,53 @@
 use std::fs::File;
 use std::io::{BufReader, Read};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use ipnet::IpNet;
 
 /// Representation of server's configuration.
 #[derive(serde::Deserialize)]
 pub struct Config {
     /// Server's port.
     pub port: u16,
-    /// Database file name.
+    /// Database file name, used when `database.url` is not set.
     pub database_name: String,
+    /// Database location, connection pool and SQLite parameters.
+    #[serde(default)]
+    pub database: DatabaseConfig,
+    /// Seconds in-flight requests are given to complete after a shutdown is requested.
+    #[serde(default = "Config::default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// Readiness check parameters.
+    #[serde(default)]
+    pub readiness: ReadinessConfig,
+    /// Logging parameters.
+    #[serde(default)]
+    pub log: LogConfig,
+    /// OpenTelemetry trace export parameters.
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    /// Provider chains, timeouts, retries and circuit breakers of calls to foreign APIs.
+    #[serde(default)]
+    pub upstream: UpstreamConfig,
+    /// Weather alert evaluation and webhook delivery parameters.
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+    /// Live weather stream parameters.
+    #[serde(default)]
+    pub stream: StreamConfig,
+    /// Reverse proxies in front of the server.
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    /// Database backup parameters.
+    #[serde(default)]
+    pub backup: BackupConfig,
+    /// Retention of deleted accounts.
+    #[serde(default)]
+    pub accounts: AccountsConfig,
+    /// Location of clients with private addresses, for running the server locally.
+    #[serde(default)]
+    pub dev_location: Option<DevLocation>,
 }
 
 impl Config {
@@ -30,6 +70,481 @@ impl Config {
 
         toml::from_str(&content).map_err(Error::Parse)
     }
+
+    /// Returns the URL of the database.
+    ///
+    /// A configured path without a scheme is a SQLite database file,
+    /// the SQLite database named `database_name` is used if no URL is configured.
+    #[must_use]
+    pub fn database_url(&self) -> String {
+        match &self.database.url {
+            Some(url) if url.contains("://") || url.starts_with("sqlite:") => url.clone(),
+            Some(path) => format!("sqlite://{path}"),
+            None => format!("sqlite://database/{}.db", self.database_name),
+        }
+    }
+
+    /// Default value of `shutdown_timeout`, used when the configuration file omits it.
+    const fn default_shutdown_timeout() -> u64 {
+        30
+    }
+}
+
+/// Parameters of the readiness check, under the `[readiness]` table.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct ReadinessConfig {
+    /// Whether readiness checks also call the geolocation and weather APIs.
+    pub probe_upstream: bool,
+    /// Seconds the results of upstream probes are reused for, to spare API quotas.
+    pub upstream_cache_seconds: u64,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            probe_upstream: false,
+            upstream_cache_seconds: 60,
+        }
+    }
+}
+
+/// Parameters of the database, under the `[database]` table.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    /// URL of the database, `sqlite:` or `postgres:`, which selects the database backend,
+    /// or the path of a SQLite database file.
+    pub url: Option<String>,
+    /// Most connections the pool opens.
+    pub max_connections: u32,
+    /// Connections the pool keeps open even when idle.
+    pub min_connections: u32,
+    /// Seconds a query waits for a connection of the pool before failing.
+    pub acquire_timeout_seconds: u64,
+    /// Pragmas applied to every SQLite connection, under the `[database.sqlite]` table.
+    pub sqlite: SqliteConfig,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_seconds: 30,
+            sqlite: SqliteConfig::default(),
+        }
+    }
+}
+
+/// Pragmas applied to every SQLite connection, ignored for PostgreSQL.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct SqliteConfig {
+    /// Journal mode, `wal` lets readers proceed while a write is in progress.
+    pub journal_mode: SqliteJournalMode,
+    /// How often SQLite waits for writes to reach the disk.
+    pub synchronous: SqliteSynchronous,
+    /// Milliseconds a connection waits for a lock held by another connection before failing
+    /// with "database is locked".
+    pub busy_timeout_ms: u64,
+    /// Whether foreign key constraints are enforced, which deletions of users cascade through.
+    pub foreign_keys: bool,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Self {
+            journal_mode: SqliteJournalMode::Wal,
+            synchronous: SqliteSynchronous::Normal,
+            busy_timeout_ms: 5000,
+            foreign_keys: true,
+        }
+    }
+}
+
+/// Value of the `journal_mode` pragma.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SqliteJournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+impl From<SqliteJournalMode> for sqlx::sqlite::SqliteJournalMode {
+    fn from(mode: SqliteJournalMode) -> Self {
+        match mode {
+            SqliteJournalMode::Delete => Self::Delete,
+            SqliteJournalMode::Truncate => Self::Truncate,
+            SqliteJournalMode::Persist => Self::Persist,
+            SqliteJournalMode::Memory => Self::Memory,
+            SqliteJournalMode::Wal => Self::Wal,
+            SqliteJournalMode::Off => Self::Off,
+        }
+    }
+}
+
+/// Value of the `synchronous` pragma.
+///
+/// `normal` is safe from corruption in `wal` mode, though the last commits may be lost on power failure.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SqliteSynchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl From<SqliteSynchronous> for sqlx::sqlite::SqliteSynchronous {
+    fn from(synchronous: SqliteSynchronous) -> Self {
+        match synchronous {
+            SqliteSynchronous::Off => Self::Off,
+            SqliteSynchronous::Normal => Self::Normal,
+            SqliteSynchronous::Full => Self::Full,
+            SqliteSynchronous::Extra => Self::Extra,
+        }
+    }
+}
+
+/// Parameters of database backups, under the `[backup]` table.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct BackupConfig {
+    /// Directory backups are written to, created if it does not exist.
+    pub directory: PathBuf,
+    /// Whether the server takes backups periodically, besides the ones requested by administrators.
+    pub scheduled: bool,
+    /// Seconds between scheduled backups.
+    pub interval_seconds: u64,
+    /// Most backups kept in the directory, older ones are removed after a backup is taken.
+    pub retention: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("database/backups"),
+            scheduled: false,
+            interval_seconds: 86_400,
+            retention: 7,
+        }
+    }
+}
+
+/// Parameters of registration and deleted accounts, under the `[accounts]` table.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct AccountsConfig {
+    /// Days deleted users are kept for, so deletions can be undone, before they are permanently deleted.
+    pub deletion_retention_days: u32,
+    /// Seconds between checks for deleted users past the retention period.
+    pub purge_interval_seconds: u64,
+    /// Whether usernames mixing Latin, Greek and Cyrillic letters, which can pass for other usernames, are refused.
+    pub reject_confusable_usernames: bool,
+}
+
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        Self {
+            deletion_retention_days: 30,
+            purge_interval_seconds: 3_600,
+            reject_confusable_usernames: false,
+        }
+    }
+}
+
+/// Parameters of weather alerts, under the `[alerts]` table.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct AlertsConfig {
+    /// Whether alert rules are evaluated by the server.
+    pub enabled: bool,
+    /// Seconds between evaluations of alert rules.
+    pub poll_interval_seconds: u64,
+    /// Milliseconds to wait for a webhook to respond to a delivery.
+    pub delivery_timeout_ms: u64,
+    /// Retries of a failed delivery after the first attempt.
+    pub max_delivery_retries: u32,
+    /// Upper bound of the delay before the first delivery retry in milliseconds, doubled on every retry.
+    pub retry_base_delay_ms: u64,
+    /// Upper bound of any delivery retry delay in milliseconds.
+    pub retry_max_delay_ms: u64,
+    /// Whether webhooks may point to loopback, private and link-local addresses, e.g. for development.
+    pub allow_private_webhooks: bool,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            poll_interval_seconds: 300,
+            delivery_timeout_ms: 5_000,
+            max_delivery_retries: 4,
+            retry_base_delay_ms: 1_000,
+            retry_max_delay_ms: 60_000,
+            allow_private_webhooks: false,
+        }
+    }
+}
+
+/// Parameters of live weather streams, under the `[stream]` table.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct StreamConfig {
+    /// Seconds between calls for the weather of a location while it has subscribers.
+    pub poll_interval_seconds: u64,
+    /// Seconds between keep-alive comments sent to idle subscribers.
+    pub keep_alive_seconds: u64,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_seconds: 60,
+            keep_alive_seconds: 15,
+        }
+    }
+}
+
+/// Parameters of reverse proxies, under the `[proxy]` table.
+#[derive(serde::Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// Networks of the proxies whose forwarding header is trusted, e.g. `10.0.0.0/8`.
+    /// The header is ignored when empty.
+    pub trusted: Vec<IpNet>,
+    /// The forwarding header the proxies set, others are ignored as they may be sent by clients.
+    pub header: ForwardingHeader,
+}
+
+/// Header reverse proxies forward the client address in.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ForwardingHeader {
+    /// `Forwarded`, standardized by RFC 7239.
+    #[serde(rename = "forwarded")]
+    Forwarded,
+    /// `X-Forwarded-For`, set by most load balancers.
+    #[default]
+    #[serde(rename = "x-forwarded-for")]
+    XForwardedFor,
+    /// `X-Real-IP`, holding a single address.
+    #[serde(rename = "x-real-ip")]
+    XRealIp,
+}
+
+/// Location used for clients with loopback, private or unique local addresses, under the `[dev_location]` table.
+///
+/// Such addresses can not be geolocated, so requests from them fail unless it is configured.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(untagged)]
+pub enum DevLocation {
+    /// Fixed coordinates, used without calling the geolocation API.
+    Coordinates { latitude: f64, longitude: f64 },
+    /// Public address geolocated in place of the private one.
+    Ip { ip: IpAddr },
+}
+
+/// Parameters of logging, under the `[log]` table.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Output format of log lines.
+    pub format: LogFormat,
+    /// Filter directives in `tracing_subscriber::EnvFilter` syntax, e.g. `info,sqlx=warn`.
+    ///
+    /// Overridden by the `RUST_LOG` environment variable if it is set.
+    pub level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Pretty,
+            level: "info".to_owned(),
+        }
+    }
+}
+
+/// Output format of log lines.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines.
+    Pretty,
+    /// One JSON object per line, including the fields of enclosing spans.
+    Json,
+}
+
+/// Parameters of OpenTelemetry trace export, under the `[telemetry]` table.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    /// URL of the OTLP/HTTP traces endpoint of a collector, e.g. `http://localhost:4318/v1/traces`.
+    ///
+    /// Spans are not exported and trace context is not propagated if it is not set.
+    pub otlp_endpoint: Option<String>,
+    /// Service name attached to exported spans.
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "weather_server_demo".to_owned(),
+        }
+    }
+}
+
+/// Parameters of calls to foreign APIs, under the `[upstream]` table.
+///
+/// Providers of each chain are tried in order until one of them succeeds.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct UpstreamConfig {
+    /// Geolocation providers, under `[[upstream.geolocation]]` entries.
+    pub geolocation: Vec<ProviderConfig<GeolocationProvider>>,
+    /// Weather providers, under `[[upstream.weather]]` entries.
+    pub weather: Vec<ProviderConfig<WeatherProvider>>,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            geolocation: vec![ProviderConfig::new(GeolocationProvider::Ipapi)],
+            weather: vec![ProviderConfig::new(WeatherProvider::Weatherapi)],
+        }
+    }
+}
+
+/// A provider in a chain and the policy calls to it follow.
+#[derive(serde::Deserialize, Clone)]
+pub struct ProviderConfig<P> {
+    /// Which API the provider is.
+    pub provider: P,
+    /// Base URL of the API, the provider's public URL is used if omitted.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Timeouts, retries and circuit breaker parameters, given in the same entry.
+    #[serde(flatten)]
+    pub policy: ProviderPolicy,
+}
+
+impl<P> ProviderConfig<P> {
+    /// Creates a provider entry with public URL and default policy.
+    pub fn new(provider: P) -> Self {
+        Self {
+            provider,
+            host: None,
+            policy: ProviderPolicy::default(),
+        }
+    }
+}
+
+/// Supported geolocation APIs.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GeolocationProvider {
+    /// `ipapi.co`.
+    #[serde(rename = "ipapi")]
+    Ipapi,
+    /// `ip-api.com`, only to be configured explicitly, as its free tier is served over plain HTTP
+    /// and restricted to non-commercial use.
+    #[serde(rename = "ip-api")]
+    IpApi,
+}
+
+impl GeolocationProvider {
+    /// Returns the name of the provider, as written in configuration.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Ipapi => "ipapi",
+            Self::IpApi => "ip-api",
+        }
+    }
+
+    /// Returns the public URL of the provider.
+    #[must_use]
+    pub const fn default_host(self) -> &'static str {
+        match self {
+            Self::Ipapi => "https://ipapi.co",
+            // Free tier of ip-api.com is only served over plain HTTP
+            Self::IpApi => "http://ip-api.com",
+        }
+    }
+}
+
+/// Supported weather APIs.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WeatherProvider {
+    /// `weatherapi.com`, requires `WEATHER_API_KEY` environment variable.
+    #[serde(rename = "weatherapi")]
+    Weatherapi,
+    /// `open-meteo.com`.
+    #[serde(rename = "open-meteo")]
+    OpenMeteo,
+}
+
+impl WeatherProvider {
+    /// Returns the name of the provider, as written in configuration.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Weatherapi => "weatherapi",
+            Self::OpenMeteo => "open-meteo",
+        }
+    }
+
+    /// Returns the public URL of the provider.
+    #[must_use]
+    pub const fn default_host(self) -> &'static str {
+        match self {
+            Self::Weatherapi => "https://api.weatherapi.com",
+            Self::OpenMeteo => "https://api.open-meteo.com",
+        }
+    }
+}
+
+/// Timeouts, retries and circuit breaker parameters of a foreign API.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct ProviderPolicy {
+    /// Milliseconds to wait for a connection to be established.
+    pub connect_timeout_ms: u64,
+    /// Milliseconds to wait for each read of the response.
+    pub read_timeout_ms: u64,
+    /// Retries made after a failed attempt. Only connection failures, timeouts,
+    /// `5xx` and `429` responses are retried.
+    pub max_retries: u32,
+    /// Upper bound of the delay before the first retry in milliseconds, doubled on every retry.
+    pub retry_base_delay_ms: u64,
+    /// Upper bound of any retry delay in milliseconds.
+    /// A `Retry-After` longer than this stops retrying.
+    pub retry_max_delay_ms: u64,
+    /// Consecutive failed calls that open the circuit.
+    pub failure_threshold: u32,
+    /// Seconds the circuit stays open before a trial call is let through.
+    pub open_seconds: u64,
+}
+
+impl Default for ProviderPolicy {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: 2_000,
+            read_timeout_ms: 5_000,
+            max_retries: 2,
+            retry_base_delay_ms: 200,
+            retry_max_delay_ms: 2_000,
+            failure_threshold: 5,
+            open_seconds: 30,
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
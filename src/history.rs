@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+
+use chrono::{NaiveDate, NaiveTime, Utc};
+
+use crate::http_client::{self, HistoricalObservation, HttpClient, Sourced, WeatherApiResponse};
+use crate::queries::{Database, GridCell, Observation};
+
+/// Most days a history query can span, inclusive.
+pub const MAX_DAYS: u32 = 7;
+
+/// An observation in a history, with whether it was served from the database.
+pub struct HistoryEntry {
+    pub observation: Observation,
+    pub stored: bool,
+}
+
+/// Stores the current weather reported for given coordinates as the observation of its hour.
+///
+/// Failures are only logged, so they do not fail the request that fetched the weather.
+pub async fn record_current(
+    database: &Database,
+    latitude: f64,
+    longitude: f64,
+    response: &Sourced<WeatherApiResponse>,
+) {
+    let observed_at = response
+        .last_updated()
+        .map_or_else(|| Utc::now().timestamp(), |t| t.timestamp());
+    let current = &response.current;
+    let observation = Observation {
+        observed_at: start_of_hour(observed_at),
+        temperature_c: current.temp_c,
+        feels_like_c: Some(current.feelslike_c),
+        condition: Some(current.condition.text.clone()),
+        condition_code: current.condition.code,
+        wind_kph: current.wind_kph,
+        humidity: current.humidity,
+        pressure_mb: current.pressure_mb,
+        precip_mm: current.precip_mm,
+        cloud: current.cloud,
+        uv: current.uv,
+        provider: response.provider.to_owned(),
+    };
+
+    let grid = GridCell::containing(latitude, longitude);
+    if database.store_observations(grid, &[observation]).await.is_err() {
+        tracing::warn!("storing the observation failed");
+    }
+}
+
+/// Returns the hourly observations at given coordinates of the days from `from` to `to`, inclusive, in UTC.
+///
+/// Observations are served from the database for the days it has every past hour of.
+/// The other days are fetched from the weather providers and stored, stored observations
+/// take precedence over fetched ones of the same hour.
+///
+/// # Errors
+/// Returns the error of the weather providers if days need to be fetched and every provider fails.
+pub async fn observations(
+    database: &Database,
+    http_client: &HttpClient,
+    latitude: f64,
+    longitude: f64,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<HistoryEntry>, http_client::Error> {
+    let grid = GridCell::containing(latitude, longitude);
+    let (start, end) = (day_start(from), day_start(to) + 86_400);
+
+    let stored = database.list_observations(grid, start, end)
+        .await
+        .unwrap_or_else(|_| {
+            tracing::warn!("reading stored observations failed, fetching every day");
+            Vec::new()
+        });
+
+    let now = Utc::now().timestamp();
+    let missing_days = from
+        .iter_days()
+        .take_while(|day| *day <= to)
+        .filter(|day| {
+            let day_start = day_start(*day);
+            let expected_hours = ((now - day_start) / 3600 + 1).clamp(0, 24);
+            let stored_hours = stored
+                .iter()
+                .filter(|o| (day_start..day_start + 86_400).contains(&o.observed_at))
+                .count() as i64;
+
+            stored_hours < expected_hours
+        })
+        .collect::<Vec<_>>();
+
+    let mut entries = BTreeMap::new();
+    if let (Some(&first), Some(&last)) = (missing_days.first(), missing_days.last()) {
+        let fetched = http_client
+            .get_history_for_coordinates(latitude, longitude, first, last)
+            .await?;
+        let fetched = fetched
+            .value
+            .iter()
+            .map(|o| observation(o, fetched.provider))
+            .collect::<Vec<_>>();
+
+        if database.store_observations(grid, &fetched).await.is_err() {
+            tracing::warn!("storing fetched observations failed");
+        }
+
+        for observation in fetched {
+            entries.insert(observation.observed_at, HistoryEntry { observation, stored: false });
+        }
+    }
+
+    for observation in stored {
+        entries.insert(observation.observed_at, HistoryEntry { observation, stored: true });
+    }
+
+    Ok(entries.into_values().collect())
+}
+
+/// Converts an observation fetched from given provider to its stored form.
+fn observation(fetched: &HistoricalObservation, provider: &str) -> Observation {
+    Observation {
+        observed_at: start_of_hour(fetched.time_epoch),
+        temperature_c: fetched.temp_c,
+        feels_like_c: fetched.feelslike_c,
+        condition: fetched.condition.clone(),
+        condition_code: fetched.condition_code,
+        wind_kph: fetched.wind_kph,
+        humidity: fetched.humidity,
+        pressure_mb: fetched.pressure_mb,
+        precip_mm: fetched.precip_mm,
+        cloud: fetched.cloud,
+        uv: fetched.uv,
+        provider: provider.to_owned(),
+    }
+}
+
+/// Returns the UNIX time of the start of the day in UTC.
+fn day_start(day: NaiveDate) -> i64 {
+    day.and_time(NaiveTime::MIN).and_utc().timestamp()
+}
+
+/// Truncates a UNIX time to the start of its hour.
+const fn start_of_hour(time: i64) -> i64 {
+    time - time.rem_euclid(3600)
+}
@@ -1,33 +1,41 @@
 use std::collections::HashMap;
 use std::env::VarError;
+use std::ops::Deref;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, Offset, Utc};
 use reqwest::StatusCode;
 
+use crate::config::{
+    GeolocationProvider, ProviderConfig, ProviderPolicy, UpstreamConfig, WeatherProvider,
+};
+use crate::metrics::Metrics;
+use crate::resilience::{self, CircuitBreaker, CircuitState, RetryPolicy};
+use crate::telemetry;
+
 /// Wrapper for foreign API accesses.
+///
+/// Holds an ordered chain of providers for geolocation and for weather,
+/// each provider having its own connection pool, timeouts, retry policy and circuit breaker.
+/// Calls fall over to the next provider of the chain on any error.
 pub struct HttpClient {
-    client: reqwest::Client,
-    weather_api_key: String,
-    geolocation_api_host: String,
-    weather_api_host: String,
+    weather_api_key: Option<String>,
+    geolocation: Vec<Upstream<GeolocationProvider>>,
+    weather: Vec<Upstream<WeatherProvider>>,
 }
 
 impl HttpClient {
-    /// Default geolocation API hostname.
-    const GEOLOCATION_API_HOST: &'static str = "https://ipapi.co";
-
-    /// Default weather API hostname.
-    const WEATHER_API_HOST: &'static str = "https://api.weatherapi.com";
-
-    /// Creates a `HTTPClient` instance with default hostnames.
+    /// Creates a `HTTPClient` instance with default providers and policies.
     /// 
     /// # Errors
     /// Returns an error if environment variable `WEATHER_API_KEY` is not set.
-    pub fn new() -> Result<Self, VarError> {
-        Self::new_with_hosts(Self::GEOLOCATION_API_HOST, Self::WEATHER_API_HOST)
+    pub fn new() -> Result<Self, CreationError> {
+        Self::from_config(&UpstreamConfig::default())
     }
 
-    /// Creates a `HTTPClient` instance with given foreign API hostnames.
+    /// Creates a `HTTPClient` instance with default providers, `ipapi` and `weatherapi`,
+    /// at given foreign API hostnames and with default policies.
     /// 
     /// Used in testing to enable the ability to direct the calls to a local endpoint.
     /// 
@@ -36,137 +44,1344 @@ impl HttpClient {
     pub fn new_with_hosts(
         geolocation_api_host: &str,
         weather_api_host: &str,
-    ) -> Result<Self, VarError> {
-        let weather_api_key = std::env::var("WEATHER_API_KEY")?;
+    ) -> Result<Self, CreationError> {
+        let config = UpstreamConfig {
+            geolocation: vec![ProviderConfig {
+                host: Some(geolocation_api_host.to_owned()),
+                ..ProviderConfig::new(GeolocationProvider::Ipapi)
+            }],
+            weather: vec![ProviderConfig {
+                host: Some(weather_api_host.to_owned()),
+                ..ProviderConfig::new(WeatherProvider::Weatherapi)
+            }],
+        };
+
+        Self::from_config(&config)
+    }
+
+    /// Creates a `HTTPClient` instance with given provider chains.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - A chain is empty
+    /// - `weatherapi` is in the weather chain and environment variable `WEATHER_API_KEY` is not set
+    /// - The underlying clients can not be created
+    pub fn from_config(config: &UpstreamConfig) -> Result<Self, CreationError> {
+        if config.geolocation.is_empty() || config.weather.is_empty() {
+            return Err(CreationError::EmptyChain);
+        }
+
+        let weather_api_key = if config
+            .weather
+            .iter()
+            .any(|p| p.provider == WeatherProvider::Weatherapi)
+        {
+            Some(std::env::var("WEATHER_API_KEY")?)
+        } else {
+            None
+        };
+
+        let geolocation = config
+            .geolocation
+            .iter()
+            .map(|p| Upstream::new(p.provider, p.provider.name(), p.provider.default_host(), p))
+            .collect::<Result<_, _>>()?;
+        let weather = config
+            .weather
+            .iter()
+            .map(|p| Upstream::new(p.provider, p.provider.name(), p.provider.default_host(), p))
+            .collect::<Result<_, _>>()?;
+
         let client = Self {
-            client: reqwest::Client::default(),
             weather_api_key,
-            geolocation_api_host: geolocation_api_host.to_owned(),
-            weather_api_host: weather_api_host.to_owned(),
+            geolocation,
+            weather,
         };
 
         Ok(client)
     }
 
-    /// Makes a call to the geolocation API, parses the response and returns the coordinates.
-    /// 
-    /// Expected response format is `LATITUDE,LONGITUDE`.
+    /// Returns the state of the circuit breaker of each provider, by provider name.
+    #[must_use]
+    pub fn circuit_states(&self) -> Vec<(&'static str, CircuitState)> {
+        let geolocation = self.geolocation.iter().map(|u| (u.name, u.breaker.state()));
+        let weather = self.weather.iter().map(|u| (u.name, u.breaker.state()));
+
+        geolocation.chain(weather).collect()
+    }
+
+    /// Makes a call to the geolocation providers in order, parses the response
+    /// and returns the coordinates with the provider that served them.
     /// 
     /// # Errors
-    /// Returns an error if:
-    /// - Call to endpoint fails
+    /// Returns the error of the last provider if every provider fails. A provider fails if:
+    /// - Circuit of the provider is open
+    /// - Call to endpoint fails after retries
     /// - Response status code is not `200 Success`
-    /// - The response does not include a body
+    /// - The response reports an error in its body
     /// - Response has unexpected format
-    pub async fn get_coordinates_for_ip(&self, ip: &str) -> Result<GeolocationApiResponse, Error> {
-        let url = format!("{}/{ip}/latlong/", self.geolocation_api_host);
+    ///
+    /// # Panics
+    /// Never panics, chains are checked to be non-empty on creation.
+    #[tracing::instrument(skip(self), fields(otel.kind = "client"))]
+    pub async fn get_coordinates_for_ip(
+        &self,
+        ip: &str,
+    ) -> Result<Sourced<GeolocationApiResponse>, Error> {
+        let mut last_error = None;
+        for upstream in &self.geolocation {
+            let started = Instant::now();
+            let result = match upstream.provider {
+                GeolocationProvider::Ipapi => self.request_ipapi(upstream, ip).await,
+                GeolocationProvider::IpApi => self.request_ip_api(upstream, ip).await,
+            };
+            Metrics::get().observe_upstream_call(upstream.name, started, result.as_ref().err().map(Error::kind));
+
+            match result {
+                Ok(value) => return Ok(Sourced { provider: upstream.name, value }),
+                Err(e) => {
+                    tracing::warn!(provider = upstream.name, error = %e, details = ?e, "geolocation API call failed");
+                    last_error = Some(e);
+                }
+            }
+        }
 
-        let response = self
-            .client
-            .get(url)
-            .send()
+        Err(last_error.expect("provider chains are never empty"))
+    }
+
+    /// Makes a call to the weather providers in order and returns the response
+    /// with the provider that served it.
+    ///
+    /// The API key is sent as a query parameter, so the request URL is never logged.
+    /// 
+    /// # Errors
+    /// Returns the error of the last provider if every provider fails. A provider fails if:
+    /// - Circuit of the provider is open
+    /// - Call to endpoint fails after retries
+    /// - Response status code is not `200 Success`
+    /// - The body is not in expected format
+    ///
+    /// # Panics
+    /// Never panics, chains are checked to be non-empty on creation.
+    pub async fn get_weather_for_coordinates(
+        &self,
+        latitude: f64,
+        longitude: f64,
+    ) -> Result<Sourced<WeatherApiResponse>, Error> {
+        self.get_localized_weather_for_coordinates(latitude, longitude, None)
             .await
-            .map_err(|_| Error::RequestFailed)?;
+    }
+
+    /// Same as `get_weather_for_coordinates`, with condition text in given language.
+    ///
+    /// `lang` is a language code such as `fr` or `zh_tw`.
+    /// Providers that do not support languages, such as `open-meteo`, return English text.
+    ///
+    /// # Errors
+    /// Returns the error of the last provider if every provider fails,
+    /// see `get_weather_for_coordinates`.
+    ///
+    /// # Panics
+    /// Never panics, chains are checked to be non-empty on creation.
+    #[tracing::instrument(skip(self), fields(otel.kind = "client"))]
+    pub async fn get_localized_weather_for_coordinates(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        lang: Option<&str>,
+    ) -> Result<Sourced<WeatherApiResponse>, Error> {
+        let mut last_error = None;
+        for upstream in &self.weather {
+            let started = Instant::now();
+            let result = match upstream.provider {
+                WeatherProvider::Weatherapi => {
+                    self.request_weatherapi(upstream, latitude, longitude, lang).await
+                }
+                WeatherProvider::OpenMeteo => {
+                    self.request_open_meteo(upstream, latitude, longitude).await
+                }
+            };
+            Metrics::get().observe_upstream_call(upstream.name, started, result.as_ref().err().map(Error::kind));
+
+            match result {
+                Ok(value) => return Ok(Sourced { provider: upstream.name, value }),
+                Err(e) => {
+                    tracing::warn!(provider = upstream.name, error = %e, details = ?e, "weather API call failed");
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("provider chains are never empty"))
+    }
+
+    /// Makes a call to the weather providers in order and returns the hourly precipitation forecast
+    /// for the next `hours` hours, starting with the current hour, with the provider that served it.
+    ///
+    /// # Errors
+    /// Returns the error of the last provider if every provider fails,
+    /// see `get_weather_for_coordinates`.
+    ///
+    /// # Panics
+    /// Never panics, chains are checked to be non-empty on creation.
+    #[tracing::instrument(skip(self), fields(otel.kind = "client"))]
+    pub async fn get_forecast_for_coordinates(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        hours: u8,
+    ) -> Result<Sourced<Forecast>, Error> {
+        let mut last_error = None;
+        for upstream in &self.weather {
+            let started = Instant::now();
+            let result = match upstream.provider {
+                WeatherProvider::Weatherapi => {
+                    self.request_weatherapi_forecast(upstream, latitude, longitude, hours).await
+                }
+                WeatherProvider::OpenMeteo => {
+                    self.request_open_meteo_forecast(upstream, latitude, longitude, hours).await
+                }
+            };
+            Metrics::get().observe_upstream_call(upstream.name, started, result.as_ref().err().map(Error::kind));
+
+            match result {
+                Ok(value) => return Ok(Sourced { provider: upstream.name, value }),
+                Err(e) => {
+                    tracing::warn!(provider = upstream.name, error = %e, details = ?e, "forecast API call failed");
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("provider chains are never empty"))
+    }
+
+    /// Makes a call to the weather providers in order and returns the hourly observations
+    /// of the days from `from` to `to`, inclusive, in UTC, with the provider that served them.
+    ///
+    /// Hours the provider has no observation for are missing.
+    ///
+    /// # Errors
+    /// Returns the error of the last provider if every provider fails,
+    /// see `get_weather_for_coordinates`.
+    ///
+    /// # Panics
+    /// Never panics, chains are checked to be non-empty on creation.
+    #[tracing::instrument(skip(self), fields(otel.kind = "client"))]
+    pub async fn get_history_for_coordinates(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Sourced<Vec<HistoricalObservation>>, Error> {
+        let mut last_error = None;
+        for upstream in &self.weather {
+            let started = Instant::now();
+            let result = match upstream.provider {
+                WeatherProvider::Weatherapi => {
+                    self.request_weatherapi_history(upstream, latitude, longitude, from, to).await
+                }
+                WeatherProvider::OpenMeteo => {
+                    self.request_open_meteo_history(upstream, latitude, longitude, from, to).await
+                }
+            };
+            Metrics::get().observe_upstream_call(upstream.name, started, result.as_ref().err().map(Error::kind));
 
-        let status_code = response.status();
-        if status_code != StatusCode::OK {
-            return Err(Error::ApiInternalError(format!(
-                "API returned {status_code}"
-            )));
+            match result {
+                Ok(value) => return Ok(Sourced { provider: upstream.name, value }),
+                Err(e) => {
+                    tracing::warn!(provider = upstream.name, error = %e, details = ?e, "history API call failed");
+                    last_error = Some(e);
+                }
+            }
         }
 
-        let response = response.text().await.map_err(|_| Error::RequestFailed)?;
-        let coordinate = Coordinate::from_str(&response)?;
+        Err(last_error.expect("provider chains are never empty"))
+    }
+
+    /// Calls `ipapi.co`, whose response format is `LATITUDE,LONGITUDE`, so no place names are returned.
+    async fn request_ipapi(
+        &self,
+        upstream: &Upstream<GeolocationProvider>,
+        ip: &str,
+    ) -> Result<GeolocationApiResponse, Error> {
+        let url = format!("{}/{ip}/latlong/", upstream.host);
+
+        let response = upstream
+            .send(|client| client.get(&url).headers(telemetry::trace_headers()))
+            .await?;
+
+        let body = read_body(response, ErrorDetails::from_ipapi).await?;
+        let coordinate = Coordinate::from_str(body.trim())?;
 
         let response = GeolocationApiResponse {
             latitude: coordinate.latitude,
             longitude: coordinate.longitude,
+            ..GeolocationApiResponse::default()
         };
 
         Ok(response)
     }
 
-    /// Makes a call to weather API and returns the response.
-    /// 
-    /// # Errors
-    /// Will fail if:
-    /// - Call to endpoint fails
-    /// - The body is not in expected format
-    pub async fn get_weather_for_coordinates(
+    /// Calls `ip-api.com`, which reports failures in the body with a `200 Success`.
+    async fn request_ip_api(
+        &self,
+        upstream: &Upstream<GeolocationProvider>,
+        ip: &str,
+    ) -> Result<GeolocationApiResponse, Error> {
+        let url = format!("{}/json/{ip}", upstream.host);
+
+        let response = upstream
+            .send(|client| {
+                client
+                    .get(&url)
+                    .query(&[("fields", "status,message,lat,lon,city,regionName,country,timezone")])
+                    .headers(telemetry::trace_headers())
+            })
+            .await?;
+
+        let body = read_body(response, ErrorDetails::none).await?;
+        let response = parse_json::<IpApiResponse>(&body)?;
+
+        match response {
+            IpApiResponse {
+                lat: Some(latitude),
+                lon: Some(longitude),
+                ..
+            } if response.status == "success" => Ok(GeolocationApiResponse {
+                latitude,
+                longitude,
+                city: response.city,
+                region: response.region_name,
+                country: response.country,
+                timezone: response.timezone,
+            }),
+            _ => Err(Error::ProviderError {
+                code: Some(response.status),
+                message: response.message,
+            }),
+        }
+    }
+
+    /// Calls `weatherapi.com`.
+    async fn request_weatherapi(
         &self,
+        upstream: &Upstream<WeatherProvider>,
         latitude: f64,
         longitude: f64,
+        lang: Option<&str>,
     ) -> Result<WeatherApiResponse, Error> {
-        let url = format!("{}/v1/current.json", self.weather_api_host);
+        let url = format!("{}/v1/current.json", upstream.host);
 
         let mut query_parameters = HashMap::new();
         let location_query = format!("{latitude},{longitude}");
         query_parameters.insert("q", location_query);
-        query_parameters.insert("key", self.weather_api_key.clone());
+        query_parameters.insert("aqi", "yes".to_owned());
+        if let Some(lang) = lang {
+            query_parameters.insert("lang", lang.to_owned());
+        }
+        query_parameters.insert("key", self.weather_api_key.clone().unwrap_or_default());
 
-        self.client
-            .get(url)
-            .query(&query_parameters)
-            .send()
-            .await
-            .map_err(|_| Error::RequestFailed)?
-            .json::<WeatherApiResponse>()
-            .await
-            .map_err(|_| Error::JsonParsingFailed)
+        let response = upstream
+            .send(|client| {
+                client
+                    .get(&url)
+                    .query(&query_parameters)
+                    .headers(telemetry::trace_headers())
+            })
+            .await?;
+
+        let body = read_body(response, ErrorDetails::from_weatherapi).await?;
+
+        parse_json(&body)
+    }
+
+    /// Calls `open-meteo.com` and converts its response to the format of `weatherapi.com`.
+    async fn request_open_meteo(
+        &self,
+        upstream: &Upstream<WeatherProvider>,
+        latitude: f64,
+        longitude: f64,
+    ) -> Result<WeatherApiResponse, Error> {
+        let url = format!("{}/v1/forecast", upstream.host);
+
+        let query_parameters = [
+            ("latitude", latitude.to_string()),
+            ("longitude", longitude.to_string()),
+            ("current", OpenMeteoCurrent::FIELDS.to_owned()),
+            ("timezone", "auto".to_owned()),
+        ];
+
+        let response = upstream
+            .send(|client| {
+                client
+                    .get(&url)
+                    .query(&query_parameters)
+                    .headers(telemetry::trace_headers())
+            })
+            .await?;
+
+        let body = read_body(response, ErrorDetails::from_open_meteo).await?;
+        let response = parse_json::<OpenMeteoResponse>(&body)?;
+
+        Ok(WeatherApiResponse::from(response))
+    }
+
+    /// Calls the forecast endpoint of `weatherapi.com`, which returns every hour of the requested days.
+    async fn request_weatherapi_forecast(
+        &self,
+        upstream: &Upstream<WeatherProvider>,
+        latitude: f64,
+        longitude: f64,
+        hours: u8,
+    ) -> Result<Forecast, Error> {
+        let url = format!("{}/v1/forecast.json", upstream.host);
+
+        // Hours of the next day are needed when the window passes midnight
+        let query_parameters = [
+            ("q", format!("{latitude},{longitude}")),
+            ("days", "2".to_owned()),
+            ("key", self.weather_api_key.clone().unwrap_or_default()),
+        ];
+
+        let response = upstream
+            .send(|client| {
+                client
+                    .get(&url)
+                    .query(&query_parameters)
+                    .headers(telemetry::trace_headers())
+            })
+            .await?;
+
+        let body = read_body(response, ErrorDetails::from_weatherapi).await?;
+        let response = parse_json::<WeatherApiForecastResponse>(&body)?;
+
+        let forecasts = response
+            .forecast
+            .forecastday
+            .into_iter()
+            .flat_map(|day| day.hour)
+            .map(|hour| HourlyForecast {
+                time_epoch: hour.time_epoch,
+                precip_mm: hour.precip_mm,
+                chance_of_rain: hour.chance_of_rain,
+            });
+
+        Ok(Forecast::within(forecasts, hours_window(hours)))
+    }
+
+    /// Calls `open-meteo.com` for the hourly forecast.
+    async fn request_open_meteo_forecast(
+        &self,
+        upstream: &Upstream<WeatherProvider>,
+        latitude: f64,
+        longitude: f64,
+        hours: u8,
+    ) -> Result<Forecast, Error> {
+        let url = format!("{}/v1/forecast", upstream.host);
+
+        let query_parameters = [
+            ("latitude", latitude.to_string()),
+            ("longitude", longitude.to_string()),
+            ("hourly", "precipitation,precipitation_probability".to_owned()),
+            ("forecast_hours", hours.to_string()),
+            ("timeformat", "unixtime".to_owned()),
+        ];
+
+        let response = upstream
+            .send(|client| {
+                client
+                    .get(&url)
+                    .query(&query_parameters)
+                    .headers(telemetry::trace_headers())
+            })
+            .await?;
+
+        let body = read_body(response, ErrorDetails::from_open_meteo).await?;
+        let hourly = parse_json::<OpenMeteoForecastResponse>(&body)?.hourly;
+
+        let forecasts = hourly.time.iter().enumerate().map(|(i, &time_epoch)| HourlyForecast {
+            time_epoch,
+            precip_mm: hourly.precipitation.get(i).copied().flatten(),
+            chance_of_rain: hourly.precipitation_probability.get(i).copied().flatten(),
+        });
+
+        Ok(Forecast::within(forecasts, hours_window(hours)))
+    }
+
+    /// Calls the history endpoint of `weatherapi.com` once for each day, as it returns a single day per call.
+    ///
+    /// Days are requested in the local time of the location, so the days around the range are also requested
+    /// and observations outside of the range are dropped.
+    async fn request_weatherapi_history(
+        &self,
+        upstream: &Upstream<WeatherProvider>,
+        latitude: f64,
+        longitude: f64,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<HistoricalObservation>, Error> {
+        let url = format!("{}/v1/history.json", upstream.host);
+        let range = utc_day_range(from, to);
+
+        let mut observations = Vec::new();
+        let days = from.pred_opt().unwrap_or(from).iter_days().take_while(|d| *d <= to.succ_opt().unwrap_or(to));
+        for day in days {
+            let query_parameters = [
+                ("q", format!("{latitude},{longitude}")),
+                ("dt", day.format("%Y-%m-%d").to_string()),
+                ("key", self.weather_api_key.clone().unwrap_or_default()),
+            ];
+
+            let response = upstream
+                .send(|client| {
+                    client
+                        .get(&url)
+                        .query(&query_parameters)
+                        .headers(telemetry::trace_headers())
+                })
+                .await?;
+
+            let body = read_body(response, ErrorDetails::from_weatherapi).await?;
+            let response = parse_json::<WeatherApiForecastResponse>(&body)?;
+
+            observations.extend(
+                response
+                    .forecast
+                    .forecastday
+                    .into_iter()
+                    .flat_map(|day| day.hour)
+                    .filter(|hour| range.contains(&hour.time_epoch))
+                    .filter_map(HistoricalObservation::from_weatherapi),
+            );
+        }
+
+        observations.sort_by_key(|o| o.time_epoch);
+        observations.dedup_by_key(|o| o.time_epoch);
+
+        Ok(observations)
+    }
+
+    /// Calls `open-meteo.com` for the hourly observations, which it serves for the past three months.
+    async fn request_open_meteo_history(
+        &self,
+        upstream: &Upstream<WeatherProvider>,
+        latitude: f64,
+        longitude: f64,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<HistoricalObservation>, Error> {
+        let url = format!("{}/v1/forecast", upstream.host);
+
+        let query_parameters = [
+            ("latitude", latitude.to_string()),
+            ("longitude", longitude.to_string()),
+            ("hourly", OpenMeteoHourly::HISTORY_FIELDS.to_owned()),
+            ("start_date", from.format("%Y-%m-%d").to_string()),
+            ("end_date", to.format("%Y-%m-%d").to_string()),
+            ("timezone", "UTC".to_owned()),
+            ("timeformat", "unixtime".to_owned()),
+        ];
+
+        let response = upstream
+            .send(|client| {
+                client
+                    .get(&url)
+                    .query(&query_parameters)
+                    .headers(telemetry::trace_headers())
+            })
+            .await?;
+
+        let body = read_body(response, ErrorDetails::from_open_meteo).await?;
+        let hourly = parse_json::<OpenMeteoForecastResponse>(&body)?.hourly;
+
+        // Hours after the current one are forecasts, not observations
+        let now = Utc::now().timestamp();
+        let observations = (0..hourly.time.len())
+            .filter(|&i| hourly.time[i] <= now)
+            .filter_map(|i| hourly.observation(i))
+            .collect();
+
+        Ok(observations)
+    }
+}
+
+/// Returns the UNIX time range of the days from `from` to `to`, inclusive, in UTC.
+fn utc_day_range(from: NaiveDate, to: NaiveDate) -> std::ops::Range<i64> {
+    let start = from.and_time(chrono::NaiveTime::MIN).and_utc().timestamp();
+    let end = to.and_time(chrono::NaiveTime::MIN).and_utc().timestamp() + 86_400;
+
+    start..end
+}
+
+/// Returns the UNIX times of the start of the current hour and the end of the hour `hours` hours later.
+fn hours_window(hours: u8) -> (i64, i64) {
+    let now = Utc::now().timestamp();
+    let start = now - now.rem_euclid(3600);
+
+    (start, start + i64::from(hours) * 3600)
+}
+
+/// A response together with the provider that served it.
+///
+/// Dereferences to the response.
+pub struct Sourced<T> {
+    /// Name of the provider, as written in configuration.
+    pub provider: &'static str,
+    /// The response.
+    pub value: T,
+}
+
+impl<T> Deref for Sourced<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+/// A foreign API provider with its own client, retry policy and circuit breaker.
+struct Upstream<P> {
+    provider: P,
+    /// Name of the provider, used in metrics and logs.
+    name: &'static str,
+    client: reqwest::Client,
+    host: String,
+    retry: RetryPolicy,
+    breaker: CircuitBreaker,
+}
+
+impl<P> Upstream<P> {
+    /// Creates the client of a provider with timeouts of the given policy.
+    ///
+    /// `default_host` is used unless the configuration overrides it.
+    fn new(
+        provider: P,
+        name: &'static str,
+        default_host: &str,
+        config: &ProviderConfig<P>,
+    ) -> Result<Self, CreationError> {
+        let policy: &ProviderPolicy = &config.policy;
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_millis(policy.connect_timeout_ms))
+            .read_timeout(Duration::from_millis(policy.read_timeout_ms))
+            .build()
+            .map_err(CreationError::Client)?;
+
+        Ok(Self {
+            provider,
+            name,
+            client,
+            host: config.host.as_deref().unwrap_or(default_host).to_owned(),
+            retry: RetryPolicy::from(policy),
+            breaker: CircuitBreaker::from(policy),
+        })
+    }
+
+    /// Sends the request built by `build`, retrying it on connection failures, timeouts,
+    /// `5xx` and `429` responses.
+    ///
+    /// Only idempotent requests should be sent through this function, as they may be sent many times.
+    ///
+    /// Responses with other statuses are returned for the caller to handle.
+    /// If retries are exhausted on a retryable status, the last response is returned.
+    ///
+    /// # Errors
+    /// Returns error if the circuit is open or every attempt fails to get a response.
+    async fn send(
+        &self,
+        build: impl Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, Error> {
+        if !self.breaker.try_acquire() {
+            return Err(Error::CircuitOpen);
+        }
+
+        let mut retry = 0;
+        loop {
+            let result = build(&self.client).send().await;
+
+            let requested_delay = match &result {
+                Ok(response) if !resilience::is_retryable(response.status()) => {
+                    self.breaker.record_success();
+                    return result.map_err(|e| Error::RequestFailed(e.without_url()));
+                }
+                Ok(response) => resilience::retry_after(response),
+                Err(_) => None,
+            };
+
+            retry += 1;
+            let delay = requested_delay.unwrap_or_else(|| self.retry.backoff(retry));
+            if retry > self.retry.max_retries || delay > self.retry.max_delay {
+                self.breaker.record_failure();
+                return result.map_err(|e| Error::RequestFailed(e.without_url()));
+            }
+
+            tracing::debug!(provider = self.name, retry, delay_ms = delay.as_millis() as u64, "retrying API call");
+            tokio::time::sleep(delay).await;
+        }
     }
 }
 
 /// The response HTTP client returns from geolocation API call.
-#[derive(serde::Deserialize)]
+///
+/// Place names and the timezone are only returned by providers that report them.
+#[derive(Default, serde::Deserialize)]
 pub struct GeolocationApiResponse {
     pub latitude: f64,
     pub longitude: f64,
+    #[serde(default)]
+    pub city: Option<String>,
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub country: Option<String>,
+    /// IANA timezone name, e.g. `Europe/Istanbul`.
+    #[serde(default)]
+    pub timezone: Option<String>,
 }
 
 /// The response HTTP client returns from weather API call.
-/// 
-/// The API is configured to return only the desired information
-/// but can be configured to return more.
-/// 
-/// The API also returns information about the location of the coordinates, but they are discarded.
-#[derive(serde::Deserialize, serde::Serialize)]
+///
+/// Fields other than the temperatures, the condition text and `last_updated` are optional,
+/// so the response is parsed whichever fields the API account is configured to return.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
 pub struct WeatherApiResponse {
-    #[serde(skip)]
+    #[serde(default)]
     pub location: Location,
     pub current: Current,
 }
 
-/// A placeholder type, used in `WeatherApiResponse`
-/// so `location` section of the response can be discarded.
-#[derive(Default)]
-pub struct Location;
+impl WeatherApiResponse {
+    /// Returns the UTC offset of the location at the time of the response, if the API reports it.
+    ///
+    /// `weatherapi.com` only reports the local time and the UNIX time of the location,
+    /// so the offset is their difference rounded to 15 minutes, the granularity of real offsets.
+    #[must_use]
+    pub fn utc_offset(&self) -> Option<FixedOffset> {
+        if let Some(seconds) = self.location.utc_offset_seconds {
+            return FixedOffset::east_opt(seconds);
+        }
+
+        let epoch = self.location.localtime_epoch?;
+        let local = parse_local_time(self.location.localtime.as_deref()?)?;
+        let difference = (local.and_utc().timestamp() - epoch) as f64;
+        let seconds = (difference / 900.0).round() as i32 * 900;
+
+        FixedOffset::east_opt(seconds)
+    }
+
+    /// Returns the time the weather information was last updated, in the timezone of the location.
+    ///
+    /// Returns `None` if neither the UNIX time nor the UTC offset is reported,
+    /// as the local time alone does not identify an instant.
+    #[must_use]
+    pub fn last_updated(&self) -> Option<DateTime<FixedOffset>> {
+        let offset = self.utc_offset();
+
+        if let Some(epoch) = self.current.last_updated_epoch {
+            let utc = DateTime::from_timestamp(epoch, 0)?;
+            let offset = offset.unwrap_or_else(|| Utc.fix());
+            return Some(utc.with_timezone(&offset));
+        }
+
+        parse_local_time(&self.current.last_updated)?
+            .and_local_timezone(offset?)
+            .single()
+    }
+}
+
+/// Hourly precipitation forecast returned by the forecast call.
+#[derive(Default, Debug)]
+pub struct Forecast {
+    /// Forecasts of each hour, ordered by time.
+    pub hours: Vec<HourlyForecast>,
+}
+
+impl Forecast {
+    /// Creates a forecast of the hours starting within given UNIX time range, end exclusive.
+    fn within(hours: impl Iterator<Item = HourlyForecast>, (start, end): (i64, i64)) -> Self {
+        Self {
+            hours: hours
+                .filter(|h| (start..end).contains(&h.time_epoch))
+                .collect(),
+        }
+    }
+}
+
+/// Precipitation forecast of a single hour.
+#[derive(Debug, Clone)]
+pub struct HourlyForecast {
+    /// UNIX time of the start of the hour.
+    pub time_epoch: i64,
+    pub precip_mm: Option<f64>,
+    /// Probability of rain in percent.
+    pub chance_of_rain: Option<u8>,
+}
+
+/// Weather observed during an hour, returned by the history call.
+///
+/// Units are metric: °C, km/h, hPa and mm.
+#[derive(Debug, Clone, Default)]
+pub struct HistoricalObservation {
+    /// UNIX time of the start of the hour.
+    pub time_epoch: i64,
+    pub temp_c: f64,
+    pub feelslike_c: Option<f64>,
+    pub condition: Option<String>,
+    /// Code of the condition in the scheme of the provider.
+    pub condition_code: Option<u16>,
+    pub wind_kph: Option<f64>,
+    /// Relative humidity in percent.
+    pub humidity: Option<u8>,
+    pub pressure_mb: Option<f64>,
+    pub precip_mm: Option<f64>,
+    /// Cloud cover in percent.
+    pub cloud: Option<u8>,
+    pub uv: Option<f64>,
+}
+
+impl HistoricalObservation {
+    /// Converts an hour of `weatherapi.com` history, which has no observation if the temperature is missing.
+    fn from_weatherapi(hour: WeatherApiForecastHour) -> Option<Self> {
+        Some(Self {
+            time_epoch: hour.time_epoch,
+            temp_c: hour.temp_c?,
+            feelslike_c: hour.feelslike_c,
+            condition: hour.condition.as_ref().map(|c| c.text.clone()),
+            condition_code: hour.condition.and_then(|c| c.code),
+            wind_kph: hour.wind_kph,
+            humidity: hour.humidity,
+            pressure_mb: hour.pressure_mb,
+            precip_mm: hour.precip_mm,
+            cloud: hour.cloud,
+            uv: hour.uv,
+        })
+    }
+}
+
+/// Response of the forecast and history endpoints of `weatherapi.com`, only the fields used are parsed.
+#[derive(serde::Deserialize)]
+struct WeatherApiForecastResponse {
+    forecast: WeatherApiForecast,
+}
+
+#[derive(serde::Deserialize)]
+struct WeatherApiForecast {
+    forecastday: Vec<WeatherApiForecastDay>,
+}
+
+#[derive(serde::Deserialize)]
+struct WeatherApiForecastDay {
+    hour: Vec<WeatherApiForecastHour>,
+}
+
+/// An hour in `weatherapi.com` forecast and history responses.
+///
+/// History responses have no `chance_of_rain`, forecast responses are only used for precipitation.
+#[derive(serde::Deserialize)]
+struct WeatherApiForecastHour {
+    time_epoch: i64,
+    precip_mm: Option<f64>,
+    chance_of_rain: Option<u8>,
+    temp_c: Option<f64>,
+    feelslike_c: Option<f64>,
+    condition: Option<Condition>,
+    wind_kph: Option<f64>,
+    humidity: Option<u8>,
+    pressure_mb: Option<f64>,
+    cloud: Option<u8>,
+    uv: Option<f64>,
+}
+
+/// Response of `open-meteo.com` for hourly forecasts requested with `timeformat=unixtime`.
+#[derive(serde::Deserialize)]
+struct OpenMeteoForecastResponse {
+    hourly: OpenMeteoHourly,
+}
+
+/// Hourly values in `open-meteo.com` response, values of each hour are at the same index.
+///
+/// Only the requested fields are present.
+#[derive(serde::Deserialize)]
+struct OpenMeteoHourly {
+    time: Vec<i64>,
+    #[serde(default)]
+    precipitation: Vec<Option<f64>>,
+    #[serde(default)]
+    precipitation_probability: Vec<Option<u8>>,
+    #[serde(default)]
+    temperature_2m: Vec<Option<f64>>,
+    #[serde(default)]
+    apparent_temperature: Vec<Option<f64>>,
+    #[serde(default)]
+    weather_code: Vec<Option<u8>>,
+    #[serde(default)]
+    wind_speed_10m: Vec<Option<f64>>,
+    #[serde(default)]
+    relative_humidity_2m: Vec<Option<u8>>,
+    #[serde(default)]
+    pressure_msl: Vec<Option<f64>>,
+    #[serde(default)]
+    cloud_cover: Vec<Option<u8>>,
+    #[serde(default)]
+    uv_index: Vec<Option<f64>>,
+}
+
+impl OpenMeteoHourly {
+    /// Fields requested in `hourly` query parameter of history calls.
+    const HISTORY_FIELDS: &'static str = "temperature_2m,apparent_temperature,weather_code,\
+        wind_speed_10m,relative_humidity_2m,pressure_msl,precipitation,cloud_cover,uv_index";
+
+    /// Returns the observation at given index, if the temperature is reported for it.
+    fn observation(&self, index: usize) -> Option<HistoricalObservation> {
+        fn at<T: Copy>(values: &[Option<T>], index: usize) -> Option<T> {
+            values.get(index).copied().flatten()
+        }
+
+        let weather_code = at(&self.weather_code, index);
+        Some(HistoricalObservation {
+            time_epoch: *self.time.get(index)?,
+            temp_c: at(&self.temperature_2m, index)?,
+            feelslike_c: at(&self.apparent_temperature, index),
+            condition: weather_code.map(|c| wmo_condition_text(c).to_owned()),
+            condition_code: weather_code.map(u16::from),
+            wind_kph: at(&self.wind_speed_10m, index),
+            humidity: at(&self.relative_humidity_2m, index),
+            pressure_mb: at(&self.pressure_msl, index),
+            precip_mm: at(&self.precipitation, index),
+            cloud: at(&self.cloud_cover, index),
+            uv: at(&self.uv_index, index),
+        })
+    }
+}
+
+/// Parses local times in `YYYY-MM-DD HH:MM` format.
+fn parse_local_time(time: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").ok()
+}
+
+/// The information the API returns about the location of the coordinates.
+#[derive(Clone, Default, serde::Deserialize, serde::Serialize)]
+pub struct Location {
+    pub name: Option<String>,
+    pub region: Option<String>,
+    pub country: Option<String>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    /// IANA timezone name, e.g. `Europe/Istanbul`.
+    pub tz_id: Option<String>,
+    pub localtime_epoch: Option<i64>,
+    /// Local time in `YYYY-MM-DD HH:MM` format.
+    pub localtime: Option<String>,
+    /// Not returned by `weatherapi.com`, filled by providers that report it.
+    pub utc_offset_seconds: Option<i32>,
+}
 
 /// The information the API returns about the weather at given location
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Default, serde::Deserialize, serde::Serialize)]
 pub struct Current {
+    pub last_updated_epoch: Option<i64>,
+    /// Local time in `YYYY-MM-DD HH:MM` format.
     pub last_updated: String,
     pub temp_c: f64,
     pub condition: Condition,
     pub feelslike_c: f64,
+    /// `1` during the day, `0` during the night.
+    pub is_day: Option<u8>,
+    pub wind_kph: Option<f64>,
+    pub wind_degree: Option<u16>,
+    /// Compass direction of the wind, e.g. `NNW`.
+    pub wind_dir: Option<String>,
+    pub gust_kph: Option<f64>,
+    /// Relative humidity in percent.
+    pub humidity: Option<u8>,
+    pub pressure_mb: Option<f64>,
+    pub precip_mm: Option<f64>,
+    /// Cloud cover in percent.
+    pub cloud: Option<u8>,
+    pub vis_km: Option<f64>,
+    pub uv: Option<f64>,
+    /// Only returned if requested with `aqi=yes`.
+    pub air_quality: Option<AirQuality>,
 }
 
 /// The information about weather condition
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Default, serde::Deserialize, serde::Serialize)]
 pub struct Condition {
     pub text: String,
+    /// URL of the condition icon, may be protocol relative.
+    pub icon: Option<String>,
+    /// Code of the condition in the scheme of the provider.
+    pub code: Option<u16>,
+}
+
+/// Air pollutant concentrations in μg/m³ and air quality indices.
+#[derive(Clone, Default, serde::Deserialize, serde::Serialize)]
+pub struct AirQuality {
+    pub co: Option<f64>,
+    pub no2: Option<f64>,
+    pub o3: Option<f64>,
+    pub so2: Option<f64>,
+    pub pm2_5: Option<f64>,
+    pub pm10: Option<f64>,
+    /// US EPA index, from 1 (good) to 6 (hazardous).
+    #[serde(rename = "us-epa-index")]
+    pub us_epa_index: Option<u8>,
+    /// UK DEFRA index, from 1 (low) to 10 (very high).
+    #[serde(rename = "gb-defra-index")]
+    pub gb_defra_index: Option<u8>,
+}
+
+/// Response of `ip-api.com`, `lat` and `lon` are missing if `status` is not `success`.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IpApiResponse {
+    status: String,
+    message: Option<String>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    city: Option<String>,
+    region_name: Option<String>,
+    country: Option<String>,
+    timezone: Option<String>,
+}
+
+/// Response of `open-meteo.com` for current conditions.
+///
+/// Coordinates are of the grid cell the weather is computed for.
+#[derive(serde::Deserialize)]
+struct OpenMeteoResponse {
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    /// IANA timezone name, resolved from the coordinates as `timezone=auto` is requested.
+    timezone: Option<String>,
+    utc_offset_seconds: Option<i32>,
+    current: OpenMeteoCurrent,
+}
+
+/// Current conditions in `open-meteo.com` response.
+///
+/// Units are the API defaults: km/h, hPa, mm and meters.
+#[derive(serde::Deserialize)]
+struct OpenMeteoCurrent {
+    /// Local time in `YYYY-MM-DDTHH:MM` format.
+    time: String,
+    temperature_2m: f64,
+    apparent_temperature: f64,
+    /// WMO weather interpretation code.
+    weather_code: u8,
+    is_day: Option<u8>,
+    wind_speed_10m: Option<f64>,
+    wind_direction_10m: Option<u16>,
+    wind_gusts_10m: Option<f64>,
+    relative_humidity_2m: Option<u8>,
+    pressure_msl: Option<f64>,
+    precipitation: Option<f64>,
+    cloud_cover: Option<u8>,
+    visibility: Option<f64>,
+    uv_index: Option<f64>,
+}
+
+impl OpenMeteoCurrent {
+    /// Fields requested in `current` query parameter.
+    const FIELDS: &'static str = "temperature_2m,apparent_temperature,weather_code,is_day,\
+        wind_speed_10m,wind_direction_10m,wind_gusts_10m,relative_humidity_2m,pressure_msl,\
+        precipitation,cloud_cover,visibility,uv_index";
+}
+
+impl From<OpenMeteoResponse> for WeatherApiResponse {
+    fn from(response: OpenMeteoResponse) -> Self {
+        let current = response.current;
+        Self {
+            location: Location {
+                lat: response.latitude,
+                lon: response.longitude,
+                tz_id: response.timezone,
+                utc_offset_seconds: response.utc_offset_seconds,
+                ..Location::default()
+            },
+            current: Current {
+                last_updated_epoch: None,
+                last_updated: current.time.replacen('T', " ", 1),
+                temp_c: current.temperature_2m,
+                condition: Condition {
+                    text: wmo_condition_text(current.weather_code).to_owned(),
+                    icon: None,
+                    code: Some(u16::from(current.weather_code)),
+                },
+                feelslike_c: current.apparent_temperature,
+                is_day: current.is_day,
+                wind_kph: current.wind_speed_10m,
+                wind_degree: current.wind_direction_10m,
+                wind_dir: current.wind_direction_10m.map(|d| compass_direction(d).to_owned()),
+                gust_kph: current.wind_gusts_10m,
+                humidity: current.relative_humidity_2m,
+                pressure_mb: current.pressure_msl,
+                precip_mm: current.precipitation,
+                cloud: current.cloud_cover,
+                vis_km: current.visibility.map(|meters| meters / 1000.0),
+                uv: current.uv_index,
+                air_quality: None,
+            },
+        }
+    }
+}
+
+/// Returns the 16-point compass direction of the wind blowing from given degrees.
+fn compass_direction(degrees: u16) -> &'static str {
+    const DIRECTIONS: [&str; 16] = [
+        "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW",
+        "NNW",
+    ];
+
+    // Each direction covers 22.5 degrees centered on it
+    let index = ((f64::from(degrees % 360) + 11.25) / 22.5) as usize % 16;
+
+    DIRECTIONS[index]
+}
+
+/// Returns the description of a WMO weather interpretation code.
+const fn wmo_condition_text(code: u8) -> &'static str {
+    match code {
+        0 => "Clear",
+        1 => "Mainly clear",
+        2 => "Partly cloudy",
+        3 => "Overcast",
+        45 | 48 => "Fog",
+        51 | 53 | 55 => "Drizzle",
+        56 | 57 => "Freezing drizzle",
+        61 | 63 | 65 => "Rain",
+        66 | 67 => "Freezing rain",
+        71 | 73 | 75 | 77 => "Snow",
+        80..=82 => "Rain showers",
+        85 | 86 => "Snow showers",
+        95 => "Thunderstorm",
+        96 | 99 => "Thunderstorm with hail",
+        _ => "Unknown",
+    }
 }
 
 /// HTTP client errors
+///
+/// Bodies carried by errors are truncated to `MAX_ERROR_BODY_LENGTH` bytes.
+/// Request URLs are stripped from `reqwest` errors, as they may contain API keys.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error("API request failed")]
-    RequestFailed,
+    /// Sending the request or receiving the response failed, e.g. connection refused or timed out.
+    #[error("API request failed: {0}")]
+    RequestFailed(#[source] reqwest::Error),
+    /// The API responded with a status other than `200 Success`.
+    #[error("API returned {status}{}", describe(code.as_deref(), message.as_deref()))]
+    UnexpectedStatus {
+        status: StatusCode,
+        /// Error code reported by the API in the body, if any.
+        code: Option<String>,
+        /// Error message reported by the API in the body, if any.
+        message: Option<String>,
+        body: String,
+    },
+    /// The API responded with `200 Success` but reported an error in the body.
+    #[error("API reported an error{}", describe(code.as_deref(), message.as_deref()))]
+    ProviderError {
+        code: Option<String>,
+        message: Option<String>,
+    },
+    /// The body is not in the expected plain text format.
     #[error("parsing API response failed")]
-    ParsingFailed,
-    #[error("parsing API response failed")]
-    JsonParsingFailed,
-    #[error("API internal error: {0}")]
-    ApiInternalError(String),
+    ParsingFailed { body: String },
+    /// The body is not in the expected JSON format.
+    #[error("parsing API response failed: {source}")]
+    JsonParsingFailed {
+        #[source]
+        source: serde_json::Error,
+        body: String,
+    },
+    #[error("API is failing, circuit is open")]
+    CircuitOpen,
+}
+
+/// Errors of HTTP client creation.
+#[derive(Debug, thiserror::Error)]
+pub enum CreationError {
+    #[error("no weather API key in environment variables, please define 'WEATHER_API_KEY'")]
+    MissingApiKey(#[from] VarError),
+    #[error("could not create HTTP client")]
+    Client(reqwest::Error),
+    #[error("provider chains need at least one provider")]
+    EmptyChain,
+}
+
+impl Error {
+    /// Returns a short, stable name of the error, used as a metric label.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::RequestFailed(e) if e.is_timeout() => "timeout",
+            Self::RequestFailed(_) => "request_failed",
+            Self::UnexpectedStatus { .. } => "unexpected_status",
+            Self::ProviderError { .. } => "provider_error",
+            Self::ParsingFailed { .. } => "parsing_failed",
+            Self::JsonParsingFailed { .. } => "json_parsing_failed",
+            Self::CircuitOpen => "circuit_open",
+        }
+    }
+
+    /// Returns whether the API did not respond in time.
+    #[must_use]
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::RequestFailed(e) if e.is_timeout())
+    }
+
+    /// Returns whether the API is temporarily refusing calls, so a later call may succeed.
+    ///
+    /// These are open circuits, `429 Too Many Requests`, `503 Service Unavailable`
+    /// and exceeded quotas reported by the API.
+    #[must_use]
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            Self::CircuitOpen => true,
+            Self::UnexpectedStatus { status, code, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::SERVICE_UNAVAILABLE
+                    || code.as_deref() == Some(WEATHERAPI_QUOTA_EXCEEDED)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Longest body, in bytes, kept in errors.
+pub const MAX_ERROR_BODY_LENGTH: usize = 512;
+
+/// Error code `weatherapi.com` reports when the monthly call quota is exceeded.
+const WEATHERAPI_QUOTA_EXCEEDED: &str = "2007";
+
+/// Formats the error code and message reported by an API for error messages.
+fn describe(code: Option<&str>, message: Option<&str>) -> String {
+    match (code, message) {
+        (Some(code), Some(message)) => format!(": {message} ({code})"),
+        (Some(text), None) | (None, Some(text)) => format!(": {text}"),
+        (None, None) => String::new(),
+    }
+}
+
+/// Returns the body cut to `MAX_ERROR_BODY_LENGTH` bytes at a character boundary.
+fn truncate(body: &str) -> String {
+    let mut end = body.len().min(MAX_ERROR_BODY_LENGTH);
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    body[..end].to_owned()
+}
+
+/// Reads the body of the response.
+///
+/// # Errors
+/// Returns `Error::UnexpectedStatus` with the details extracted by `details`
+/// if the status is not `200 Success`, or `Error::RequestFailed` if the body can not be read.
+async fn read_body(
+    response: reqwest::Response,
+    details: fn(&str) -> ErrorDetails,
+) -> Result<String, Error> {
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::RequestFailed(e.without_url()))?;
+
+    if status != StatusCode::OK {
+        let ErrorDetails { code, message } = details(&body);
+        return Err(Error::UnexpectedStatus {
+            status,
+            code,
+            message,
+            body: truncate(&body),
+        });
+    }
+
+    Ok(body)
+}
+
+/// Parses the body as JSON.
+///
+/// # Errors
+/// Returns `Error::JsonParsingFailed` with the body if it is not in expected format.
+fn parse_json<T: serde::de::DeserializeOwned>(body: &str) -> Result<T, Error> {
+    serde_json::from_str(body).map_err(|source| Error::JsonParsingFailed {
+        source,
+        body: truncate(body),
+    })
+}
+
+/// Error code and message an API reports in the body of an error response.
+#[derive(Default)]
+struct ErrorDetails {
+    code: Option<String>,
+    message: Option<String>,
+}
+
+impl ErrorDetails {
+    /// Used for APIs that do not report details.
+    fn none(_: &str) -> Self {
+        Self::default()
+    }
+
+    /// Extracts `{"error":{"code":2006,"message":"API key is invalid."}}`.
+    fn from_weatherapi(body: &str) -> Self {
+        #[derive(serde::Deserialize)]
+        struct Body {
+            error: Inner,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct Inner {
+            code: Option<i64>,
+            message: Option<String>,
+        }
+
+        serde_json::from_str::<Body>(body).map_or_else(
+            |_| Self::default(),
+            |Body { error }| Self {
+                code: error.code.map(|c| c.to_string()),
+                message: error.message,
+            },
+        )
+    }
+
+    /// Extracts `{"error":true,"reason":"RateLimited","message":"..."}`.
+    fn from_ipapi(body: &str) -> Self {
+        #[derive(serde::Deserialize)]
+        struct Body {
+            reason: Option<String>,
+            message: Option<String>,
+        }
+
+        serde_json::from_str::<Body>(body).map_or_else(
+            |_| Self::default(),
+            |body| Self {
+                code: body.reason,
+                message: body.message,
+            },
+        )
+    }
+
+    /// Extracts `{"error":true,"reason":"..."}`, where the reason is a message.
+    fn from_open_meteo(body: &str) -> Self {
+        #[derive(serde::Deserialize)]
+        struct Body {
+            reason: Option<String>,
+        }
+
+        serde_json::from_str::<Body>(body).map_or_else(
+            |_| Self::default(),
+            |body| Self {
+                code: None,
+                message: body.reason,
+            },
+        )
+    }
 }
 
 /// Represents a coordinate, used to parse the geolocation API response
@@ -179,12 +1394,14 @@ impl FromStr for Coordinate {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsing_failed = || Error::ParsingFailed { body: truncate(s) };
+
         let Some((latitude, longitude)) = s.split_once(',') else {
-            return Err(Error::ParsingFailed);
+            return Err(parsing_failed());
         };
 
-        let latitude = latitude.parse().map_err(|_| Error::ParsingFailed)?;
-        let longitude = longitude.parse().map_err(|_| Error::ParsingFailed)?;
+        let latitude = latitude.parse().map_err(|_| parsing_failed())?;
+        let longitude = longitude.parse().map_err(|_| parsing_failed())?;
 
         let coordinate = Self {
             latitude,
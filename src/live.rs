@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+
+use crate::config::StreamConfig;
+use crate::history;
+use crate::http_client::{HttpClient, Sourced, WeatherApiResponse};
+use crate::queries::Database;
+
+/// Latest weather of a feed, missing until the first call succeeds.
+pub type LatestWeather = Option<Arc<Sourced<WeatherApiResponse>>>;
+
+/// Polls the current weather of the locations streams are subscribed to.
+///
+/// Subscribers of the same coordinates and language share a feed, which calls the weather API once per
+/// poll interval however many subscribers it has. A feed stops when its last subscriber leaves.
+#[derive(Clone)]
+pub struct LiveWeather {
+    http_client: Arc<HttpClient>,
+    database: Database,
+    poll_interval: Duration,
+    keep_alive: Duration,
+    feeds: Arc<Mutex<HashMap<FeedKey, watch::Sender<LatestWeather>>>>,
+}
+
+/// Coordinates and language a feed is for, coordinates are compared by their bits.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct FeedKey {
+    latitude: u64,
+    longitude: u64,
+    lang: Option<String>,
+}
+
+impl LiveWeather {
+    /// Creates the feed registry with the HTTP client feeds call the weather API with
+    /// and the database their observations are recorded to.
+    #[must_use]
+    pub fn new(config: &StreamConfig, http_client: Arc<HttpClient>, database: Database) -> Self {
+        Self {
+            http_client,
+            database,
+            poll_interval: Duration::from_secs(config.poll_interval_seconds.max(1)),
+            keep_alive: Duration::from_secs(config.keep_alive_seconds.max(1)),
+            feeds: Arc::default(),
+        }
+    }
+
+    /// Returns the interval the weather of feeds is fetched at.
+    #[must_use]
+    pub const fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    /// Returns the interval of keep-alive comments sent to idle subscribers.
+    #[must_use]
+    pub const fn keep_alive(&self) -> Duration {
+        self.keep_alive
+    }
+
+    /// Subscribes to the weather at given coordinates in given language, starting a feed if there is none.
+    ///
+    /// The receiver is marked changed if the feed already has a weather, so it is seen immediately.
+    ///
+    /// # Panics
+    /// Panics if the feed registry lock is poisoned.
+    #[must_use]
+    pub fn subscribe(&self, latitude: f64, longitude: f64, lang: Option<String>) -> watch::Receiver<LatestWeather> {
+        let key = FeedKey {
+            latitude: latitude.to_bits(),
+            longitude: longitude.to_bits(),
+            lang,
+        };
+
+        let mut feeds = self.feeds.lock().expect("feed registry lock should not be poisoned");
+        if let Some(sender) = feeds.get(&key) {
+            let mut receiver = sender.subscribe();
+            if receiver.borrow().is_some() {
+                receiver.mark_changed();
+            }
+            return receiver;
+        }
+
+        let (sender, receiver) = watch::channel(None);
+        feeds.insert(key.clone(), sender.clone());
+        drop(feeds);
+
+        tokio::spawn(self.clone().poll(key, latitude, longitude, sender));
+        receiver
+    }
+
+    /// Calls the weather API every poll interval while the feed has subscribers,
+    /// notifying them when the weather is updated.
+    ///
+    /// Failed calls are logged and retried on the next tick, subscribers keep the last weather.
+    #[tracing::instrument(skip_all)]
+    async fn poll(self, key: FeedKey, latitude: f64, longitude: f64, sender: watch::Sender<LatestWeather>) {
+        let mut interval = tokio::time::interval(self.poll_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            if !self.has_subscribers(&key, &sender) {
+                return;
+            }
+
+            let response = match self
+                .http_client
+                .get_localized_weather_for_coordinates(latitude, longitude, key.lang.as_deref())
+                .await
+            {
+                Ok(response) => response,
+                Err(e) => {
+                    tracing::warn!(kind = e.kind(), error = %e, "live weather query failed");
+                    continue;
+                }
+            };
+
+            // Localized condition texts are not stored, as in `weather` calls
+            if key.lang.is_none() {
+                history::record_current(&self.database, latitude, longitude, &response).await;
+            }
+
+            sender.send_if_modified(|latest| {
+                let updated = !matches!(latest, Some(latest) if is_same_update(latest, &response));
+                if updated {
+                    *latest = Some(Arc::new(response));
+                }
+                updated
+            });
+        }
+    }
+
+    /// Returns whether the feed still has subscribers, removing it from the registry otherwise.
+    ///
+    /// Checked with the registry locked, so no subscriber can join a feed that is stopping.
+    fn has_subscribers(&self, key: &FeedKey, sender: &watch::Sender<LatestWeather>) -> bool {
+        let mut feeds = self.feeds.lock().expect("feed registry lock should not be poisoned");
+        if sender.receiver_count() > 0 {
+            return true;
+        }
+
+        feeds.remove(key);
+        false
+    }
+}
+
+/// Returns whether two responses report the same update of the weather.
+fn is_same_update(latest: &WeatherApiResponse, response: &WeatherApiResponse) -> bool {
+    latest.current.last_updated_epoch == response.current.last_updated_epoch
+        && latest.current.last_updated == response.current.last_updated
+}
@@ -0,0 +1,69 @@
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+use crate::config::{LogConfig, LogFormat, TelemetryConfig};
+use crate::telemetry;
+
+/// Installs the global tracing subscriber with given format and filter and,
+/// if configured, the OpenTelemetry span exporter.
+///
+/// The `RUST_LOG` environment variable takes precedence over the configured filter.
+///
+/// The returned guard needs to be kept alive while the program runs,
+/// dropping it flushes spans that are not exported yet.
+///
+/// # Errors
+/// Returns error if the filter is not valid, the exporter can not be created
+/// or a global subscriber is already installed.
+pub fn init(log: &LogConfig, telemetry: &TelemetryConfig) -> Result<LogGuard, anyhow::Error> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(directives) if !directives.is_empty() => EnvFilter::try_new(directives)?,
+        _ => EnvFilter::try_new(&log.level)?,
+    };
+
+    let mut layers: Vec<Box<dyn Layer<Registry> + Send + Sync>> = Vec::new();
+    match log.format {
+        LogFormat::Pretty => layers.push(tracing_subscriber::fmt::layer().boxed()),
+        LogFormat::Json => layers.push(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .boxed(),
+        ),
+    }
+
+    let tracer_provider = telemetry::tracer_provider(telemetry)?;
+    if let Some(provider) = &tracer_provider {
+        layers.push(
+            tracing_opentelemetry::layer()
+                .with_tracer(telemetry::tracer(provider))
+                .boxed(),
+        );
+    }
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .try_init()?;
+
+    Ok(LogGuard { tracer_provider })
+}
+
+/// Flushes and shuts down the span exporter when dropped.
+#[must_use = "dropping the guard stops span export"]
+pub struct LogGuard {
+    tracer_provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.tracer_provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("could not flush spans: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use poem::http::header::CONTENT_TYPE;
+use poem::web::Data;
+use poem::{Endpoint, IntoResponse, Request, Response};
+use poem_openapi::OperationId;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::queries::Database;
+
+/// Static storage for the metrics.
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Collected metrics and the registry they are exported from.
+pub struct Metrics {
+    registry: Registry,
+    /// Handled API requests, by operation, method and status code.
+    http_requests_total: IntCounterVec,
+    /// Time spent handling API requests, by operation and method.
+    http_request_duration_seconds: HistogramVec,
+    /// Time spent on calls to foreign APIs, by API and outcome.
+    upstream_request_duration_seconds: HistogramVec,
+    /// Failed calls to foreign APIs, by API and error kind.
+    upstream_errors_total: IntCounterVec,
+    /// Time spent hashing and verifying passwords, by operation.
+    password_hash_duration_seconds: HistogramVec,
+    /// Time spent on database queries, by query.
+    database_query_duration_seconds: HistogramVec,
+    /// Connections in the database pool, by state.
+    database_pool_connections: IntGaugeVec,
+}
+
+impl Metrics {
+    /// Returns the metrics if they are previously initialized, or initializes them.
+    pub fn get() -> &'static Self {
+        METRICS.get_or_init(Self::new)
+    }
+
+    /// Creates and registers every metric.
+    ///
+    /// # Panics
+    /// `expect`s in the function should not cause any panics as metric definitions are static.
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "Handled API requests"),
+            &["operation", "method", "status"],
+        )
+        .expect("metric definition should be valid");
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time spent handling API requests"),
+            &["operation", "method"],
+        )
+        .expect("metric definition should be valid");
+        let upstream_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_request_duration_seconds",
+                "Time spent on calls to foreign APIs",
+            ),
+            &["api", "outcome"],
+        )
+        .expect("metric definition should be valid");
+        let upstream_errors_total = IntCounterVec::new(
+            Opts::new("upstream_errors_total", "Failed calls to foreign APIs"),
+            &["api", "kind"],
+        )
+        .expect("metric definition should be valid");
+        let password_hash_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "password_hash_duration_seconds",
+                "Time spent hashing and verifying passwords",
+            )
+            .buckets(vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]),
+            &["operation"],
+        )
+        .expect("metric definition should be valid");
+        let database_query_duration_seconds = HistogramVec::new(
+            HistogramOpts::new("database_query_duration_seconds", "Time spent on database queries")
+                .buckets(vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25]),
+            &["query"],
+        )
+        .expect("metric definition should be valid");
+        let database_pool_connections = IntGaugeVec::new(
+            Opts::new("database_pool_connections", "Connections in the database pool"),
+            &["state"],
+        )
+        .expect("metric definition should be valid");
+
+        registry
+            .register(Box::new(http_requests_total.clone()))
+            .and_then(|()| registry.register(Box::new(http_request_duration_seconds.clone())))
+            .and_then(|()| registry.register(Box::new(upstream_request_duration_seconds.clone())))
+            .and_then(|()| registry.register(Box::new(upstream_errors_total.clone())))
+            .and_then(|()| registry.register(Box::new(password_hash_duration_seconds.clone())))
+            .and_then(|()| registry.register(Box::new(database_query_duration_seconds.clone())))
+            .and_then(|()| registry.register(Box::new(database_pool_connections.clone())))
+            .expect("metric names should be unique");
+
+        Self {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            upstream_request_duration_seconds,
+            upstream_errors_total,
+            password_hash_duration_seconds,
+            database_query_duration_seconds,
+            database_pool_connections,
+        }
+    }
+
+    /// Records the outcome and duration of a call to a foreign API.
+    ///
+    /// `error_kind` is `None` if the call succeeded.
+    pub fn observe_upstream_call(&self, api: &str, started: Instant, error_kind: Option<&str>) {
+        let outcome = if error_kind.is_some() { "error" } else { "success" };
+        self.upstream_request_duration_seconds
+            .with_label_values(&[api, outcome])
+            .observe(started.elapsed().as_secs_f64());
+
+        if let Some(kind) = error_kind {
+            self.upstream_errors_total.with_label_values(&[api, kind]).inc();
+        }
+    }
+
+    /// Starts a timer that records the duration of a password operation when dropped.
+    pub fn time_password(&self, operation: &str) -> HistogramTimer {
+        self.password_hash_duration_seconds
+            .with_label_values(&[operation])
+            .start_timer()
+    }
+
+    /// Starts a timer that records the duration of a database query when dropped.
+    pub fn time_query(&self, query: &str) -> HistogramTimer {
+        self.database_query_duration_seconds
+            .with_label_values(&[query])
+            .start_timer()
+    }
+}
+
+/// Middleware function that records count and duration of API requests.
+///
+/// Requests are labeled with the operation ID poem-openapi attaches to responses,
+/// so paths that match no operation do not create new label values.
+///
+/// # Errors
+/// Never fails, errors of the inner endpoint are converted to responses.
+pub async fn track_requests<E: Endpoint>(next: Arc<E>, request: Request) -> poem::Result<Response> {
+    let method = request.method().to_string();
+    let started = Instant::now();
+
+    let response = next.get_response(request).await;
+
+    let operation = response.data::<OperationId>().map_or("unknown", |o| o.0);
+    let status = response.status();
+    let metrics = Metrics::get();
+    metrics
+        .http_requests_total
+        .with_label_values(&[operation, &method, status.as_str()])
+        .inc();
+    metrics
+        .http_request_duration_seconds
+        .with_label_values(&[operation, &method])
+        .observe(started.elapsed().as_secs_f64());
+
+    Ok(response)
+}
+
+/// Exports the metrics in Prometheus text format.
+///
+/// Pool usage is sampled at the time of the export.
+#[poem::handler]
+pub fn export(database: Data<&Database>) -> Response {
+    let metrics = Metrics::get();
+
+    let pool = database.pool_status();
+    let idle = pool.idle as i64;
+    let size = i64::from(pool.size);
+    metrics
+        .database_pool_connections
+        .with_label_values(&["idle"])
+        .set(idle);
+    metrics
+        .database_pool_connections
+        .with_label_values(&["in_use"])
+        .set(size - idle);
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if encoder.encode(&metrics.registry.gather(), &mut body).is_err() {
+        return poem::http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    Response::builder()
+        .header(CONTENT_TYPE, encoder.format_type())
+        .body(body)
+}
@@ -2,6 +2,8 @@ use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
 use tokio::task::spawn_blocking;
 
+use crate::metrics::Metrics;
+
 /// Hashes the given password with Argon2id version `0x13`-`19` with parameters
 /// `m_cost`=15000, `t_cost`=2, `p_cost`=1.
 ///
@@ -9,6 +11,7 @@ use tokio::task::spawn_blocking;
 /// `expect`s in the function should not cause any panics with possible inputs of the function.
 #[must_use]
 pub fn hash(password: &str) -> String {
+    let _timer = Metrics::get().time_password("hash");
     let salt = SaltString::generate(&mut rand::thread_rng());
     let params = Params::new(15000, 2, 1, None).expect("provided parameters should not throw");
     Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
@@ -36,6 +39,7 @@ pub async fn validate(password: String, hash: Option<String>) -> bool {
 /// Returns error if spawning blocking task fails or password verification fails for any reason.
 async fn compare(password: String, hash: String) -> Result<(), anyhow::Error> {
     spawn_blocking(move || {
+        let _timer = Metrics::get().time_password("verify");
         let hash = PasswordHash::new(&hash)?;
         Argon2::default().verify_password(password.as_bytes(), &hash)
     })
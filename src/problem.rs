@@ -0,0 +1,263 @@
+use std::fmt::{Display, Formatter};
+
+use poem::http::header::CONTENT_TYPE;
+use poem::http::{HeaderValue, StatusCode};
+use poem::IntoResponse;
+use poem_openapi::payload::Json;
+use poem_openapi::{Enum, Object};
+
+use crate::request_id::RequestId;
+
+/// Content type of error response bodies.
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Body of error responses, as described in RFC 7807.
+///
+/// Sent with `application/problem+json` content type.
+#[derive(Debug, serde::Deserialize, Object)]
+pub struct Problem {
+    /// URI identifying the problem type, derived from `code`.
+    #[oai(rename = "type")]
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Stable machine-readable code of the problem type.
+    pub code: ProblemCode,
+    /// Short summary of the problem type, does not change between occurrences.
+    pub title: String,
+    /// HTTP status code of the response.
+    pub status: u16,
+    /// Explanation specific to this occurrence of the problem.
+    pub detail: String,
+    /// Problems with individual fields of the request, only present for validation failures.
+    #[oai(skip_serializing_if_is_empty)]
+    #[serde(default)]
+    pub errors: Vec<FieldError>,
+    /// ID of the request, as returned in the `X-Request-Id` header.
+    #[oai(skip_serializing_if_is_none)]
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+impl Problem {
+    /// Creates a problem of given type with the ID of the request being handled, if any.
+    #[must_use]
+    pub fn new(code: ProblemCode, detail: &str) -> Self {
+        Self {
+            problem_type: code.uri(),
+            code,
+            title: code.title().to_owned(),
+            status: code.status().as_u16(),
+            detail: detail.to_owned(),
+            errors: Vec::new(),
+            request_id: RequestId::current().map(|id| id.as_str().to_owned()),
+        }
+    }
+
+    /// Creates an `invalid_credentials` problem listing the invalid fields.
+    #[must_use]
+    pub fn validation(errors: Vec<FieldError>) -> Self {
+        Self {
+            errors,
+            ..Self::new(
+                ProblemCode::InvalidCredentials,
+                "One or more fields of the request are invalid.",
+            )
+        }
+    }
+
+    /// Creates an `invalid_fields` problem listing the invalid fields.
+    #[must_use]
+    pub fn invalid_fields(errors: Vec<FieldError>) -> Self {
+        Self {
+            errors,
+            ..Self::new(
+                ProblemCode::InvalidFields,
+                "One or more fields of the request are invalid.",
+            )
+        }
+    }
+
+    /// Converts the problem into a poem-openapi JSON serializable type.
+    #[must_use]
+    pub const fn into_json(self) -> ProblemBody {
+        Json(self)
+    }
+}
+
+/// A problem body serializable to JSON by poem-openapi.
+///
+/// Response variants carrying it are declared with `application/problem+json` content type.
+pub type ProblemBody = Json<Problem>;
+
+/// Stable codes of problem types.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum ProblemCode {
+    /// The request is malformed, e.g. the body is not valid JSON or a field is missing.
+    InvalidRequest,
+    /// Registration credentials do not satisfy the restrictions.
+    InvalidCredentials,
+    /// Parameters or body fields other than credentials do not satisfy the restrictions.
+    InvalidFields,
+    /// No or an invalid session token is attached.
+    Unauthorized,
+    /// User identifier or password is wrong.
+    WrongCredentials,
+    /// A user with given credentials already exists.
+    AlreadyRegistered,
+    /// The user has a saved location with the same name.
+    LocationExists,
+    /// The user has saved as many locations as allowed.
+    LocationLimitReached,
+    /// The user has created as many alert rules as allowed.
+    AlertRuleLimitReached,
+    /// No resource exists at the requested path.
+    NotFound,
+    /// The caller's address is private, so it can not be geolocated.
+    PrivateAddress,
+    /// The server does not support the operation in its configuration.
+    NotSupported,
+    /// The server failed to handle the request.
+    InternalError,
+    /// A foreign API failed or responded with an error.
+    UpstreamError,
+    /// A foreign API is temporarily refusing calls.
+    UpstreamUnavailable,
+    /// A foreign API did not respond in time.
+    UpstreamTimeout,
+}
+
+impl ProblemCode {
+    /// Returns the code as written in responses.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::InvalidRequest => "invalid_request",
+            Self::InvalidCredentials => "invalid_credentials",
+            Self::InvalidFields => "invalid_fields",
+            Self::Unauthorized => "unauthorized",
+            Self::WrongCredentials => "wrong_credentials",
+            Self::AlreadyRegistered => "already_registered",
+            Self::LocationExists => "location_exists",
+            Self::LocationLimitReached => "location_limit_reached",
+            Self::AlertRuleLimitReached => "alert_rule_limit_reached",
+            Self::NotFound => "not_found",
+            Self::PrivateAddress => "private_address",
+            Self::NotSupported => "not_supported",
+            Self::InternalError => "internal_error",
+            Self::UpstreamError => "upstream_error",
+            Self::UpstreamUnavailable => "upstream_unavailable",
+            Self::UpstreamTimeout => "upstream_timeout",
+        }
+    }
+
+    /// Returns the URI of the problem type.
+    #[must_use]
+    pub fn uri(self) -> String {
+        format!("urn:weather-server-demo:problem:{}", self.as_str().replace('_', "-"))
+    }
+
+    /// Returns the short summary of the problem type.
+    #[must_use]
+    pub const fn title(self) -> &'static str {
+        match self {
+            Self::InvalidRequest => "Invalid request",
+            Self::InvalidCredentials => "Invalid credentials",
+            Self::InvalidFields => "Invalid fields",
+            Self::Unauthorized => "Unauthorized",
+            Self::WrongCredentials => "Wrong credentials",
+            Self::AlreadyRegistered => "Already registered",
+            Self::LocationExists => "Location exists",
+            Self::LocationLimitReached => "Location limit reached",
+            Self::AlertRuleLimitReached => "Alert rule limit reached",
+            Self::NotFound => "Not found",
+            Self::PrivateAddress => "Private address",
+            Self::NotSupported => "Not supported",
+            Self::InternalError => "Internal error",
+            Self::UpstreamError => "Foreign API error",
+            Self::UpstreamUnavailable => "Foreign API unavailable",
+            Self::UpstreamTimeout => "Foreign API timeout",
+        }
+    }
+
+    /// Returns the HTTP status responses of the problem type are sent with.
+    #[must_use]
+    pub const fn status(self) -> StatusCode {
+        match self {
+            Self::InvalidRequest | Self::InvalidCredentials | Self::InvalidFields => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::WrongCredentials | Self::NotFound => StatusCode::NOT_FOUND,
+            Self::AlreadyRegistered
+            | Self::LocationExists
+            | Self::LocationLimitReached
+            | Self::AlertRuleLimitReached => StatusCode::CONFLICT,
+            Self::PrivateAddress => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotSupported => StatusCode::NOT_IMPLEMENTED,
+            Self::UpstreamError => StatusCode::BAD_GATEWAY,
+            Self::UpstreamUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Self::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+}
+
+/// Problem with a single field of the request.
+#[derive(Debug, serde::Deserialize, Object)]
+pub struct FieldError {
+    /// Name of the field.
+    pub field: String,
+    /// What is wrong with the field.
+    pub message: String,
+}
+
+impl FieldError {
+    /// Creates a field error with given message.
+    #[must_use]
+    pub fn new(field: &str, message: &str) -> Self {
+        Self {
+            field: field.to_owned(),
+            message: message.to_owned(),
+        }
+    }
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Converts errors that are not handled by an endpoint, e.g. unknown paths, into problem responses.
+///
+/// The status of the error is kept, messages of server errors are replaced with a generic one.
+pub async fn error_response(error: poem::Error) -> poem::Response {
+    let status = error.status();
+    let code = match status {
+        StatusCode::NOT_FOUND => ProblemCode::NotFound,
+        StatusCode::UNAUTHORIZED => ProblemCode::Unauthorized,
+        s if s.is_server_error() => ProblemCode::InternalError,
+        _ => ProblemCode::InvalidRequest,
+    };
+
+    // Messages of server errors may reveal internals
+    let detail = if status.is_server_error() {
+        "The server failed to handle the request.".to_owned()
+    } else {
+        error.to_string()
+    };
+    let problem = Problem {
+        status: status.as_u16(),
+        ..Problem::new(code, &detail)
+    };
+
+    let mut response = Json(problem).into_response();
+    response.set_status(status);
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_CONTENT_TYPE));
+
+    response
+}
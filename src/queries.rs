@@ -1,66 +1,592 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
 use sqlx::error::ErrorKind;
-use sqlx::{Executor, Row, SqlitePool};
-
-/// Persists a user to the database.
-/// 
-/// Caller is responsible to hash the password correctly.
-/// 
-/// # Errors
-/// Will return error if any database error occurs
-pub async fn register_user(
-    database: &SqlitePool,
-    username: &str,
-    email: &str,
-    password: &str,
-) -> Result<u64, SqlError> {
-    let query = sqlx::query!(
-        r#"
-            INSERT INTO user (id, username, email, password)
-            VALUES (NULL, $1, $2, $3)
-            RETURNING id
-        "#,
-        username,
-        email,
-        password
-    );
-
-    let row = database.fetch_one(query).await.map_err(SqlError::from)?;
-    let user_id = row.get::<u64, &str>("id");
-
-    Ok(user_id)
-}
-
-/// Returns user ID and password of user matching the given username or email.
+use sqlx::migrate::Migrator;
+
+/// PostgreSQL implementation of the repository
+mod postgres;
+/// SQLite implementation of the repository
+mod sqlite;
+
+pub use postgres::PostgresRepository;
+pub use sqlite::SqliteRepository;
+pub(crate) use postgres::MIGRATOR as POSTGRES_MIGRATOR;
+pub(crate) use sqlite::MIGRATOR as SQLITE_MIGRATOR;
+
+/// Repository the server persists to, shared by handlers and background tasks.
+pub type Database = Arc<dyn Repository>;
+
+/// Persistence of users, their preferences, locations and alerts, the audit log and weather observations.
 ///
-/// If no user matches, a user ID of 0 and a None in place of a password is returned.
-/// This is so caller can use a placeholder password and continue password validation in the case
-/// user does not exist.
+/// Implemented for every supported database backend, selected by the scheme of the database URL.
+#[async_trait]
+pub trait Repository: Send + Sync {
+    /// Persists a user to the database, with the canonical forms of their username and email.
+    /// 
+    /// Caller is responsible to hash the password correctly.
+    /// 
+    /// # Errors
+    /// Will return error if any database error occurs,
+    /// `SqlError::UniqueConstraintViolation` if the canonical username or email is taken.
+    async fn register_user(
+        &self,
+        username: &str,
+        email: &str,
+        password: &str,
+    ) -> Result<u64, SqlError>;
+
+    /// Returns user ID and password of the active user matching the given username or email.
+    ///
+    /// Users are matched by the canonical forms of their username and email, see `canonical`.
+    /// A user whose canonical form is missing, as it collides with an older user's, is matched by the identity
+    /// exactly as registered instead, and is preferred over the older user then.
+    ///
+    /// If no user matches, or the matching user is disabled or deleted,
+    /// a user ID of 0 and a None in place of a password is returned.
+    /// This is so caller can use a placeholder password and continue password validation in the case
+    /// user does not exist.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn get_user_id_and_password_by_username_or_email(
+        &self,
+        username: &str,
+        email: &str,
+    ) -> (u64, Option<String>);
+
+    /// Returns ID, username, email and status of every registered user, deleted ones included, ordered by ID.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn list_users(&self) -> Result<Vec<UserSummary>, SqlError>;
+
+    /// Returns ID, username, email and status of the user with given ID, if such user exists.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn get_user_by_id(
+        &self,
+        user_id: u64,
+    ) -> Result<Option<UserSummary>, SqlError>;
+
+    /// Deletes the user with given ID, with their saved locations, alert rules, webhook and alert deliveries.
+    ///
+    /// Returns whether a user was deleted.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn delete_user(&self, user_id: u64) -> Result<bool, SqlError>;
+
+    /// Returns the users whose canonical username or email is not stored, ordered by ID.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn list_users_without_canonical_identity(&self) -> Result<Vec<UserSummary>, SqlError>;
+
+    /// Stores the canonical username of the user with given ID.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs,
+    /// `SqlError::UniqueConstraintViolation` if another user has the canonical username.
+    async fn set_canonical_username(&self, user_id: u64, username: &str) -> Result<(), SqlError>;
+
+    /// Stores the canonical email of the user with given ID.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs,
+    /// `SqlError::UniqueConstraintViolation` if another user has the canonical email.
+    async fn set_canonical_email(&self, user_id: u64, email: &str) -> Result<(), SqlError>;
+
+    /// Returns whether the user with given ID exists and is neither disabled nor deleted.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn is_user_active(&self, user_id: u64) -> Result<bool, SqlError>;
+
+    /// Sets the UNIX time the user with given ID is disabled at, `None` enables the user.
+    ///
+    /// Returns whether a user that is not deleted was updated.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn set_user_disabled(&self, user_id: u64, disabled_at: Option<i64>) -> Result<bool, SqlError>;
+
+    /// Sets the UNIX time the user with given ID is deleted at, `None` restores a deleted user.
+    ///
+    /// Returns whether a user was updated.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn set_user_deleted(&self, user_id: u64, deleted_at: Option<i64>) -> Result<bool, SqlError>;
+
+    /// Permanently deletes the users deleted before given UNIX time, with every row that belongs to them.
+    ///
+    /// Returns the IDs of the users deleted, ordered by ID.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn purge_deleted_users(&self, deleted_before: i64) -> Result<Vec<u64>, SqlError>;
+
+    /// Replaces the password of the user matching the canonical form of the given username or email,
+    /// matched as in `get_user_id_and_password_by_username_or_email`.
+    ///
+    /// Caller is responsible to hash the password correctly.
+    ///
+    /// Returns the ID of the updated user, if a user was updated.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn update_password_by_username_or_email(
+        &self,
+        username: &str,
+        email: &str,
+        password: &str,
+    ) -> Result<Option<u64>, SqlError>;
+
+    /// Returns the preferred unit system and language of the user with given ID, if such user exists.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn get_preferences(
+        &self,
+        user_id: u64,
+    ) -> Result<Option<Preferences>, SqlError>;
+
+    /// Replaces the preferred unit system and language of the user with given ID.
+    ///
+    /// Returns whether a user was updated.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn set_preferences(
+        &self,
+        user_id: u64,
+        preferences: &Preferences,
+    ) -> Result<bool, SqlError>;
+
+    /// Returns the saved locations of the user with given ID, ordered by ID.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn list_locations(
+        &self,
+        user_id: u64,
+    ) -> Result<Vec<SavedLocation>, SqlError>;
+
+    /// Returns the saved location with given ID, if it exists and belongs to the user with given ID.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn get_location(
+        &self,
+        user_id: u64,
+        location_id: u64,
+    ) -> Result<Option<SavedLocation>, SqlError>;
+
+    /// Returns the default location of the user with given ID, if they have one.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn get_default_location(
+        &self,
+        user_id: u64,
+    ) -> Result<Option<SavedLocation>, SqlError>;
+
+    /// Saves a location for the user with given ID and returns its ID,
+    /// or nothing if the user already saved `limit` locations.
+    ///
+    /// The limit is checked in the same transaction the location is saved in,
+    /// so concurrent calls can not exceed it.
+    /// If the location is the default, the previous default location of the user stops being one.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs,
+    /// `SqlError::UniqueConstraintViolation` if the user has a location with the same name.
+    async fn create_location(
+        &self,
+        user_id: u64,
+        location: &NewLocation,
+        limit: u64,
+    ) -> Result<Option<u64>, SqlError>;
+
+    /// Replaces the saved location with given ID, if it belongs to the user with given ID.
+    ///
+    /// If the location becomes the default, the previous default location of the user stops being one.
+    ///
+    /// Returns whether a location was updated.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs,
+    /// `SqlError::UniqueConstraintViolation` if the user has another location with the same name.
+    async fn update_location(
+        &self,
+        user_id: u64,
+        location_id: u64,
+        location: &NewLocation,
+    ) -> Result<bool, SqlError>;
+
+    /// Deletes the saved location with given ID, if it belongs to the user with given ID.
+    ///
+    /// Returns whether a location was deleted.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn delete_location(
+        &self,
+        user_id: u64,
+        location_id: u64,
+    ) -> Result<bool, SqlError>;
+
+    /// Returns the alert webhook of the user with given ID, if they configured one.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn get_webhook(&self, user_id: u64) -> Result<Option<Webhook>, SqlError>;
+
+    /// Sets the alert webhook of the user with given ID, replacing the previous one.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn set_webhook(&self, user_id: u64, webhook: &Webhook) -> Result<(), SqlError>;
+
+    /// Deletes the alert webhook of the user with given ID.
+    ///
+    /// Returns whether a webhook was deleted.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn delete_webhook(&self, user_id: u64) -> Result<bool, SqlError>;
+
+    /// Returns the alert rules of the user with given ID, ordered by ID.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn list_alert_rules(&self, user_id: u64) -> Result<Vec<AlertRule>, SqlError>;
+
+    /// Creates an alert rule for the user with given ID and returns its ID,
+    /// or nothing if the user already created `limit` alert rules.
+    ///
+    /// The limit is checked in the same statement the rule is created with, so concurrent calls can not exceed it.
+    /// The location of the rule is expected to belong to the user.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn create_alert_rule(
+        &self,
+        user_id: u64,
+        rule: &NewAlertRule,
+        limit: u64,
+    ) -> Result<Option<u64>, SqlError>;
+
+    /// Deletes the alert rule with given ID, if it belongs to the user with given ID.
+    ///
+    /// Returns whether a rule was deleted.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn delete_alert_rule(&self, user_id: u64, rule_id: u64) -> Result<bool, SqlError>;
+
+    /// Returns every alert rule whose user is active and configured a webhook, with its location and the webhook.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn list_scheduled_alert_rules(&self) -> Result<Vec<ScheduledAlertRule>, SqlError>;
+
+    /// Records whether the condition of the alert rule with given ID holds,
+    /// and the UNIX time it fired at if it just started holding.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn set_alert_rule_firing(
+        &self,
+        rule_id: u64,
+        firing: bool,
+        fired_at: Option<i64>,
+    ) -> Result<(), SqlError>;
+
+    /// Persists an attempt to deliver an alert.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn log_alert_delivery(&self, delivery: &AlertDelivery) -> Result<(), SqlError>;
+
+    /// Returns the latest alert delivery attempts of the user with given ID, newest first.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn list_alert_deliveries(
+        &self,
+        user_id: u64,
+        limit: u32,
+    ) -> Result<Vec<AlertDelivery>, SqlError>;
+
+    /// Appends an event to the audit log.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn log_audit_event(&self, event: &NewAuditEvent) -> Result<(), SqlError>;
+
+    /// Returns the audit events matching the filter, newest first.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn list_audit_events(
+        &self,
+        filter: &AuditFilter,
+        limit: u32,
+    ) -> Result<Vec<AuditEvent>, SqlError>;
+
+    /// Persists observations at given grid cell, keeping the stored observation of an hour if there is one.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn store_observations(
+        &self,
+        grid: GridCell,
+        observations: &[Observation],
+    ) -> Result<(), SqlError>;
+
+    /// Returns the observations at given grid cell within given UNIX time range, end exclusive, ordered by time.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn list_observations(
+        &self,
+        grid: GridCell,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<Observation>, SqlError>;
+
+    /// Runs a trivial query to check the database is reachable.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn ping(&self) -> Result<(), SqlError>;
+
+    /// Returns versions of the migrations that are successfully applied to the database.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs
+    async fn applied_migration_versions(&self) -> Result<Vec<i64>, SqlError>;
+
+    /// Writes a consistent copy of the database to a new file at given path, while the database stays in use.
+    ///
+    /// # Errors
+    /// Will return error if any database error occurs,
+    /// `SqlError::Unsupported` if the backend does not store the database in a file.
+    async fn backup(&self, destination: &Path) -> Result<(), SqlError>;
+
+    /// Returns the migrations embedded into the binary for the backend of the repository.
+    fn migrator(&self) -> &'static Migrator;
+
+    /// Returns the number of open and idle connections of the pool.
+    fn pool_status(&self) -> PoolStatus;
+
+    /// Closes every connection of the pool, waiting for checked out connections to be returned.
+    async fn close(&self);
+
+    /// Returns whether the pool is closed.
+    fn is_closed(&self) -> bool;
+}
+
+/// Connections of a database pool.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolStatus {
+    /// Open connections, idle or in use.
+    pub size: u32,
+    /// Connections waiting to be used.
+    pub idle: usize,
+}
+
+/// Identifying information and status of a user, without their password.
+#[derive(Debug)]
+pub struct UserSummary {
+    pub id: u64,
+    pub username: String,
+    pub email: String,
+    /// UNIX time the user is disabled at, if they are disabled.
+    pub disabled_at: Option<i64>,
+    /// UNIX time the user is deleted at, if they are deleted and not yet purged.
+    pub deleted_at: Option<i64>,
+}
+
+impl UserSummary {
+    /// Returns whether the user is neither disabled nor deleted.
+    #[must_use]
+    pub const fn is_active(&self) -> bool {
+        self.disabled_at.is_none() && self.deleted_at.is_none()
+    }
+}
+
+/// A location saved by a user.
+#[derive(Debug, Clone)]
+pub struct SavedLocation {
+    pub id: u64,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub is_default: bool,
+}
+
+/// A location to be saved, or to replace a saved one.
+#[derive(Debug)]
+pub struct NewLocation {
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub is_default: bool,
+}
+
+/// Webhook alerts of a user are delivered to.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub url: String,
+    /// Key of the HMAC signatures of payloads.
+    pub secret: String,
+}
+
+/// An alert rule created by a user.
+///
+/// `condition` is the name of an `alerts::AlertCondition`.
+#[derive(Debug, Clone)]
+pub struct AlertRule {
+    pub id: u64,
+    pub location_id: u64,
+    pub condition: String,
+    pub threshold: f64,
+    pub within_hours: Option<u8>,
+    /// Whether the condition held at the last evaluation.
+    pub firing: bool,
+    /// UNIX time the rule last fired at.
+    pub last_fired_at: Option<i64>,
+}
+
+/// An alert rule to be created.
+#[derive(Debug)]
+pub struct NewAlertRule {
+    pub location_id: u64,
+    pub condition: String,
+    pub threshold: f64,
+    pub within_hours: Option<u8>,
+}
+
+/// An alert rule to evaluate, with what is needed to evaluate it and deliver its alerts.
+#[derive(Debug, Clone)]
+pub struct ScheduledAlertRule {
+    pub user_id: u64,
+    pub rule: AlertRule,
+    pub location_name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub webhook: Webhook,
+}
+
+/// An attempt to deliver an alert to a webhook.
+#[derive(Debug)]
+pub struct AlertDelivery {
+    pub user_id: u64,
+    pub rule_id: u64,
+    /// ID of the alert, same for every attempt to deliver it.
+    pub event_id: String,
+    /// Number of the attempt, starting from 1.
+    pub attempt: u32,
+    /// Status the webhook responded with, if it responded.
+    pub status_code: Option<u16>,
+    /// Why the attempt failed, if it failed without a response.
+    pub error: Option<String>,
+    pub delivered: bool,
+    /// UNIX time of the attempt.
+    pub attempted_at: i64,
+}
+
+/// A security-relevant event of an account, in the audit log.
 ///
-/// # Errors
-/// Will return error if any database error occurs
-pub async fn get_user_id_and_password_by_username_or_email(
-    database: &SqlitePool,
-    username: &str,
-    email: &str,
-) -> (u64, Option<String>) {
-    let query = sqlx::query!(
-        r#"
-            SELECT id, password
-            FROM user
-            WHERE username = ? OR email = ?
-        "#,
-        username,
-        email
-    );
-
-    let Ok(row) = database.fetch_one(query).await.map_err(SqlError::from) else {
-        return (0u64, None);
-    };
-
-    let id = row.get::<u64, &str>("id");
-    let password = row.get::<String, &str>("password");
-
-    (id, Some(password))
+/// `event` is the name of an `audit::AuditEventKind` and `outcome` of an `audit::AuditOutcome`.
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub id: u64,
+    /// ID of the user the event is about, missing if no user is identified, e.g. for failed registrations.
+    pub user_id: Option<u64>,
+    pub event: String,
+    pub outcome: String,
+    /// Address of the client, missing for events of the command-line interface.
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// UNIX time of the event.
+    pub occurred_at: i64,
+}
+
+/// An event to be appended to the audit log.
+#[derive(Debug)]
+pub struct NewAuditEvent {
+    pub user_id: Option<u64>,
+    pub event: String,
+    pub outcome: String,
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// UNIX time of the event.
+    pub occurred_at: i64,
+}
+
+/// Criteria audit events are listed by, `None` matches every event.
+#[derive(Debug, Default, Clone)]
+pub struct AuditFilter {
+    pub user_id: Option<u64>,
+    pub event: Option<String>,
+    pub outcome: Option<String>,
+    pub client_ip: Option<String>,
+    /// UNIX time events occurred at or after.
+    pub from: Option<i64>,
+    /// UNIX time events occurred before.
+    pub to: Option<i64>,
+    /// ID events are older than, to continue listing after the last event of a previous page.
+    pub before_id: Option<u64>,
+}
+
+/// Cell of the grid observations are stored in, coordinates are in hundredths of a degree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridCell {
+    pub latitude: i64,
+    pub longitude: i64,
+}
+
+impl GridCell {
+    /// Returns the cell containing given coordinates, about a kilometer wide.
+    #[must_use]
+    pub fn containing(latitude: f64, longitude: f64) -> Self {
+        Self {
+            latitude: (latitude * 100.0).round() as i64,
+            longitude: (longitude * 100.0).round() as i64,
+        }
+    }
+}
+
+/// Weather observed during an hour at a grid cell, in metric units.
+#[derive(Debug, Clone)]
+pub struct Observation {
+    /// UNIX time of the start of the hour.
+    pub observed_at: i64,
+    pub temperature_c: f64,
+    pub feels_like_c: Option<f64>,
+    pub condition: Option<String>,
+    pub condition_code: Option<u16>,
+    pub wind_kph: Option<f64>,
+    pub humidity: Option<u8>,
+    pub pressure_mb: Option<f64>,
+    pub precip_mm: Option<f64>,
+    pub cloud: Option<u8>,
+    pub uv: Option<f64>,
+    /// Name of the provider that reported the observation.
+    pub provider: String,
+}
+
+/// Preferred unit system and language of a user, `None` if not chosen.
+#[derive(Debug, Default)]
+pub struct Preferences {
+    pub units: Option<String>,
+    pub lang: Option<String>,
 }
 
 /// Error derived from `sqlx::Error`, that allows caller of register query function understand user
@@ -68,6 +594,8 @@ pub async fn get_user_id_and_password_by_username_or_email(
 #[derive(Debug)]
 pub enum SqlError {
     UniqueConstraintViolation,
+    /// The operation is not supported by the database backend.
+    Unsupported,
     Other, // Wrap sqlx::Error inside if more context is needed
 }
 
@@ -0,0 +1,942 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::PgRow;
+use sqlx::{Executor, PgPool, Postgres, Row, Transaction};
+
+use super::{
+    AlertDelivery, AlertRule, AuditEvent, AuditFilter, GridCell, NewAlertRule, NewAuditEvent, NewLocation,
+    Observation, PoolStatus, Preferences, Repository, SavedLocation, ScheduledAlertRule, SqlError, UserSummary,
+    Webhook,
+};
+use crate::canonical;
+use crate::metrics::Metrics;
+
+/// Migrations of PostgreSQL databases, embedded into the binary.
+pub(crate) static MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+/// Repository persisting to a PostgreSQL database.
+///
+/// Queries are not checked at compile time, as builds only have a SQLite database to check against.
+/// PostgreSQL has no unsigned integers, so IDs are stored as `BIGINT` and small numbers as `SMALLINT`
+/// or `INTEGER`.
+pub struct PostgresRepository {
+    pool: PgPool,
+}
+
+impl PostgresRepository {
+    /// Wraps a connection pool of a migrated database.
+    #[must_use]
+    pub const fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl Repository for PostgresRepository {
+    async fn register_user(&self, username: &str, email: &str, password: &str) -> Result<u64, SqlError> {
+        let _timer = Metrics::get().time_query("register_user");
+        let query = sqlx::query(
+            r#"
+                INSERT INTO "user" (username, email, password, username_canonical, email_canonical)
+                VALUES ($1, $2, $3, $4, $5)
+                RETURNING id
+            "#,
+        )
+        .bind(username)
+        .bind(email)
+        .bind(password)
+        .bind(canonical::username(username))
+        .bind(canonical::email(email));
+
+        let row = self.pool.fetch_one(query).await.map_err(SqlError::from)?;
+
+        Ok(row.get::<i64, &str>("id") as u64)
+    }
+
+    async fn get_user_id_and_password_by_username_or_email(
+        &self,
+        username: &str,
+        email: &str,
+    ) -> (u64, Option<String>) {
+        let _timer = Metrics::get().time_query("get_user_id_and_password_by_username_or_email");
+        // Users whose canonical form collides with an older user's are matched as registered, preferring them
+        let query = sqlx::query(
+            r#"
+                SELECT id, password
+                FROM "user"
+                WHERE (
+                    username_canonical = $1 OR email_canonical = $2
+                    OR (username_canonical IS NULL AND username = $3) OR (email_canonical IS NULL AND email = $4)
+                ) AND disabled_at IS NULL AND deleted_at IS NULL
+                ORDER BY (username = $3 OR email = $4) DESC, id
+                LIMIT 1
+            "#,
+        )
+        .bind(canonical::username(username))
+        .bind(canonical::email(email))
+        .bind(username)
+        .bind(email);
+
+        let Ok(row) = self.pool.fetch_one(query).await.map_err(SqlError::from) else {
+            return (0u64, None);
+        };
+
+        let id = row.get::<i64, &str>("id") as u64;
+        let password = row.get::<String, &str>("password");
+
+        (id, Some(password))
+    }
+
+    async fn list_users(&self) -> Result<Vec<UserSummary>, SqlError> {
+        let _timer = Metrics::get().time_query("list_users");
+        let query = sqlx::query(
+            r#"
+                SELECT id, username, email, disabled_at, deleted_at
+                FROM "user"
+                ORDER BY id
+            "#,
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        Ok(rows.iter().map(user_summary).collect())
+    }
+
+    async fn get_user_by_id(&self, user_id: u64) -> Result<Option<UserSummary>, SqlError> {
+        let _timer = Metrics::get().time_query("get_user_by_id");
+        let query = sqlx::query(
+            r#"
+                SELECT id, username, email, disabled_at, deleted_at
+                FROM "user"
+                WHERE id = $1
+            "#,
+        )
+        .bind(user_id as i64);
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.as_ref().map(user_summary))
+    }
+
+    async fn delete_user(&self, user_id: u64) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("delete_user");
+        // Rows of the user are deleted by cascading foreign keys, as in `purge_deleted_users`
+        let query = sqlx::query(
+            r#"
+                DELETE FROM "user"
+                WHERE id = $1
+            "#,
+        )
+        .bind(user_id as i64);
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_users_without_canonical_identity(&self) -> Result<Vec<UserSummary>, SqlError> {
+        let _timer = Metrics::get().time_query("list_users_without_canonical_identity");
+        let query = sqlx::query(
+            r#"
+                SELECT id, username, email, disabled_at, deleted_at
+                FROM "user"
+                WHERE username_canonical IS NULL OR email_canonical IS NULL
+                ORDER BY id
+            "#,
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        Ok(rows.iter().map(user_summary).collect())
+    }
+
+    async fn set_canonical_username(&self, user_id: u64, username: &str) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("set_canonical_username");
+        let query = sqlx::query(
+            r#"
+                UPDATE "user"
+                SET username_canonical = $1
+                WHERE id = $2
+            "#,
+        )
+        .bind(username)
+        .bind(user_id as i64);
+
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn set_canonical_email(&self, user_id: u64, email: &str) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("set_canonical_email");
+        let query = sqlx::query(
+            r#"
+                UPDATE "user"
+                SET email_canonical = $1
+                WHERE id = $2
+            "#,
+        )
+        .bind(email)
+        .bind(user_id as i64);
+
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn is_user_active(&self, user_id: u64) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("is_user_active");
+        let query = sqlx::query(
+            r#"
+                SELECT id
+                FROM "user"
+                WHERE id = $1 AND disabled_at IS NULL AND deleted_at IS NULL
+            "#,
+        )
+        .bind(user_id as i64);
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.is_some())
+    }
+
+    async fn set_user_disabled(&self, user_id: u64, disabled_at: Option<i64>) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("set_user_disabled");
+        let query = sqlx::query(
+            r#"
+                UPDATE "user"
+                SET disabled_at = $1
+                WHERE id = $2 AND deleted_at IS NULL
+            "#,
+        )
+        .bind(disabled_at)
+        .bind(user_id as i64);
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_user_deleted(&self, user_id: u64, deleted_at: Option<i64>) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("set_user_deleted");
+        let query = sqlx::query(
+            r#"
+                UPDATE "user"
+                SET deleted_at = $1
+                WHERE id = $2
+            "#,
+        )
+        .bind(deleted_at)
+        .bind(user_id as i64);
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn purge_deleted_users(&self, deleted_before: i64) -> Result<Vec<u64>, SqlError> {
+        let _timer = Metrics::get().time_query("purge_deleted_users");
+        // Rows of the users are deleted by cascading foreign keys, which PostgreSQL always enforces
+        let query = sqlx::query(
+            r#"
+                DELETE FROM "user"
+                WHERE deleted_at < $1
+                RETURNING id
+            "#,
+        )
+        .bind(deleted_before);
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+        let mut purged: Vec<u64> = rows.iter().map(|row| row.get::<i64, &str>("id") as u64).collect();
+        purged.sort_unstable();
+
+        Ok(purged)
+    }
+
+    async fn update_password_by_username_or_email(
+        &self,
+        username: &str,
+        email: &str,
+        password: &str,
+    ) -> Result<Option<u64>, SqlError> {
+        let _timer = Metrics::get().time_query("update_password_by_username_or_email");
+        let query = sqlx::query(
+            r#"
+                UPDATE "user"
+                SET password = $1
+                WHERE id = (
+                    SELECT id
+                    FROM "user"
+                    WHERE username_canonical = $2 OR email_canonical = $3
+                        OR (username_canonical IS NULL AND username = $4) OR (email_canonical IS NULL AND email = $5)
+                    ORDER BY (username = $4 OR email = $5) DESC, id
+                    LIMIT 1
+                )
+                RETURNING id
+            "#,
+        )
+        .bind(password)
+        .bind(canonical::username(username))
+        .bind(canonical::email(email))
+        .bind(username)
+        .bind(email);
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.map(|row| row.get::<i64, &str>("id") as u64))
+    }
+
+    async fn get_preferences(&self, user_id: u64) -> Result<Option<Preferences>, SqlError> {
+        let _timer = Metrics::get().time_query("get_preferences");
+        let query = sqlx::query(
+            r#"
+                SELECT units, lang
+                FROM "user"
+                WHERE id = $1
+            "#,
+        )
+        .bind(user_id as i64);
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+        let preferences = row.map(|row| Preferences {
+            units: row.get::<Option<String>, &str>("units"),
+            lang: row.get::<Option<String>, &str>("lang"),
+        });
+
+        Ok(preferences)
+    }
+
+    async fn set_preferences(&self, user_id: u64, preferences: &Preferences) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("set_preferences");
+        let query = sqlx::query(
+            r#"
+                UPDATE "user"
+                SET units = $1, lang = $2
+                WHERE id = $3
+            "#,
+        )
+        .bind(preferences.units.as_deref())
+        .bind(preferences.lang.as_deref())
+        .bind(user_id as i64);
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_locations(&self, user_id: u64) -> Result<Vec<SavedLocation>, SqlError> {
+        let _timer = Metrics::get().time_query("list_locations");
+        let query = sqlx::query(
+            r#"
+                SELECT id, name, latitude, longitude, is_default
+                FROM location
+                WHERE user_id = $1
+                ORDER BY id
+            "#,
+        )
+        .bind(user_id as i64);
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        Ok(rows.iter().map(saved_location).collect())
+    }
+
+    async fn get_location(&self, user_id: u64, location_id: u64) -> Result<Option<SavedLocation>, SqlError> {
+        let _timer = Metrics::get().time_query("get_location");
+        let query = sqlx::query(
+            r#"
+                SELECT id, name, latitude, longitude, is_default
+                FROM location
+                WHERE id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(location_id as i64)
+        .bind(user_id as i64);
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.as_ref().map(saved_location))
+    }
+
+    async fn get_default_location(&self, user_id: u64) -> Result<Option<SavedLocation>, SqlError> {
+        let _timer = Metrics::get().time_query("get_default_location");
+        let query = sqlx::query(
+            r#"
+                SELECT id, name, latitude, longitude, is_default
+                FROM location
+                WHERE user_id = $1 AND is_default
+            "#,
+        )
+        .bind(user_id as i64);
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.as_ref().map(saved_location))
+    }
+
+    async fn create_location(&self, user_id: u64, location: &NewLocation, limit: u64) -> Result<Option<u64>, SqlError> {
+        let _timer = Metrics::get().time_query("create_location");
+        let user_id = user_id as i64;
+        let mut transaction = self.pool.begin().await.map_err(SqlError::from)?;
+        lock_user(&mut transaction, user_id).await?;
+
+        if location.is_default {
+            clear_default_location(&mut transaction, user_id).await?;
+        }
+
+        let query = sqlx::query(
+            r#"
+                INSERT INTO location (user_id, name, latitude, longitude, is_default)
+                SELECT $1, $2, $3, $4, $5
+                WHERE (SELECT COUNT(*) FROM location WHERE user_id = $1) < $6
+                RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .bind(&location.name)
+        .bind(location.latitude)
+        .bind(location.longitude)
+        .bind(location.is_default)
+        .bind(limit as i64);
+        let row = transaction.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        // Rolls back clearing the previous default if the limit is reached
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        transaction.commit().await.map_err(SqlError::from)?;
+
+        Ok(Some(row.get::<i64, &str>("id") as u64))
+    }
+
+    async fn update_location(&self, user_id: u64, location_id: u64, location: &NewLocation) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("update_location");
+        let user_id = user_id as i64;
+        let mut transaction = self.pool.begin().await.map_err(SqlError::from)?;
+
+        if location.is_default {
+            clear_default_location(&mut transaction, user_id).await?;
+        }
+
+        let query = sqlx::query(
+            r#"
+                UPDATE location
+                SET name = $1, latitude = $2, longitude = $3, is_default = $4
+                WHERE id = $5 AND user_id = $6
+            "#,
+        )
+        .bind(&location.name)
+        .bind(location.latitude)
+        .bind(location.longitude)
+        .bind(location.is_default)
+        .bind(location_id as i64)
+        .bind(user_id);
+        let result = transaction.execute(query).await.map_err(SqlError::from)?;
+
+        // Rolls back clearing the previous default if no location is updated
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        transaction.commit().await.map_err(SqlError::from)?;
+
+        Ok(true)
+    }
+
+    async fn delete_location(&self, user_id: u64, location_id: u64) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("delete_location");
+        let query = sqlx::query(
+            r#"
+                DELETE FROM location
+                WHERE id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(location_id as i64)
+        .bind(user_id as i64);
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_webhook(&self, user_id: u64) -> Result<Option<Webhook>, SqlError> {
+        let _timer = Metrics::get().time_query("get_webhook");
+        let query = sqlx::query(
+            r#"
+                SELECT url, secret
+                FROM alert_webhook
+                WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id as i64);
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.as_ref().map(webhook))
+    }
+
+    async fn set_webhook(&self, user_id: u64, webhook: &Webhook) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("set_webhook");
+        let query = sqlx::query(
+            r#"
+                INSERT INTO alert_webhook (user_id, url, secret)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (user_id) DO UPDATE SET url = excluded.url, secret = excluded.secret
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(&webhook.url)
+        .bind(&webhook.secret);
+
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn delete_webhook(&self, user_id: u64) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("delete_webhook");
+        let query = sqlx::query(
+            r#"
+                DELETE FROM alert_webhook
+                WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id as i64);
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_alert_rules(&self, user_id: u64) -> Result<Vec<AlertRule>, SqlError> {
+        let _timer = Metrics::get().time_query("list_alert_rules");
+        let query = sqlx::query(
+            r#"
+                SELECT id, location_id, condition, threshold, within_hours, firing, last_fired_at
+                FROM alert_rule
+                WHERE user_id = $1
+                ORDER BY id
+            "#,
+        )
+        .bind(user_id as i64);
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        Ok(rows.iter().map(alert_rule).collect())
+    }
+
+    async fn create_alert_rule(&self, user_id: u64, rule: &NewAlertRule, limit: u64) -> Result<Option<u64>, SqlError> {
+        let _timer = Metrics::get().time_query("create_alert_rule");
+        let user_id = user_id as i64;
+        let mut transaction = self.pool.begin().await.map_err(SqlError::from)?;
+        lock_user(&mut transaction, user_id).await?;
+
+        let query = sqlx::query(
+            r#"
+                INSERT INTO alert_rule (user_id, location_id, condition, threshold, within_hours)
+                SELECT $1, $2, $3, $4, $5
+                WHERE (SELECT COUNT(*) FROM alert_rule WHERE user_id = $1) < $6
+                RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .bind(rule.location_id as i64)
+        .bind(&rule.condition)
+        .bind(rule.threshold)
+        .bind(rule.within_hours.map(i16::from))
+        .bind(limit as i64);
+        let row = transaction.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        transaction.commit().await.map_err(SqlError::from)?;
+
+        Ok(row.map(|row| row.get::<i64, &str>("id") as u64))
+    }
+
+    async fn delete_alert_rule(&self, user_id: u64, rule_id: u64) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("delete_alert_rule");
+        let query = sqlx::query(
+            r#"
+                DELETE FROM alert_rule
+                WHERE id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(rule_id as i64)
+        .bind(user_id as i64);
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_scheduled_alert_rules(&self) -> Result<Vec<ScheduledAlertRule>, SqlError> {
+        let _timer = Metrics::get().time_query("list_scheduled_alert_rules");
+        let query = sqlx::query(
+            r#"
+                SELECT r.id, r.user_id, r.location_id, r.condition, r.threshold, r.within_hours, r.firing,
+                       r.last_fired_at, l.name, l.latitude, l.longitude, w.url, w.secret
+                FROM alert_rule r
+                INNER JOIN location l ON l.id = r.location_id
+                INNER JOIN alert_webhook w ON w.user_id = r.user_id
+                INNER JOIN "user" u ON u.id = r.user_id
+                WHERE u.disabled_at IS NULL AND u.deleted_at IS NULL
+                ORDER BY r.location_id, r.id
+            "#,
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        let rules = rows
+            .iter()
+            .map(|row| ScheduledAlertRule {
+                user_id: row.get::<i64, &str>("user_id") as u64,
+                rule: alert_rule(row),
+                location_name: row.get::<String, &str>("name"),
+                latitude: row.get::<f64, &str>("latitude"),
+                longitude: row.get::<f64, &str>("longitude"),
+                webhook: webhook(row),
+            })
+            .collect();
+
+        Ok(rules)
+    }
+
+    async fn set_alert_rule_firing(&self, rule_id: u64, firing: bool, fired_at: Option<i64>) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("set_alert_rule_firing");
+        let query = sqlx::query(
+            r#"
+                UPDATE alert_rule
+                SET firing = $1, last_fired_at = COALESCE($2, last_fired_at)
+                WHERE id = $3
+            "#,
+        )
+        .bind(firing)
+        .bind(fired_at)
+        .bind(rule_id as i64);
+
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn log_alert_delivery(&self, delivery: &AlertDelivery) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("log_alert_delivery");
+        let query = sqlx::query(
+            r#"
+                INSERT INTO alert_delivery
+                    (user_id, rule_id, event_id, attempt, status_code, error, delivered, attempted_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(delivery.user_id as i64)
+        .bind(delivery.rule_id as i64)
+        .bind(&delivery.event_id)
+        .bind(delivery.attempt as i32)
+        .bind(delivery.status_code.map(i32::from))
+        .bind(delivery.error.as_deref())
+        .bind(delivery.delivered)
+        .bind(delivery.attempted_at);
+
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn list_alert_deliveries(&self, user_id: u64, limit: u32) -> Result<Vec<AlertDelivery>, SqlError> {
+        let _timer = Metrics::get().time_query("list_alert_deliveries");
+        let query = sqlx::query(
+            r#"
+                SELECT user_id, rule_id, event_id, attempt, status_code, error, delivered, attempted_at
+                FROM alert_delivery
+                WHERE user_id = $1
+                ORDER BY id DESC
+                LIMIT $2
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(i64::from(limit));
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        let deliveries = rows
+            .iter()
+            .map(|row| AlertDelivery {
+                user_id: row.get::<i64, &str>("user_id") as u64,
+                rule_id: row.get::<i64, &str>("rule_id") as u64,
+                event_id: row.get::<String, &str>("event_id"),
+                attempt: row.get::<i32, &str>("attempt") as u32,
+                status_code: row.get::<Option<i32>, &str>("status_code").map(|code| code as u16),
+                error: row.get::<Option<String>, &str>("error"),
+                delivered: row.get::<bool, &str>("delivered"),
+                attempted_at: row.get::<i64, &str>("attempted_at"),
+            })
+            .collect();
+
+        Ok(deliveries)
+    }
+
+    async fn log_audit_event(&self, event: &NewAuditEvent) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("log_audit_event");
+        let query = sqlx::query(
+            r#"
+                INSERT INTO audit_event (user_id, event, outcome, client_ip, user_agent, occurred_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(event.user_id.map(|id| id as i64))
+        .bind(&event.event)
+        .bind(&event.outcome)
+        .bind(event.client_ip.as_deref())
+        .bind(event.user_agent.as_deref())
+        .bind(event.occurred_at);
+
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn list_audit_events(&self, filter: &AuditFilter, limit: u32) -> Result<Vec<AuditEvent>, SqlError> {
+        let _timer = Metrics::get().time_query("list_audit_events");
+        let query = sqlx::query(
+            r#"
+                SELECT id, user_id, event, outcome, client_ip, user_agent, occurred_at
+                FROM audit_event
+                WHERE ($1::BIGINT IS NULL OR user_id = $1)
+                    AND ($2::TEXT IS NULL OR event = $2)
+                    AND ($3::TEXT IS NULL OR outcome = $3)
+                    AND ($4::TEXT IS NULL OR client_ip = $4)
+                    AND ($5::BIGINT IS NULL OR occurred_at >= $5)
+                    AND ($6::BIGINT IS NULL OR occurred_at < $6)
+                    AND ($7::BIGINT IS NULL OR id < $7)
+                ORDER BY id DESC
+                LIMIT $8
+            "#,
+        )
+        .bind(filter.user_id.map(|id| id as i64))
+        .bind(filter.event.as_deref())
+        .bind(filter.outcome.as_deref())
+        .bind(filter.client_ip.as_deref())
+        .bind(filter.from)
+        .bind(filter.to)
+        .bind(filter.before_id.map(|id| id as i64))
+        .bind(i64::from(limit));
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        let events = rows
+            .iter()
+            .map(|row| AuditEvent {
+                id: row.get::<i64, &str>("id") as u64,
+                user_id: row.get::<Option<i64>, &str>("user_id").map(|id| id as u64),
+                event: row.get::<String, &str>("event"),
+                outcome: row.get::<String, &str>("outcome"),
+                client_ip: row.get::<Option<String>, &str>("client_ip"),
+                user_agent: row.get::<Option<String>, &str>("user_agent"),
+                occurred_at: row.get::<i64, &str>("occurred_at"),
+            })
+            .collect();
+
+        Ok(events)
+    }
+
+    async fn store_observations(&self, grid: GridCell, observations: &[Observation]) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("store_observations");
+        let mut transaction = self.pool.begin().await.map_err(SqlError::from)?;
+
+        for observation in observations {
+            let query = sqlx::query(
+                r#"
+                    INSERT INTO observation (
+                        grid_latitude, grid_longitude, observed_at, temperature_c, feels_like_c, condition,
+                        condition_code, wind_kph, humidity, pressure_mb, precip_mm, cloud, uv, provider
+                    )
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+                    ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(grid.latitude)
+            .bind(grid.longitude)
+            .bind(observation.observed_at)
+            .bind(observation.temperature_c)
+            .bind(observation.feels_like_c)
+            .bind(observation.condition.as_deref())
+            .bind(observation.condition_code.map(i32::from))
+            .bind(observation.wind_kph)
+            .bind(observation.humidity.map(i16::from))
+            .bind(observation.pressure_mb)
+            .bind(observation.precip_mm)
+            .bind(observation.cloud.map(i16::from))
+            .bind(observation.uv)
+            .bind(&observation.provider);
+            transaction.execute(query).await.map_err(SqlError::from)?;
+        }
+
+        transaction.commit().await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn list_observations(&self, grid: GridCell, from: i64, to: i64) -> Result<Vec<Observation>, SqlError> {
+        let _timer = Metrics::get().time_query("list_observations");
+        let query = sqlx::query(
+            r#"
+                SELECT observed_at, temperature_c, feels_like_c, condition, condition_code, wind_kph, humidity,
+                       pressure_mb, precip_mm, cloud, uv, provider
+                FROM observation
+                WHERE grid_latitude = $1 AND grid_longitude = $2 AND observed_at >= $3 AND observed_at < $4
+                ORDER BY observed_at
+            "#,
+        )
+        .bind(grid.latitude)
+        .bind(grid.longitude)
+        .bind(from)
+        .bind(to);
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        let observations = rows
+            .iter()
+            .map(|row| Observation {
+                observed_at: row.get::<i64, &str>("observed_at"),
+                temperature_c: row.get::<f64, &str>("temperature_c"),
+                feels_like_c: row.get::<Option<f64>, &str>("feels_like_c"),
+                condition: row.get::<Option<String>, &str>("condition"),
+                condition_code: row.get::<Option<i32>, &str>("condition_code").map(|code| code as u16),
+                wind_kph: row.get::<Option<f64>, &str>("wind_kph"),
+                humidity: row.get::<Option<i16>, &str>("humidity").map(|humidity| humidity as u8),
+                pressure_mb: row.get::<Option<f64>, &str>("pressure_mb"),
+                precip_mm: row.get::<Option<f64>, &str>("precip_mm"),
+                cloud: row.get::<Option<i16>, &str>("cloud").map(|cloud| cloud as u8),
+                uv: row.get::<Option<f64>, &str>("uv"),
+                provider: row.get::<String, &str>("provider"),
+            })
+            .collect();
+
+        Ok(observations)
+    }
+
+    async fn ping(&self) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("ping");
+        self.pool.execute("SELECT 1").await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn applied_migration_versions(&self) -> Result<Vec<i64>, SqlError> {
+        let _timer = Metrics::get().time_query("applied_migration_versions");
+        let query = sqlx::query(
+            r#"
+                SELECT version
+                FROM _sqlx_migrations
+                WHERE success = TRUE
+                ORDER BY version
+            "#,
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+        let versions = rows.iter().map(|row| row.get::<i64, &str>("version")).collect();
+
+        Ok(versions)
+    }
+
+    async fn backup(&self, _destination: &Path) -> Result<(), SqlError> {
+        // PostgreSQL databases are backed up by the server's own tooling, e.g. `pg_dump`
+        Err(SqlError::Unsupported)
+    }
+
+    fn migrator(&self) -> &'static Migrator {
+        &MIGRATOR
+    }
+
+    fn pool_status(&self) -> PoolStatus {
+        PoolStatus {
+            size: self.pool.size(),
+            idle: self.pool.num_idle(),
+        }
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    fn is_closed(&self) -> bool {
+        self.pool.is_closed()
+    }
+}
+
+/// Locks the row of the user with given ID until the transaction ends, so the user's rows counted
+/// against a limit can not be added to concurrently, as `READ COMMITTED` transactions do not see each other's.
+async fn lock_user(transaction: &mut Transaction<'_, Postgres>, user_id: i64) -> Result<(), SqlError> {
+    let query = sqlx::query(
+        r#"
+            SELECT id
+            FROM "user"
+            WHERE id = $1
+            FOR UPDATE
+        "#,
+    )
+    .bind(user_id);
+    transaction.fetch_optional(query).await.map_err(SqlError::from)?;
+
+    Ok(())
+}
+
+/// Makes no location of the user the default.
+async fn clear_default_location(transaction: &mut Transaction<'_, Postgres>, user_id: i64) -> Result<(), SqlError> {
+    let query = sqlx::query(
+        r#"
+            UPDATE location
+            SET is_default = FALSE
+            WHERE user_id = $1 AND is_default
+        "#,
+    )
+    .bind(user_id);
+    transaction.execute(query).await.map_err(SqlError::from)?;
+
+    Ok(())
+}
+
+/// Reads a user from a row with the `id`, `username`, `email`, `disabled_at` and `deleted_at` columns of `user` table.
+fn user_summary(row: &PgRow) -> UserSummary {
+    UserSummary {
+        id: row.get::<i64, &str>("id") as u64,
+        username: row.get::<String, &str>("username"),
+        email: row.get::<String, &str>("email"),
+        disabled_at: row.get::<Option<i64>, &str>("disabled_at"),
+        deleted_at: row.get::<Option<i64>, &str>("deleted_at"),
+    }
+}
+
+/// Reads a location from a row with the columns of `location` table, except `user_id`.
+fn saved_location(row: &PgRow) -> SavedLocation {
+    SavedLocation {
+        id: row.get::<i64, &str>("id") as u64,
+        name: row.get::<String, &str>("name"),
+        latitude: row.get::<f64, &str>("latitude"),
+        longitude: row.get::<f64, &str>("longitude"),
+        is_default: row.get::<bool, &str>("is_default"),
+    }
+}
+
+/// Reads a rule from a row with the columns of `alert_rule` table, except `user_id`.
+fn alert_rule(row: &PgRow) -> AlertRule {
+    AlertRule {
+        id: row.get::<i64, &str>("id") as u64,
+        location_id: row.get::<i64, &str>("location_id") as u64,
+        condition: row.get::<String, &str>("condition"),
+        threshold: row.get::<f64, &str>("threshold"),
+        within_hours: row.get::<Option<i16>, &str>("within_hours").map(|hours| hours as u8),
+        firing: row.get::<bool, &str>("firing"),
+        last_fired_at: row.get::<Option<i64>, &str>("last_fired_at"),
+    }
+}
+
+/// Reads a webhook from a row with the `url` and `secret` columns of `alert_webhook` table.
+fn webhook(row: &PgRow) -> Webhook {
+    Webhook {
+        url: row.get::<String, &str>("url"),
+        secret: row.get::<String, &str>("secret"),
+    }
+}
@@ -0,0 +1,1094 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Executor, Row, Sqlite, SqlitePool, Transaction};
+
+use super::{
+    AlertDelivery, AlertRule, AuditEvent, AuditFilter, GridCell, NewAlertRule, NewAuditEvent, NewLocation,
+    Observation, PoolStatus, Preferences, Repository, SavedLocation, ScheduledAlertRule, SqlError, UserSummary,
+    Webhook,
+};
+use crate::canonical;
+use crate::metrics::Metrics;
+
+/// Migrations of SQLite databases, embedded into the binary.
+pub(crate) static MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+/// Repository persisting to a SQLite database.
+///
+/// Queries are checked at compile time against the database `DATABASE_URL` points to.
+pub struct SqliteRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteRepository {
+    /// Wraps a connection pool of a migrated database.
+    #[must_use]
+    pub const fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl Repository for SqliteRepository {
+    async fn register_user(
+        &self,
+        username: &str,
+        email: &str,
+        password: &str,
+    ) -> Result<u64, SqlError> {
+        let _timer = Metrics::get().time_query("register_user");
+        let username_canonical = canonical::username(username);
+        let email_canonical = canonical::email(email);
+        let query = sqlx::query!(
+            r#"
+                INSERT INTO user (id, username, email, password, username_canonical, email_canonical)
+                VALUES (NULL, $1, $2, $3, $4, $5)
+                RETURNING id
+            "#,
+            username,
+            email,
+            password,
+            username_canonical,
+            email_canonical
+        );
+
+        let row = self.pool.fetch_one(query).await.map_err(SqlError::from)?;
+        let user_id = row.get::<u64, &str>("id");
+
+        Ok(user_id)
+    }
+
+    async fn get_user_id_and_password_by_username_or_email(
+        &self,
+        username: &str,
+        email: &str,
+    ) -> (u64, Option<String>) {
+        let _timer = Metrics::get().time_query("get_user_id_and_password_by_username_or_email");
+        let username_canonical = canonical::username(username);
+        let email_canonical = canonical::email(email);
+        // Users whose canonical form collides with an older user's are matched as registered, preferring them
+        let query = sqlx::query!(
+            r#"
+                SELECT id, password
+                FROM user
+                WHERE (
+                    username_canonical = ? OR email_canonical = ?
+                    OR (username_canonical IS NULL AND username = ?) OR (email_canonical IS NULL AND email = ?)
+                ) AND disabled_at IS NULL AND deleted_at IS NULL
+                ORDER BY (username = ? OR email = ?) DESC, id
+                LIMIT 1
+            "#,
+            username_canonical,
+            email_canonical,
+            username,
+            email,
+            username,
+            email
+        );
+
+        let Ok(row) = self.pool.fetch_one(query).await.map_err(SqlError::from) else {
+            return (0u64, None);
+        };
+
+        let id = row.get::<u64, &str>("id");
+        let password = row.get::<String, &str>("password");
+
+        (id, Some(password))
+    }
+
+    async fn list_users(&self) -> Result<Vec<UserSummary>, SqlError> {
+        let _timer = Metrics::get().time_query("list_users");
+        let query = sqlx::query!(
+            r#"
+                SELECT id, username, email, disabled_at, deleted_at
+                FROM user
+                ORDER BY id
+            "#
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+        let users = rows.iter().map(user_summary).collect();
+
+        Ok(users)
+    }
+
+    async fn get_user_by_id(
+        &self,
+        user_id: u64,
+    ) -> Result<Option<UserSummary>, SqlError> {
+        let _timer = Metrics::get().time_query("get_user_by_id");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                SELECT id, username, email, disabled_at, deleted_at
+                FROM user
+                WHERE id = ?
+            "#,
+            user_id
+        );
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.as_ref().map(user_summary))
+    }
+
+    async fn delete_user(&self, user_id: u64) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("delete_user");
+        let mut transaction = self.pool.begin().await.map_err(SqlError::from)?;
+        let deleted = delete_user_rows(&mut transaction, user_id as i64).await?;
+        transaction.commit().await.map_err(SqlError::from)?;
+
+        Ok(deleted)
+    }
+
+    async fn list_users_without_canonical_identity(&self) -> Result<Vec<UserSummary>, SqlError> {
+        let _timer = Metrics::get().time_query("list_users_without_canonical_identity");
+        let query = sqlx::query!(
+            r#"
+                SELECT id, username, email, disabled_at, deleted_at
+                FROM user
+                WHERE username_canonical IS NULL OR email_canonical IS NULL
+                ORDER BY id
+            "#
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        Ok(rows.iter().map(user_summary).collect())
+    }
+
+    async fn set_canonical_username(&self, user_id: u64, username: &str) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("set_canonical_username");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                UPDATE user
+                SET username_canonical = ?
+                WHERE id = ?
+            "#,
+            username,
+            user_id
+        );
+
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn set_canonical_email(&self, user_id: u64, email: &str) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("set_canonical_email");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                UPDATE user
+                SET email_canonical = ?
+                WHERE id = ?
+            "#,
+            email,
+            user_id
+        );
+
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn is_user_active(&self, user_id: u64) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("is_user_active");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                SELECT id
+                FROM user
+                WHERE id = ? AND disabled_at IS NULL AND deleted_at IS NULL
+            "#,
+            user_id
+        );
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.is_some())
+    }
+
+    async fn set_user_disabled(&self, user_id: u64, disabled_at: Option<i64>) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("set_user_disabled");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                UPDATE user
+                SET disabled_at = ?
+                WHERE id = ? AND deleted_at IS NULL
+            "#,
+            disabled_at,
+            user_id
+        );
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_user_deleted(&self, user_id: u64, deleted_at: Option<i64>) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("set_user_deleted");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                UPDATE user
+                SET deleted_at = ?
+                WHERE id = ?
+            "#,
+            deleted_at,
+            user_id
+        );
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn purge_deleted_users(&self, deleted_before: i64) -> Result<Vec<u64>, SqlError> {
+        let _timer = Metrics::get().time_query("purge_deleted_users");
+        let mut transaction = self.pool.begin().await.map_err(SqlError::from)?;
+
+        let query = sqlx::query!(
+            r#"
+                SELECT id
+                FROM user
+                WHERE deleted_at < ?
+                ORDER BY id
+            "#,
+            deleted_before
+        );
+        let rows = transaction.fetch_all(query).await.map_err(SqlError::from)?;
+
+        let mut purged = Vec::with_capacity(rows.len());
+        for row in rows {
+            let user_id = row.get::<i64, &str>("id");
+            if delete_user_rows(&mut transaction, user_id).await? {
+                purged.push(user_id as u64);
+            }
+        }
+
+        transaction.commit().await.map_err(SqlError::from)?;
+
+        Ok(purged)
+    }
+
+    async fn update_password_by_username_or_email(
+        &self,
+        username: &str,
+        email: &str,
+        password: &str,
+    ) -> Result<Option<u64>, SqlError> {
+        let _timer = Metrics::get().time_query("update_password_by_username_or_email");
+        let username_canonical = canonical::username(username);
+        let email_canonical = canonical::email(email);
+        let query = sqlx::query!(
+            r#"
+                UPDATE user
+                SET password = ?
+                WHERE id = (
+                    SELECT id
+                    FROM user
+                    WHERE username_canonical = ? OR email_canonical = ?
+                        OR (username_canonical IS NULL AND username = ?) OR (email_canonical IS NULL AND email = ?)
+                    ORDER BY (username = ? OR email = ?) DESC, id
+                    LIMIT 1
+                )
+                RETURNING id
+            "#,
+            password,
+            username_canonical,
+            email_canonical,
+            username,
+            email,
+            username,
+            email
+        );
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.map(|row| row.get::<i64, &str>("id") as u64))
+    }
+
+    async fn get_preferences(
+        &self,
+        user_id: u64,
+    ) -> Result<Option<Preferences>, SqlError> {
+        let _timer = Metrics::get().time_query("get_preferences");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                SELECT units, lang
+                FROM user
+                WHERE id = ?
+            "#,
+            user_id
+        );
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+        let preferences = row.map(|row| Preferences {
+            units: row.get::<Option<String>, &str>("units"),
+            lang: row.get::<Option<String>, &str>("lang"),
+        });
+
+        Ok(preferences)
+    }
+
+    async fn set_preferences(
+        &self,
+        user_id: u64,
+        preferences: &Preferences,
+    ) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("set_preferences");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                UPDATE user
+                SET units = ?, lang = ?
+                WHERE id = ?
+            "#,
+            preferences.units,
+            preferences.lang,
+            user_id
+        );
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_locations(
+        &self,
+        user_id: u64,
+    ) -> Result<Vec<SavedLocation>, SqlError> {
+        let _timer = Metrics::get().time_query("list_locations");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                SELECT id, name, latitude, longitude, is_default
+                FROM location
+                WHERE user_id = ?
+                ORDER BY id
+            "#,
+            user_id
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        Ok(rows.iter().map(saved_location).collect())
+    }
+
+    async fn get_location(
+        &self,
+        user_id: u64,
+        location_id: u64,
+    ) -> Result<Option<SavedLocation>, SqlError> {
+        let _timer = Metrics::get().time_query("get_location");
+        let user_id = user_id as i64;
+        let location_id = location_id as i64;
+        let query = sqlx::query!(
+            r#"
+                SELECT id, name, latitude, longitude, is_default
+                FROM location
+                WHERE id = ? AND user_id = ?
+            "#,
+            location_id,
+            user_id
+        );
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.as_ref().map(saved_location))
+    }
+
+    async fn get_default_location(
+        &self,
+        user_id: u64,
+    ) -> Result<Option<SavedLocation>, SqlError> {
+        let _timer = Metrics::get().time_query("get_default_location");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                SELECT id, name, latitude, longitude, is_default
+                FROM location
+                WHERE user_id = ? AND is_default
+            "#,
+            user_id
+        );
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.as_ref().map(saved_location))
+    }
+
+    async fn create_location(
+        &self,
+        user_id: u64,
+        location: &NewLocation,
+        limit: u64,
+    ) -> Result<Option<u64>, SqlError> {
+        let _timer = Metrics::get().time_query("create_location");
+        let user_id = user_id as i64;
+        let limit = limit as i64;
+        let mut transaction = self.pool.begin().await.map_err(SqlError::from)?;
+
+        if location.is_default {
+            clear_default_location(&mut transaction, user_id).await?;
+        }
+
+        let query = sqlx::query!(
+            r#"
+                INSERT INTO location (id, user_id, name, latitude, longitude, is_default)
+                SELECT NULL, ?, ?, ?, ?, ?
+                WHERE (SELECT COUNT(*) FROM location WHERE user_id = ?) < ?
+                RETURNING id
+            "#,
+            user_id,
+            location.name,
+            location.latitude,
+            location.longitude,
+            location.is_default,
+            user_id,
+            limit
+        );
+        let row = transaction.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        // Rolls back clearing the previous default if the limit is reached
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        transaction.commit().await.map_err(SqlError::from)?;
+
+        Ok(Some(row.get::<u64, &str>("id")))
+    }
+
+    async fn update_location(
+        &self,
+        user_id: u64,
+        location_id: u64,
+        location: &NewLocation,
+    ) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("update_location");
+        let user_id = user_id as i64;
+        let location_id = location_id as i64;
+        let mut transaction = self.pool.begin().await.map_err(SqlError::from)?;
+
+        if location.is_default {
+            clear_default_location(&mut transaction, user_id).await?;
+        }
+
+        let query = sqlx::query!(
+            r#"
+                UPDATE location
+                SET name = ?, latitude = ?, longitude = ?, is_default = ?
+                WHERE id = ? AND user_id = ?
+            "#,
+            location.name,
+            location.latitude,
+            location.longitude,
+            location.is_default,
+            location_id,
+            user_id
+        );
+        let result = transaction.execute(query).await.map_err(SqlError::from)?;
+
+        // Rolls back clearing the previous default if no location is updated
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        transaction.commit().await.map_err(SqlError::from)?;
+
+        Ok(true)
+    }
+
+    async fn delete_location(
+        &self,
+        user_id: u64,
+        location_id: u64,
+    ) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("delete_location");
+        let user_id = user_id as i64;
+        let location_id = location_id as i64;
+        let query = sqlx::query!(
+            r#"
+                DELETE FROM location
+                WHERE id = ? AND user_id = ?
+            "#,
+            location_id,
+            user_id
+        );
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_webhook(&self, user_id: u64) -> Result<Option<Webhook>, SqlError> {
+        let _timer = Metrics::get().time_query("get_webhook");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                SELECT url, secret
+                FROM alert_webhook
+                WHERE user_id = ?
+            "#,
+            user_id
+        );
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.map(|row| Webhook {
+            url: row.get::<String, &str>("url"),
+            secret: row.get::<String, &str>("secret"),
+        }))
+    }
+
+    async fn set_webhook(&self, user_id: u64, webhook: &Webhook) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("set_webhook");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                INSERT INTO alert_webhook (user_id, url, secret)
+                VALUES (?, ?, ?)
+                ON CONFLICT (user_id) DO UPDATE SET url = excluded.url, secret = excluded.secret
+            "#,
+            user_id,
+            webhook.url,
+            webhook.secret
+        );
+
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn delete_webhook(&self, user_id: u64) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("delete_webhook");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                DELETE FROM alert_webhook
+                WHERE user_id = ?
+            "#,
+            user_id
+        );
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_alert_rules(&self, user_id: u64) -> Result<Vec<AlertRule>, SqlError> {
+        let _timer = Metrics::get().time_query("list_alert_rules");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                SELECT id, location_id, condition, threshold, within_hours, firing, last_fired_at
+                FROM alert_rule
+                WHERE user_id = ?
+                ORDER BY id
+            "#,
+            user_id
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        Ok(rows.iter().map(alert_rule).collect())
+    }
+
+    async fn create_alert_rule(
+        &self,
+        user_id: u64,
+        rule: &NewAlertRule,
+        limit: u64,
+    ) -> Result<Option<u64>, SqlError> {
+        let _timer = Metrics::get().time_query("create_alert_rule");
+        let user_id = user_id as i64;
+        let location_id = rule.location_id as i64;
+        let limit = limit as i64;
+        let query = sqlx::query!(
+            r#"
+                INSERT INTO alert_rule (id, user_id, location_id, condition, threshold, within_hours)
+                SELECT NULL, ?, ?, ?, ?, ?
+                WHERE (SELECT COUNT(*) FROM alert_rule WHERE user_id = ?) < ?
+                RETURNING id
+            "#,
+            user_id,
+            location_id,
+            rule.condition,
+            rule.threshold,
+            rule.within_hours,
+            user_id,
+            limit
+        );
+
+        let row = self.pool.fetch_optional(query).await.map_err(SqlError::from)?;
+
+        Ok(row.map(|row| row.get::<u64, &str>("id")))
+    }
+
+    async fn delete_alert_rule(&self, user_id: u64, rule_id: u64) -> Result<bool, SqlError> {
+        let _timer = Metrics::get().time_query("delete_alert_rule");
+        let user_id = user_id as i64;
+        let rule_id = rule_id as i64;
+        let query = sqlx::query!(
+            r#"
+                DELETE FROM alert_rule
+                WHERE id = ? AND user_id = ?
+            "#,
+            rule_id,
+            user_id
+        );
+
+        let result = self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_scheduled_alert_rules(&self) -> Result<Vec<ScheduledAlertRule>, SqlError> {
+        let _timer = Metrics::get().time_query("list_scheduled_alert_rules");
+        let query = sqlx::query!(
+            r#"
+                SELECT r.id, r.user_id, r.location_id, r.condition, r.threshold, r.within_hours, r.firing,
+                       r.last_fired_at, l.name, l.latitude, l.longitude, w.url, w.secret
+                FROM alert_rule r
+                INNER JOIN location l ON l.id = r.location_id
+                INNER JOIN alert_webhook w ON w.user_id = r.user_id
+                INNER JOIN user u ON u.id = r.user_id
+                WHERE u.disabled_at IS NULL AND u.deleted_at IS NULL
+                ORDER BY r.location_id, r.id
+            "#
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        let rules = rows
+            .iter()
+            .map(|row| ScheduledAlertRule {
+                user_id: row.get::<u64, &str>("user_id"),
+                rule: alert_rule(row),
+                location_name: row.get::<String, &str>("name"),
+                latitude: row.get::<f64, &str>("latitude"),
+                longitude: row.get::<f64, &str>("longitude"),
+                webhook: Webhook {
+                    url: row.get::<String, &str>("url"),
+                    secret: row.get::<String, &str>("secret"),
+                },
+            })
+            .collect();
+
+        Ok(rules)
+    }
+
+    async fn set_alert_rule_firing(
+        &self,
+        rule_id: u64,
+        firing: bool,
+        fired_at: Option<i64>,
+    ) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("set_alert_rule_firing");
+        let rule_id = rule_id as i64;
+        let query = sqlx::query!(
+            r#"
+                UPDATE alert_rule
+                SET firing = ?, last_fired_at = COALESCE(?, last_fired_at)
+                WHERE id = ?
+            "#,
+            firing,
+            fired_at,
+            rule_id
+        );
+
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn log_alert_delivery(&self, delivery: &AlertDelivery) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("log_alert_delivery");
+        let user_id = delivery.user_id as i64;
+        let rule_id = delivery.rule_id as i64;
+        let query = sqlx::query!(
+            r#"
+                INSERT INTO alert_delivery
+                    (id, user_id, rule_id, event_id, attempt, status_code, error, delivered, attempted_at)
+                VALUES (NULL, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            user_id,
+            rule_id,
+            delivery.event_id,
+            delivery.attempt,
+            delivery.status_code,
+            delivery.error,
+            delivery.delivered,
+            delivery.attempted_at
+        );
+
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn list_alert_deliveries(
+        &self,
+        user_id: u64,
+        limit: u32,
+    ) -> Result<Vec<AlertDelivery>, SqlError> {
+        let _timer = Metrics::get().time_query("list_alert_deliveries");
+        let user_id = user_id as i64;
+        let query = sqlx::query!(
+            r#"
+                SELECT user_id, rule_id, event_id, attempt, status_code, error, delivered, attempted_at
+                FROM alert_delivery
+                WHERE user_id = ?
+                ORDER BY id DESC
+                LIMIT ?
+            "#,
+            user_id,
+            limit
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        let deliveries = rows
+            .iter()
+            .map(|row| AlertDelivery {
+                user_id: row.get::<u64, &str>("user_id"),
+                rule_id: row.get::<u64, &str>("rule_id"),
+                event_id: row.get::<String, &str>("event_id"),
+                attempt: row.get::<u32, &str>("attempt"),
+                status_code: row.get::<Option<u16>, &str>("status_code"),
+                error: row.get::<Option<String>, &str>("error"),
+                delivered: row.get::<bool, &str>("delivered"),
+                attempted_at: row.get::<i64, &str>("attempted_at"),
+            })
+            .collect();
+
+        Ok(deliveries)
+    }
+
+    async fn log_audit_event(&self, event: &NewAuditEvent) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("log_audit_event");
+        let user_id = event.user_id.map(|id| id as i64);
+        let query = sqlx::query!(
+            r#"
+                INSERT INTO audit_event (id, user_id, event, outcome, client_ip, user_agent, occurred_at)
+                VALUES (NULL, ?, ?, ?, ?, ?, ?)
+            "#,
+            user_id,
+            event.event,
+            event.outcome,
+            event.client_ip,
+            event.user_agent,
+            event.occurred_at
+        );
+
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn list_audit_events(
+        &self,
+        filter: &AuditFilter,
+        limit: u32,
+    ) -> Result<Vec<AuditEvent>, SqlError> {
+        let _timer = Metrics::get().time_query("list_audit_events");
+        let user_id = filter.user_id.map(|id| id as i64);
+        let before_id = filter.before_id.map(|id| id as i64);
+        let query = sqlx::query!(
+            r#"
+                SELECT id, user_id, event, outcome, client_ip, user_agent, occurred_at
+                FROM audit_event
+                WHERE (? IS NULL OR user_id = ?)
+                    AND (? IS NULL OR event = ?)
+                    AND (? IS NULL OR outcome = ?)
+                    AND (? IS NULL OR client_ip = ?)
+                    AND (? IS NULL OR occurred_at >= ?)
+                    AND (? IS NULL OR occurred_at < ?)
+                    AND (? IS NULL OR id < ?)
+                ORDER BY id DESC
+                LIMIT ?
+            "#,
+            user_id,
+            user_id,
+            filter.event,
+            filter.event,
+            filter.outcome,
+            filter.outcome,
+            filter.client_ip,
+            filter.client_ip,
+            filter.from,
+            filter.from,
+            filter.to,
+            filter.to,
+            before_id,
+            before_id,
+            limit
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        let events = rows
+            .iter()
+            .map(|row| AuditEvent {
+                id: row.get::<u64, &str>("id"),
+                user_id: row.get::<Option<u64>, &str>("user_id"),
+                event: row.get::<String, &str>("event"),
+                outcome: row.get::<String, &str>("outcome"),
+                client_ip: row.get::<Option<String>, &str>("client_ip"),
+                user_agent: row.get::<Option<String>, &str>("user_agent"),
+                occurred_at: row.get::<i64, &str>("occurred_at"),
+            })
+            .collect();
+
+        Ok(events)
+    }
+
+    async fn store_observations(
+        &self,
+        grid: GridCell,
+        observations: &[Observation],
+    ) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("store_observations");
+        let mut transaction = self.pool.begin().await.map_err(SqlError::from)?;
+
+        for observation in observations {
+            let query = sqlx::query!(
+                r#"
+                    INSERT OR IGNORE INTO observation (
+                        grid_latitude, grid_longitude, observed_at, temperature_c, feels_like_c, condition,
+                        condition_code, wind_kph, humidity, pressure_mb, precip_mm, cloud, uv, provider
+                    )
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+                grid.latitude,
+                grid.longitude,
+                observation.observed_at,
+                observation.temperature_c,
+                observation.feels_like_c,
+                observation.condition,
+                observation.condition_code,
+                observation.wind_kph,
+                observation.humidity,
+                observation.pressure_mb,
+                observation.precip_mm,
+                observation.cloud,
+                observation.uv,
+                observation.provider
+            );
+            transaction.execute(query).await.map_err(SqlError::from)?;
+        }
+
+        transaction.commit().await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn list_observations(
+        &self,
+        grid: GridCell,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<Observation>, SqlError> {
+        let _timer = Metrics::get().time_query("list_observations");
+        let query = sqlx::query!(
+            r#"
+                SELECT observed_at, temperature_c, feels_like_c, condition, condition_code, wind_kph, humidity,
+                       pressure_mb, precip_mm, cloud, uv, provider
+                FROM observation
+                WHERE grid_latitude = ? AND grid_longitude = ? AND observed_at >= ? AND observed_at < ?
+                ORDER BY observed_at
+            "#,
+            grid.latitude,
+            grid.longitude,
+            from,
+            to
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+
+        let observations = rows
+            .iter()
+            .map(|row| Observation {
+                observed_at: row.get::<i64, &str>("observed_at"),
+                temperature_c: row.get::<f64, &str>("temperature_c"),
+                feels_like_c: row.get::<Option<f64>, &str>("feels_like_c"),
+                condition: row.get::<Option<String>, &str>("condition"),
+                condition_code: row.get::<Option<u16>, &str>("condition_code"),
+                wind_kph: row.get::<Option<f64>, &str>("wind_kph"),
+                humidity: row.get::<Option<u8>, &str>("humidity"),
+                pressure_mb: row.get::<Option<f64>, &str>("pressure_mb"),
+                precip_mm: row.get::<Option<f64>, &str>("precip_mm"),
+                cloud: row.get::<Option<u8>, &str>("cloud"),
+                uv: row.get::<Option<f64>, &str>("uv"),
+                provider: row.get::<String, &str>("provider"),
+            })
+            .collect();
+
+        Ok(observations)
+    }
+
+    async fn ping(&self) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("ping");
+        self.pool.execute("SELECT 1").await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    async fn applied_migration_versions(&self) -> Result<Vec<i64>, SqlError> {
+        let _timer = Metrics::get().time_query("applied_migration_versions");
+        let query = sqlx::query(
+            r#"
+                SELECT version
+                FROM _sqlx_migrations
+                WHERE success = TRUE
+                ORDER BY version
+            "#,
+        );
+
+        let rows = self.pool.fetch_all(query).await.map_err(SqlError::from)?;
+        let versions = rows.iter().map(|row| row.get::<i64, &str>("version")).collect();
+
+        Ok(versions)
+    }
+
+    async fn backup(&self, destination: &Path) -> Result<(), SqlError> {
+        let _timer = Metrics::get().time_query("backup");
+        // `VACUUM INTO` refuses to overwrite files, and is not checked at compile time as it returns no rows
+        let query = sqlx::query("VACUUM INTO ?").bind(destination.to_string_lossy().into_owned());
+        self.pool.execute(query).await.map_err(SqlError::from)?;
+
+        Ok(())
+    }
+
+    fn migrator(&self) -> &'static Migrator {
+        &MIGRATOR
+    }
+
+    fn pool_status(&self) -> PoolStatus {
+        PoolStatus {
+            size: self.pool.size(),
+            idle: self.pool.num_idle(),
+        }
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    fn is_closed(&self) -> bool {
+        self.pool.is_closed()
+    }
+}
+
+/// Deletes the user with given ID with every row that belongs to them and returns whether the user existed.
+///
+/// Rows of the user are deleted explicitly, as foreign keys are not enforced if the pragma is turned off.
+async fn delete_user_rows(transaction: &mut Transaction<'_, Sqlite>, user_id: i64) -> Result<bool, SqlError> {
+    let query = sqlx::query!(
+        r#"
+            DELETE FROM alert_delivery
+            WHERE user_id = ?
+        "#,
+        user_id
+    );
+    transaction.execute(query).await.map_err(SqlError::from)?;
+
+    let query = sqlx::query!(
+        r#"
+            DELETE FROM alert_rule
+            WHERE user_id = ?
+        "#,
+        user_id
+    );
+    transaction.execute(query).await.map_err(SqlError::from)?;
+
+    let query = sqlx::query!(
+        r#"
+            DELETE FROM alert_webhook
+            WHERE user_id = ?
+        "#,
+        user_id
+    );
+    transaction.execute(query).await.map_err(SqlError::from)?;
+
+    let query = sqlx::query!(
+        r#"
+            DELETE FROM location
+            WHERE user_id = ?
+        "#,
+        user_id
+    );
+    transaction.execute(query).await.map_err(SqlError::from)?;
+
+    let query = sqlx::query!(
+        r#"
+            DELETE FROM user
+            WHERE id = ?
+        "#,
+        user_id
+    );
+    let result = transaction.execute(query).await.map_err(SqlError::from)?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Makes no location of the user the default.
+async fn clear_default_location(
+    transaction: &mut Transaction<'_, Sqlite>,
+    user_id: i64,
+) -> Result<(), SqlError> {
+    let query = sqlx::query!(
+        r#"
+            UPDATE location
+            SET is_default = FALSE
+            WHERE user_id = ? AND is_default
+        "#,
+        user_id
+    );
+    transaction.execute(query).await.map_err(SqlError::from)?;
+
+    Ok(())
+}
+
+/// Reads a user from a row with the `id`, `username`, `email`, `disabled_at` and `deleted_at` columns of `user` table.
+fn user_summary(row: &SqliteRow) -> UserSummary {
+    UserSummary {
+        id: row.get::<u64, &str>("id"),
+        username: row.get::<String, &str>("username"),
+        email: row.get::<String, &str>("email"),
+        disabled_at: row.get::<Option<i64>, &str>("disabled_at"),
+        deleted_at: row.get::<Option<i64>, &str>("deleted_at"),
+    }
+}
+
+/// Reads a location from a row with the columns of `location` table, except `user_id`.
+fn saved_location(row: &SqliteRow) -> SavedLocation {
+    SavedLocation {
+        id: row.get::<u64, &str>("id"),
+        name: row.get::<String, &str>("name"),
+        latitude: row.get::<f64, &str>("latitude"),
+        longitude: row.get::<f64, &str>("longitude"),
+        is_default: row.get::<bool, &str>("is_default"),
+    }
+}
+
+/// Reads a rule from a row with the columns of `alert_rule` table, except `user_id`.
+fn alert_rule(row: &SqliteRow) -> AlertRule {
+    AlertRule {
+        id: row.get::<u64, &str>("id"),
+        location_id: row.get::<u64, &str>("location_id"),
+        condition: row.get::<String, &str>("condition"),
+        threshold: row.get::<f64, &str>("threshold"),
+        within_hours: row.get::<Option<u8>, &str>("within_hours"),
+        firing: row.get::<bool, &str>("firing"),
+        last_fired_at: row.get::<Option<i64>, &str>("last_fired_at"),
+    }
+}
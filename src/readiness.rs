@@ -0,0 +1,182 @@
+use std::time::{Duration, Instant};
+
+use poem_openapi::{Enum, Object};
+use tokio::sync::Mutex;
+
+use crate::config::ReadinessConfig;
+use crate::http_client::HttpClient;
+use crate::resilience::CircuitState;
+use crate::queries::Database;
+
+/// Checks whether the dependencies of the server are available.
+///
+/// Database checks and circuit breaker states are reported on every call,
+/// upstream API probes are optional and their results are cached.
+pub struct Readiness {
+    config: ReadinessConfig,
+    upstream_cache: Mutex<Option<CachedProbes>>,
+}
+
+impl Readiness {
+    /// IP address used to probe the geolocation API.
+    const PROBE_IP: &'static str = "1.1.1.1";
+
+    /// Creates a readiness checker with given configuration.
+    #[must_use]
+    pub const fn new(config: ReadinessConfig) -> Self {
+        Self {
+            config,
+            upstream_cache: Mutex::const_new(None),
+        }
+    }
+
+    /// Checks every dependency and reports their status.
+    ///
+    /// The server is ready only if every critical dependency is up.
+    pub async fn check(&self, database: &Database, http_client: &HttpClient) -> ReadinessReport {
+        let mut checks = vec![
+            check_database(database).await,
+            check_migrations(database).await,
+        ];
+
+        checks.extend(http_client.circuit_states().into_iter().map(|(provider, state)| {
+            let name = format!("{provider}_circuit");
+            match state {
+                CircuitState::Open => DependencyReport::down(&name, false, "circuit is open, calls fail fast"),
+                CircuitState::Closed | CircuitState::HalfOpen => DependencyReport::up(&name, false),
+            }
+        }));
+
+        if self.config.probe_upstream {
+            checks.extend(self.probe_upstream(http_client).await);
+        }
+
+        let ready = checks
+            .iter()
+            .all(|c| !c.critical || c.status == DependencyStatus::Up);
+
+        ReadinessReport { ready, checks }
+    }
+
+    /// Returns the cached upstream probe results, or probes the APIs if the cache is stale.
+    async fn probe_upstream(&self, http_client: &HttpClient) -> Vec<DependencyReport> {
+        let mut cache = self.upstream_cache.lock().await;
+
+        let max_age = Duration::from_secs(self.config.upstream_cache_seconds);
+        if let Some(cached) = cache.as_ref().filter(|c| c.probed_at.elapsed() < max_age) {
+            return cached.reports.clone();
+        }
+
+        let geolocation = match http_client.get_coordinates_for_ip(Self::PROBE_IP).await {
+            Ok(_) => DependencyReport::up("geolocation_api", false),
+            Err(e) => {
+                // Details of the error are only logged, as they may reveal the configuration of the server
+                tracing::warn!(kind = e.kind(), error = %e, details = ?e, "geolocation API probe failed");
+                DependencyReport::down("geolocation_api", false, e.kind())
+            }
+        };
+        let weather = match http_client.get_weather_for_coordinates(0.0, 0.0).await {
+            Ok(_) => DependencyReport::up("weather_api", false),
+            Err(e) => {
+                // Details of the error are only logged, as they may reveal the configuration of the server
+                tracing::warn!(kind = e.kind(), error = %e, details = ?e, "weather API probe failed");
+                DependencyReport::down("weather_api", false, e.kind())
+            }
+        };
+
+        let reports = vec![geolocation, weather];
+        *cache = Some(CachedProbes {
+            probed_at: Instant::now(),
+            reports: reports.clone(),
+        });
+
+        reports
+    }
+}
+
+/// Results of the last upstream probe.
+struct CachedProbes {
+    probed_at: Instant,
+    reports: Vec<DependencyReport>,
+}
+
+/// Checks the database responds to queries.
+async fn check_database(database: &Database) -> DependencyReport {
+    match database.ping().await {
+        Ok(()) => DependencyReport::up("database", true),
+        Err(_) => DependencyReport::down("database", true, "database query failed"),
+    }
+}
+
+/// Checks every migration embedded into the binary is applied to the database.
+async fn check_migrations(database: &Database) -> DependencyReport {
+    let Ok(applied) = database.applied_migration_versions().await else {
+        return DependencyReport::down("migrations", true, "could not read applied migrations");
+    };
+
+    let pending = database
+        .migrator()
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .count();
+
+    if pending == 0 {
+        DependencyReport::up("migrations", true)
+    } else {
+        DependencyReport::down("migrations", true, &format!("{pending} migrations are not applied"))
+    }
+}
+
+/// Body of `ready` call responses.
+#[derive(serde::Deserialize, Object)]
+pub struct ReadinessReport {
+    /// Whether every critical dependency is up.
+    pub ready: bool,
+    /// Status of each dependency.
+    pub checks: Vec<DependencyReport>,
+}
+
+/// Status of a single dependency.
+#[derive(Clone, serde::Deserialize, Object)]
+pub struct DependencyReport {
+    /// Name of the dependency.
+    pub name: String,
+    /// Whether the server can not serve requests without this dependency.
+    pub critical: bool,
+    /// Whether the dependency is available.
+    pub status: DependencyStatus,
+    /// Reason of the failure, if the dependency is down.
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+impl DependencyReport {
+    /// Creates a report of an available dependency.
+    fn up(name: &str, critical: bool) -> Self {
+        Self {
+            name: name.to_owned(),
+            critical,
+            status: DependencyStatus::Up,
+            detail: None,
+        }
+    }
+
+    /// Creates a report of an unavailable dependency with the reason.
+    fn down(name: &str, critical: bool, detail: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            critical,
+            status: DependencyStatus::Down,
+            detail: Some(detail.to_owned()),
+        }
+    }
+}
+
+/// Availability of a dependency.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Deserialize, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum DependencyStatus {
+    Up,
+    Down,
+}
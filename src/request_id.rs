@@ -0,0 +1,129 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Instant;
+
+use poem::http::HeaderValue;
+use poem::{Endpoint, Request, Response};
+use rand::Rng;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::telemetry;
+
+/// Name of the header carrying the request ID in both requests and responses.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    /// ID of the request handled by the current task.
+    static CURRENT: RequestId;
+}
+
+/// Identifier correlating the logs of a single request.
+///
+/// Available to handlers through request data and `RequestId::current`.
+#[derive(Clone, Debug)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Longest request ID accepted from clients.
+    const MAX_LENGTH: usize = 128;
+
+    /// Uses the ID sent by the client if it is acceptable, or generates a new one.
+    fn from_request(request: &Request) -> Self {
+        request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| Self::is_acceptable(value))
+            .map_or_else(Self::generate, |value| Self(value.to_owned()))
+    }
+
+    /// Checks the ID is not empty, not too long and only contains visible ASCII characters,
+    /// so it can not be used to forge log lines.
+    fn is_acceptable(value: &str) -> bool {
+        !value.is_empty()
+            && value.len() <= Self::MAX_LENGTH
+            && value.chars().all(|c| c.is_ascii_graphic())
+    }
+
+    /// Generates a random 128 bit ID in hexadecimal.
+    fn generate() -> Self {
+        let bytes: [u8; 16] = rand::thread_rng().gen();
+        let id = bytes.iter().map(|b| format!("{b:02x}")).collect();
+
+        Self(id)
+    }
+
+    /// Returns the ID of the request handled by the current task,
+    /// or `None` if called outside of a request.
+    #[must_use]
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+
+    /// Returns the ID as a string slice.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Middleware function that assigns a request ID and runs the request inside a span carrying it.
+///
+/// The ID is taken from the `X-Request-Id` header if the client sent an acceptable one,
+/// and is returned in the same header of the response.
+/// A log line with the status and duration is emitted when the request completes.
+///
+/// If the request carries W3C trace context headers, the span continues that trace.
+///
+/// The client address is recorded once resolved by `client_ip::assign_client_ip`.
+/// Only the method and the path are recorded, so credentials in headers or bodies never reach the logs.
+///
+/// # Errors
+/// Never fails, errors of the inner endpoint are converted to responses.
+pub async fn assign_request_id<E: Endpoint>(
+    next: Arc<E>,
+    mut request: Request,
+) -> poem::Result<Response> {
+    let request_id = RequestId::from_request(&request);
+    let span = tracing::info_span!(
+        "request",
+        otel.name = %format_args!("{} {}", request.method(), request.original_uri().path()),
+        otel.kind = "server",
+        request_id = %request_id,
+        method = %request.method(),
+        path = %request.original_uri().path(),
+        client_ip = tracing::field::Empty,
+    );
+    // Fails only if the span is disabled by the filter, in which case there is nothing to export
+    let _ = span.set_parent(telemetry::extract_context(request.headers()));
+    request.extensions_mut().insert(request_id.clone());
+
+    let started = Instant::now();
+    let mut response = CURRENT
+        .scope(
+            request_id.clone(),
+            next.get_response(request).instrument(span.clone()),
+        )
+        .await;
+
+    span.in_scope(|| {
+        tracing::info!(
+            status = response.status().as_u16(),
+            duration_ms = started.elapsed().as_millis() as u64,
+            "request completed"
+        );
+    });
+
+    if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    Ok(response)
+}
@@ -0,0 +1,191 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Response, StatusCode};
+
+use crate::config::{AlertsConfig, ProviderPolicy};
+
+/// Decides how many times and how long apart failed calls are retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Retries made after the first attempt.
+    pub max_retries: u32,
+    /// Upper bound of the delay before the first retry, doubled on every retry.
+    pub base_delay: Duration,
+    /// Upper bound of any delay, including ones requested by `Retry-After`.
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the given retry, starting from 1.
+    ///
+    /// Uses exponential backoff with full jitter, so concurrent callers do not retry in lockstep.
+    #[must_use]
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+        let ceiling = exponential.min(self.max_delay);
+
+        rand::thread_rng().gen_range(Duration::ZERO..=ceiling)
+    }
+}
+
+impl From<&ProviderPolicy> for RetryPolicy {
+    fn from(policy: &ProviderPolicy) -> Self {
+        Self {
+            max_retries: policy.max_retries,
+            base_delay: Duration::from_millis(policy.retry_base_delay_ms),
+            max_delay: Duration::from_millis(policy.retry_max_delay_ms),
+        }
+    }
+}
+
+impl From<&AlertsConfig> for RetryPolicy {
+    fn from(config: &AlertsConfig) -> Self {
+        Self {
+            max_retries: config.max_delivery_retries,
+            base_delay: Duration::from_millis(config.retry_base_delay_ms),
+            max_delay: Duration::from_millis(config.retry_max_delay_ms),
+        }
+    }
+}
+
+/// Returns whether a response with given status is worth retrying.
+#[must_use]
+pub fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Parses the `Retry-After` header of the response, in either delay seconds or HTTP date form.
+#[must_use]
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.with_timezone(&Utc) - Utc::now();
+
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+/// Stops calls to a failing API for a while, so callers fail fast instead of waiting on timeouts.
+///
+/// After `failure_threshold` consecutive failures the circuit opens and rejects calls.
+/// Once `open_duration` passes, a single trial call is let through;
+/// its success closes the circuit, its failure opens it again.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    /// Creates a closed circuit breaker.
+    #[must_use]
+    pub const fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold,
+            open_duration,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Returns whether a call may be made now.
+    ///
+    /// # Panics
+    /// Panics if the state lock is poisoned, which can not happen as no code panics while holding it.
+    pub fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().expect("circuit breaker lock should not be poisoned");
+
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { since } | State::HalfOpen { since } => {
+                // A trial call whose result is never recorded, e.g. a cancelled one,
+                // should not keep the circuit half-open forever
+                if since.elapsed() < self.open_duration {
+                    return false;
+                }
+
+                *state = State::HalfOpen {
+                    since: Instant::now(),
+                };
+                true
+            }
+        }
+    }
+
+    /// Records a successful call, closing the circuit.
+    ///
+    /// # Panics
+    /// Panics if the state lock is poisoned, which can not happen as no code panics while holding it.
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().expect("circuit breaker lock should not be poisoned");
+        *state = State::Closed { failures: 0 };
+    }
+
+    /// Records a failed call, opening the circuit if failures reach the threshold
+    /// or the failed call was a trial.
+    ///
+    /// # Panics
+    /// Panics if the state lock is poisoned, which can not happen as no code panics while holding it.
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().expect("circuit breaker lock should not be poisoned");
+
+        *state = match *state {
+            State::Closed { failures } if failures + 1 < self.failure_threshold => State::Closed {
+                failures: failures + 1,
+            },
+            _ => State::Open {
+                since: Instant::now(),
+            },
+        };
+    }
+
+    /// Returns the current state of the circuit.
+    ///
+    /// # Panics
+    /// Panics if the state lock is poisoned, which can not happen as no code panics while holding it.
+    pub fn state(&self) -> CircuitState {
+        let state = self.state.lock().expect("circuit breaker lock should not be poisoned");
+
+        match *state {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { since } if since.elapsed() < self.open_duration => CircuitState::Open,
+            State::Open { .. } | State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+}
+
+impl From<&ProviderPolicy> for CircuitBreaker {
+    fn from(policy: &ProviderPolicy) -> Self {
+        Self::new(
+            policy.failure_threshold.max(1),
+            Duration::from_secs(policy.open_seconds),
+        )
+    }
+}
+
+/// Internal state of a circuit breaker.
+enum State {
+    Closed { failures: u32 },
+    Open { since: Instant },
+    HalfOpen { since: Instant },
+}
+
+/// Observable state of a circuit breaker.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CircuitState {
+    /// Calls are made.
+    Closed,
+    /// Calls are rejected.
+    Open,
+    /// A trial call is or can be made.
+    HalfOpen,
+}
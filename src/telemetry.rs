@@ -0,0 +1,75 @@
+use opentelemetry::global;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::Context;
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use poem::http::HeaderMap;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::config::TelemetryConfig;
+
+/// Creates the tracer provider exporting spans to the configured OTLP endpoint
+/// and installs the W3C trace context propagator.
+///
+/// Returns `None` if no endpoint is configured.
+///
+/// # Errors
+/// Returns error if the exporter can not be created.
+pub fn tracer_provider(
+    config: &TelemetryConfig,
+) -> Result<Option<SdkTracerProvider>, anyhow::Error> {
+    let Some(endpoint) = &config.otlp_endpoint else {
+        return Ok(None);
+    };
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(provider.clone());
+
+    Ok(Some(provider))
+}
+
+/// Returns the tracer spans of this crate are exported with.
+#[must_use]
+pub fn tracer(provider: &SdkTracerProvider) -> SdkTracer {
+    provider.tracer(env!("CARGO_PKG_NAME"))
+}
+
+/// Extracts the trace context from `traceparent` and `tracestate` headers.
+///
+/// Returns an empty context if the headers are missing or export is not enabled.
+#[must_use]
+pub fn extract_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Returns `traceparent` and `tracestate` headers of the current span,
+/// to be attached to outbound requests.
+///
+/// Returns no headers if export is not enabled.
+#[must_use]
+pub fn trace_headers() -> HeaderMap {
+    let context = tracing::Span::current().context();
+
+    let mut headers = HeaderMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers));
+    });
+
+    headers
+}
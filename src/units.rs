@@ -0,0 +1,122 @@
+use std::str::FromStr;
+
+use poem_openapi::Enum;
+
+/// Unit system of weather responses.
+///
+/// Providers report values in metric units, which are converted to the requested system.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize, Enum)]
+#[serde(rename_all = "lowercase")]
+#[oai(rename_all = "lowercase")]
+pub enum Units {
+    /// °C, km/h, hPa, mm and km.
+    #[default]
+    Metric,
+    /// °F, mph, inHg, inches and miles.
+    Imperial,
+    /// K, m/s, Pa, mm and m.
+    Si,
+}
+
+impl Units {
+    /// Returns the name of the unit system as written in requests and the database.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Metric => "metric",
+            Self::Imperial => "imperial",
+            Self::Si => "si",
+        }
+    }
+
+    /// Converts a temperature in °C.
+    #[must_use]
+    pub fn temperature(self, celsius: f64) -> f64 {
+        match self {
+            Self::Metric => celsius,
+            Self::Imperial => round(celsius * 9.0 / 5.0 + 32.0),
+            Self::Si => round(celsius + 273.15),
+        }
+    }
+
+    /// Converts a speed in km/h.
+    #[must_use]
+    pub fn speed(self, kph: f64) -> f64 {
+        match self {
+            Self::Metric => kph,
+            Self::Imperial => round(kph / 1.609_344),
+            Self::Si => round(kph / 3.6),
+        }
+    }
+
+    /// Converts a pressure in hPa.
+    #[must_use]
+    pub fn pressure(self, hpa: f64) -> f64 {
+        match self {
+            Self::Metric => hpa,
+            Self::Imperial => round(hpa / 33.863_886),
+            Self::Si => round(hpa * 100.0),
+        }
+    }
+
+    /// Converts a precipitation amount in mm.
+    #[must_use]
+    pub fn precipitation(self, mm: f64) -> f64 {
+        match self {
+            Self::Metric | Self::Si => mm,
+            Self::Imperial => round(mm / 25.4),
+        }
+    }
+
+    /// Converts a distance in km.
+    #[must_use]
+    pub fn distance(self, km: f64) -> f64 {
+        match self {
+            Self::Metric => km,
+            Self::Imperial => round(km / 1.609_344),
+            Self::Si => round(km * 1000.0),
+        }
+    }
+}
+
+impl FromStr for Units {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "metric" => Ok(Self::Metric),
+            "imperial" => Ok(Self::Imperial),
+            "si" => Ok(Self::Si),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Rounds converted values to two decimals, so conversions do not report false precision.
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// Checks the language code is in the form providers accept, e.g. `fr`, `zh_tw` or `pt-BR`,
+/// and returns it in lowercase with an underscore separator.
+///
+/// # Errors
+/// Returns the reason if the code is not in expected form.
+pub fn normalize_lang(lang: &str) -> Result<String, String> {
+    let (language, region) = match lang.split_once(['_', '-']) {
+        Some((language, region)) => (language, Some(region)),
+        None => (lang, None),
+    };
+
+    let language_is_valid =
+        (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic());
+    let region_is_valid = match region {
+        Some(r) => (2..=4).contains(&r.len()) && r.chars().all(|c| c.is_ascii_alphanumeric()),
+        None => true,
+    };
+    if !language_is_valid || !region_is_valid {
+        return Err("Language needs to be a code such as `fr` or `zh_tw`".to_owned());
+    }
+
+    Ok(lang.to_ascii_lowercase().replace('-', "_"))
+}
@@ -0,0 +1,66 @@
+use weather_server_lib::alerts::WebhookDestinations;
+use weather_server_lib::config::AlertsConfig;
+
+#[test]
+fn webhook_destinations_refuse_ipv6_addresses_embedding_non_public_ipv4() {
+    let destinations = WebhookDestinations::new(&AlertsConfig::default());
+
+    for ip in [
+        // IPv4-mapped
+        "::ffff:127.0.0.1",
+        "::ffff:10.0.0.1",
+        // IPv4-compatible
+        "::169.254.169.254",
+        "::10.0.0.1",
+        // NAT64
+        "64:ff9b::a9fe:a9fe",
+        "64:ff9b::7f00:1",
+        "64:ff9b::c0a8:101",
+        // 6to4
+        "2002:a9fe:a9fe::",
+        "2002:7f00:1::1",
+        "2002:a00:1::",
+    ] {
+        assert!(!destinations.allows(ip.parse().unwrap()), "{ip} is allowed");
+    }
+}
+
+#[test]
+fn webhook_destinations_refuse_reserved_ipv4_blocks() {
+    let destinations = WebhookDestinations::new(&AlertsConfig::default());
+
+    for ip in [
+        "0.0.0.0",
+        "0.1.2.3",
+        "192.0.0.1",
+        "192.0.0.255",
+        "198.18.0.1",
+        "198.19.255.255",
+        "240.0.0.1",
+        "255.255.255.254",
+        // Embedded into IPv6 addresses
+        "64:ff9b::c000:1",
+        "2002:c612:1::",
+        "::ffff:240.0.0.1",
+    ] {
+        assert!(!destinations.allows(ip.parse().unwrap()), "{ip} is allowed");
+    }
+}
+
+#[test]
+fn webhook_destinations_allow_public_addresses() {
+    let destinations = WebhookDestinations::new(&AlertsConfig::default());
+
+    for ip in [
+        "93.184.215.14",
+        "192.0.1.1",
+        "198.17.255.255",
+        "198.20.0.1",
+        "223.255.255.254",
+        "2606:2800:21f:cb07:6820:80da:af6b:8b2c",
+        "64:ff9b::5db8:d70e",
+        "2002:5db8:d70e::",
+    ] {
+        assert!(destinations.allows(ip.parse().unwrap()), "{ip} is refused");
+    }
+}
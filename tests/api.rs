@@ -1,13 +1,32 @@
 use std::time::Duration;
 
+use chrono::{NaiveDate, NaiveTime, Utc};
 use fake::Fake;
 use rand::{thread_rng, Rng};
 use rand_distr::Alphanumeric;
 use reqwest::StatusCode;
-use sqlx::SqlitePool;
-use weather_server_lib::api::{LoginBody, RegisterBody, RegisterResponseBody, WeatherResponseBody};
+use sqlx::migrate::MigrateDatabase;
+use sqlx::{Postgres, SqlitePool};
+use wiremock::matchers::{method, path, path_regex, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use weather_server_lib::alerts::{AlertCondition, AlertEvent, ALERT_ID_HEADER, SIGNATURE_HEADER};
+use weather_server_lib::api::{
+    AlertDeliveryBody, AlertRuleBody, AuditEventBody, BackupBody, HistoryResponseBody, LocationBody, LocationWeather, LoginBody, NewAlertRuleBody,
+    LoginResponseBody, NewLocationBody, NewWebhookBody, PreferencesBody, RegisterBody, RegisterResponseBody,
+    WeatherResponseBody, WebhookBody,
+};
+use weather_server_lib::audit::{AuditEventKind, AuditOutcome};
 use weather_server_lib::authorization::create_token;
-use weather_server_lib::config::Config;
+use weather_server_lib::config::{
+    AccountsConfig, AlertsConfig, BackupConfig, Config, DevLocation, ForwardingHeader, GeolocationProvider, ProviderConfig, ProviderPolicy, ProxyConfig,
+    StreamConfig, UpstreamConfig, WeatherProvider,
+};
+use weather_server_lib::problem::{Problem, ProblemCode, PROBLEM_CONTENT_TYPE};
+use weather_server_lib::readiness::{DependencyStatus, ReadinessReport};
+use weather_server_lib::units::Units;
+use weather_server_lib::queries::{GridCell, Observation};
 use weather_server_lib::{password, queries};
 
 #[tokio::test]
@@ -27,6 +46,98 @@ async fn health_check_succeeds() {
     database.close().await;
 }
 
+#[tokio::test]
+#[serial_test::serial]
+async fn request_id_is_echoed_or_generated() {
+    let database = spawn_server().await;
+
+    let client = reqwest::Client::default();
+    let response = client
+        .get("http://127.0.0.1:8000/api/health_check")
+        .header("X-Request-Id", "client-supplied-id")
+        .send()
+        .await
+        .expect("health check failed");
+
+    assert_eq!(
+        response.headers().get("X-Request-Id").unwrap(),
+        "client-supplied-id"
+    );
+
+    let response = client
+        .get("http://127.0.0.1:8000/api/health_check")
+        .send()
+        .await
+        .expect("health check failed");
+
+    let generated = response
+        .headers()
+        .get("X-Request-Id")
+        .expect("request ID should be generated");
+
+    assert_eq!(generated.len(), 32);
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn ready_succeeds_with_migrated_database() {
+    let database = spawn_server().await;
+
+    let client = reqwest::Client::default();
+    let response = client
+        .get("http://127.0.0.1:8000/api/ready")
+        .send()
+        .await
+        .expect("readiness check failed");
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let report = response
+        .json::<ReadinessReport>()
+        .await
+        .expect("could not obtain readiness report");
+
+    assert!(report.ready);
+    assert!(report
+        .checks
+        .iter()
+        .all(|c| c.status == DependencyStatus::Up));
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn metrics_include_handled_requests() {
+    let database = spawn_server().await;
+
+    let client = reqwest::Client::default();
+    client
+        .get("http://127.0.0.1:8000/api/health_check")
+        .send()
+        .await
+        .expect("health check failed");
+
+    let response = client
+        .get("http://127.0.0.1:8000/metrics")
+        .send()
+        .await
+        .expect("metrics request failed");
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let metrics = response.text().await.expect("could not obtain metrics");
+
+    assert!(metrics.contains(
+        r#"http_requests_total{method="GET",operation="health_check",status="200"}"#
+    ));
+    assert!(metrics.contains("database_pool_connections"));
+
+    database.close().await;
+}
+
 #[tokio::test]
 #[serial_test::serial]
 async fn register_succeeds() {
@@ -59,6 +170,185 @@ async fn register_succeeds() {
     database.close().await;
 }
 
+#[tokio::test]
+#[serial_test::serial]
+async fn register_with_invalid_credentials_returns_problem() {
+    let database = spawn_server().await;
+
+    let request_body = RegisterBody {
+        username: "short".to_owned(),
+        email: "not an email".to_owned(),
+        password: "password123".to_owned(),
+    };
+
+    let client = reqwest::Client::default();
+    let response = client
+        .post("http://127.0.0.1:8000/api/register")
+        .header("X-Request-Id", "invalid-registration")
+        .json(&request_body)
+        .send()
+        .await
+        .expect("registration request failed");
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        PROBLEM_CONTENT_TYPE
+    );
+
+    let problem = response
+        .json::<Problem>()
+        .await
+        .expect("could not obtain problem body");
+
+    assert_eq!(problem.code, ProblemCode::InvalidCredentials);
+    assert_eq!(problem.status, 400);
+    assert_eq!(problem.request_id.as_deref(), Some("invalid-registration"));
+    let fields = problem
+        .errors
+        .iter()
+        .map(|e| e.field.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(fields, ["username", "email"]);
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn register_refuses_variants_of_registered_identities() {
+    let database = spawn_server().await;
+
+    let client = reqwest::Client::default();
+    let register = |username: &str, email: &str| {
+        let request_body = RegisterBody {
+            username: username.to_owned(),
+            email: email.to_owned(),
+            password: "password123".to_owned(),
+        };
+        client
+            .post("http://127.0.0.1:8000/api/register")
+            .json(&request_body)
+            .send()
+    };
+
+    let response = register("Alice_1", "ALICE@Example.COM").await.expect("registration request failed");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = register("Straße_1", "strasse@example.com").await.expect("registration request failed");
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Fullwidth letters, a differently cased email and a case folded `ß` are variants of registered identities
+    for (username, email) in [
+        ("ａｌｉｃｅ_1", "first@example.com"),
+        ("bob_123", "alice@example.com"),
+        ("STRASSE_1", "second@example.com"),
+    ] {
+        let response = register(username, email).await.expect("registration request failed");
+        assert_eq!(response.status(), StatusCode::CONFLICT, "{username} {email}");
+    }
+
+    for identifier in ["ALICE_1", "alice@EXAMPLE.com", "strasse_1"] {
+        let request_body = LoginBody {
+            identifier: identifier.to_owned(),
+            password: "password123".to_owned(),
+        };
+        let response = client
+            .post("http://127.0.0.1:8000/api/login")
+            .json(&request_body)
+            .send()
+            .await
+            .expect("login request failed");
+        assert_eq!(response.status(), StatusCode::OK, "{identifier}");
+    }
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn register_refuses_confusable_usernames_if_configured() {
+    let mut config = Config::read().unwrap();
+    config.accounts = AccountsConfig {
+        reject_confusable_usernames: true,
+        ..AccountsConfig::default()
+    };
+    let database = spawn_server_with_config(config).await;
+
+    let client = reqwest::Client::default();
+    let register = |username: &str, email: &str| {
+        let request_body = RegisterBody {
+            username: username.to_owned(),
+            email: email.to_owned(),
+            password: "password123".to_owned(),
+        };
+        client
+            .post("http://127.0.0.1:8000/api/register")
+            .json(&request_body)
+            .send()
+    };
+
+    // `а` is Cyrillic
+    let response = register("pаypal_1", "paypal@example.com").await.expect("registration request failed");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let problem = response
+        .json::<Problem>()
+        .await
+        .expect("could not obtain problem body");
+    assert_eq!(problem.code, ProblemCode::InvalidCredentials);
+    let fields = problem
+        .errors
+        .iter()
+        .map(|e| e.field.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(fields, ["username"]);
+
+    let response = register("иван_123", "ivan@example.com").await.expect("registration request failed");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = register("paypal_1", "paypal@example.com").await.expect("registration request failed");
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn unhandled_errors_return_problems() {
+    let database = spawn_server().await;
+
+    let client = reqwest::Client::default();
+    let response = client
+        .get("http://127.0.0.1:8000/api/does_not_exist")
+        .send()
+        .await
+        .expect("request failed");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        PROBLEM_CONTENT_TYPE
+    );
+    let problem = response
+        .json::<Problem>()
+        .await
+        .expect("could not obtain problem body");
+    assert_eq!(problem.code, ProblemCode::NotFound);
+
+    let response = client
+        .get("http://127.0.0.1:8000/api/weather")
+        .send()
+        .await
+        .expect("weather request failed");
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let problem = response
+        .json::<Problem>()
+        .await
+        .expect("could not obtain problem body");
+    assert_eq!(problem.code, ProblemCode::Unauthorized);
+
+    database.close().await;
+}
+
 #[tokio::test]
 #[serial_test::serial]
 async fn login_with_username_succeeds() {
@@ -66,8 +356,7 @@ async fn login_with_username_succeeds() {
 
     let user = User::random();
     let password_hash = password::hash(&user.password);
-    queries::register_user(
-        &database.connection,
+    database.connection.register_user(
         &user.username,
         &user.email,
         &password_hash,
@@ -100,8 +389,7 @@ async fn login_with_email_succeeds() {
 
     let user = User::random();
     let password_hash = password::hash(&user.password);
-    queries::register_user(
-        &database.connection,
+    database.connection.register_user(
         &user.username,
         &user.email,
         &password_hash,
@@ -129,10 +417,138 @@ async fn login_with_email_succeeds() {
 
 #[tokio::test]
 #[serial_test::serial]
-async fn get_weather_with_logged_in_user_succeeds() {
+async fn disabled_and_deleted_users_can_not_log_in_or_use_tokens() {
     let database = spawn_server().await;
 
-    let token = create_token(0).expect("token creation failed");
+    let user = User::random();
+    let password_hash = password::hash(&user.password);
+    let user_id = database.connection.register_user(
+        &user.username,
+        &user.email,
+        &password_hash,
+    )
+    .await
+    .expect("user persisting failed");
+
+    let login_body = LoginBody {
+        identifier: user.username,
+        password: user.password,
+    };
+    let client = reqwest::Client::default();
+    let token = client
+        .post("http://127.0.0.1:8000/api/login")
+        .json(&login_body)
+        .send()
+        .await
+        .expect("login request failed")
+        .json::<LoginResponseBody>()
+        .await
+        .expect("could not obtain login response body")
+        .token;
+
+    let response = client
+        .get("http://127.0.0.1:8000/api/preferences")
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("preferences request failed");
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    database.connection.set_user_disabled(user_id, Some(Utc::now().timestamp()))
+        .await
+        .expect("disabling user failed");
+
+    let response = client
+        .get("http://127.0.0.1:8000/api/preferences")
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("preferences request failed");
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post("http://127.0.0.1:8000/api/login")
+        .json(&login_body)
+        .send()
+        .await
+        .expect("login request failed");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    database.connection.set_user_disabled(user_id, None)
+        .await
+        .expect("enabling user failed");
+    database.connection.set_user_deleted(user_id, Some(Utc::now().timestamp()))
+        .await
+        .expect("deleting user failed");
+
+    let response = client
+        .get("http://127.0.0.1:8000/api/preferences")
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("preferences request failed");
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post("http://127.0.0.1:8000/api/login")
+        .json(&login_body)
+        .send()
+        .await
+        .expect("login request failed");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    database.connection.set_user_deleted(user_id, None)
+        .await
+        .expect("restoring user failed");
+
+    let response = client
+        .post("http://127.0.0.1:8000/api/login")
+        .json(&login_body)
+        .send()
+        .await
+        .expect("login request failed");
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn get_weather_with_logged_in_user_succeeds() {
+    let upstream = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/203.0.113.7/latlong/"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("41.0,29.0"))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(WEATHER_RESPONSE_BODY))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    let mut config = Config::read().unwrap();
+    config.upstream = mock_upstream_config(&upstream);
+    config.dev_location = Some(DevLocation::Ip {
+        ip: "203.0.113.7".parse().unwrap(),
+    });
+    let database = spawn_server_with_config(config).await;
+
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let token = create_token(user_id).expect("token creation failed");
     let authorization = format!("Bearer {token}");
 
     let client = reqwest::Client::default();
@@ -145,54 +561,1497 @@ async fn get_weather_with_logged_in_user_succeeds() {
 
     assert_eq!(response.status(), StatusCode::OK);
 
-    let _ = response
+    let response_body = response
         .json::<WeatherResponseBody>()
         .await
         .expect("could not obtain weather data");
 
+    assert_eq!(response_body.provenance.geolocation.as_deref(), Some("ipapi"));
+    assert_eq!(response_body.provenance.weather, "weatherapi");
+    assert!((response_body.location.latitude - 41.0).abs() < 0.000_000_001);
+    assert!((response_body.location.longitude - 29.0).abs() < 0.000_000_001);
+    assert!(response_body.last_updated.is_none());
+
     database.close().await;
 }
 
-#[must_use]
-async fn spawn_server() -> Database {
-    let mut config = Config::read().unwrap();
+#[tokio::test]
+#[serial_test::serial]
+async fn get_weather_uses_dev_location_for_private_addresses() {
+    let upstream = MockServer::start().await;
 
-    config.database_name = thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(32)
-        .map(|x| x as char)
-        .collect();
+    Mock::given(method("GET"))
+        .and(path_regex("/[a-fA-F0-9\\.:]*/latlong"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("41.0,29.0"))
+        .expect(0)
+        .mount(&upstream)
+        .await;
 
-    let server = weather_server_lib::setup(&config)
-        .await
-        .expect("server initialization failed");
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .and(query_param("q", "52.5,13.4"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(WEATHER_RESPONSE_BODY))
+        .expect(1)
+        .mount(&upstream)
+        .await;
 
-    let database = server.database();
-    tokio::spawn(server.serve());
+    let mut config = Config::read().unwrap();
+    config.upstream = mock_upstream_config(&upstream);
+    config.dev_location = Some(DevLocation::Coordinates {
+        latitude: 52.5,
+        longitude: 13.4,
+    });
+    let database = spawn_server_with_config(config).await;
 
-    // Poem server does not initialize quickly enough for us to query it immediately
-    tokio::time::sleep(Duration::from_secs(1)).await;
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let token = create_token(user_id).expect("token creation failed");
+    let response_body = reqwest::Client::default()
+        .get("http://127.0.0.1:8000/api/weather")
+        .header("Authorization", format!("Bearer {token}"))
+        .send()
+        .await
+        .expect("weather request failed")
+        .json::<WeatherResponseBody>()
+        .await
+        .expect("could not obtain weather data");
 
-    Database::new(&config.database_name, &database)
-}
+    assert!((response_body.location.latitude - 52.5).abs() < 0.000_000_001);
+    assert!((response_body.location.longitude - 13.4).abs() < 0.000_000_001);
+    assert!(response_body.provenance.geolocation.is_none());
 
-struct Database {
-    name: String,
-    connection: SqlitePool,
+    database.close().await;
 }
 
-impl Database {
-    fn new(name: &str, connection: &SqlitePool) -> Self {
-        Self {
-            name: name.to_owned(),
-            connection: connection.clone(),
-        }
+#[tokio::test]
+#[serial_test::serial]
+async fn get_weather_locates_client_forwarded_by_trusted_proxies() {
+    let upstream = MockServer::start().await;
+
+    for (ip, coordinates, calls) in [("203.0.113.7", "41.0,29.0", 2), ("2001:db8::7", "39.9,32.8", 1)] {
+        Mock::given(method("GET"))
+            .and(path(format!("/{ip}/latlong/")))
+            .respond_with(ResponseTemplate::new(200).set_body_string(coordinates))
+            .expect(calls)
+            .mount(&upstream)
+            .await;
     }
 
-    async fn close(self) {
-        // We need to close the connection or some changes are not flushed
-        // Possibly not important as we will be deleting those files
-        self.connection.close().await;
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(WEATHER_RESPONSE_BODY))
+        .mount(&upstream)
+        .await;
+
+    let mut config = Config::read().unwrap();
+    config.upstream = mock_upstream_config(&upstream);
+    config.proxy = ProxyConfig {
+        trusted: vec!["127.0.0.0/8".parse().unwrap(), "10.0.0.0/8".parse().unwrap()],
+        header: ForwardingHeader::XForwardedFor,
+    };
+    let database = spawn_server_with_config(config).await;
+
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let token = create_token(user_id).expect("token creation failed");
+    let client = reqwest::Client::default();
+
+    // Addresses left of the nearest untrusted hop are set by the client and ignored,
+    // as is a `Forwarded` header the proxy does not set but passes through from the client
+    for (forwarded_for, forwarded, latitude) in [
+        ("198.51.100.1, 203.0.113.7, 10.1.2.3", None, 41.0),
+        ("2001:db8::7", None, 39.9),
+        ("203.0.113.7", Some(r#"for="[2001:db8::7]:4711";proto=https"#), 41.0),
+    ] {
+        let mut request = client
+            .get("http://127.0.0.1:8000/api/weather")
+            .header("Authorization", format!("Bearer {token}"))
+            .header("X-Forwarded-For", forwarded_for);
+        if let Some(forwarded) = forwarded {
+            request = request.header("Forwarded", forwarded);
+        }
+        let response_body = request
+            .send()
+            .await
+            .expect("weather request failed")
+            .json::<WeatherResponseBody>()
+            .await
+            .expect("could not obtain weather data");
+
+        assert!((response_body.location.latitude - latitude).abs() < 0.000_000_001, "{forwarded_for}");
+    }
+
+    // Without the configured header the caller is the loopback peer, which can not be located,
+    // whatever other forwarding headers the client sends
+    for (header, value) in [("Forwarded", "for=203.0.113.7"), ("X-Real-IP", "203.0.113.7")] {
+        let response = client
+            .get("http://127.0.0.1:8000/api/weather")
+            .header("Authorization", format!("Bearer {token}"))
+            .header(header, value)
+            .send()
+            .await
+            .expect("weather request failed");
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY, "{header}");
+        let problem = response.json::<Problem>().await.expect("could not obtain problem");
+        assert_eq!(problem.code, ProblemCode::PrivateAddress);
+    }
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn get_weather_uses_units_and_language_preferences() {
+    let upstream = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex("/[a-fA-F0-9\\.:]*/latlong"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("41.0,29.0"))
+        .mount(&upstream)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .and(query_param("lang", "fr"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"current":{"last_updated":"2024-09-22 16:00","temp_c":20.0,"condition":{"text":"Ensoleillé"},"feelslike_c":20.0,"wind_kph":36.0}}"#,
+        ))
+        .expect(2)
+        .mount(&upstream)
+        .await;
+
+    let mut config = Config::read().unwrap();
+    config.upstream = mock_upstream_config(&upstream);
+    config.dev_location = Some(DevLocation::Ip {
+        ip: "203.0.113.7".parse().unwrap(),
+    });
+    let database = spawn_server_with_config(config).await;
+
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let token = create_token(user_id).expect("token creation failed");
+    let authorization = format!("Bearer {token}");
+
+    let client = reqwest::Client::default();
+    let response = client
+        .put("http://127.0.0.1:8000/api/preferences")
+        .header("Authorization", &authorization)
+        .json(&PreferencesBody {
+            units: Some(Units::Imperial),
+            lang: Some("FR".to_owned()),
+        })
+        .send()
+        .await
+        .expect("preferences request failed");
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response_body = client
+        .get("http://127.0.0.1:8000/api/weather")
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("weather request failed")
+        .json::<WeatherResponseBody>()
+        .await
+        .expect("could not obtain weather data");
+
+    assert_eq!(response_body.units, Units::Imperial);
+    assert_eq!(response_body.lang.as_deref(), Some("fr"));
+    assert!((response_body.temperature - 68.0).abs() < 0.01);
+
+    let response_body = client
+        .get("http://127.0.0.1:8000/api/weather?units=si")
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("weather request failed")
+        .json::<WeatherResponseBody>()
+        .await
+        .expect("could not obtain weather data");
+
+    assert_eq!(response_body.units, Units::Si);
+    assert!((response_body.temperature - 293.15).abs() < 0.01);
+    assert!((response_body.wind.speed.unwrap() - 10.0).abs() < 0.01);
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn saved_locations_can_be_managed() {
+    let database = spawn_server().await;
+
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let token = create_token(user_id).expect("token creation failed");
+    let authorization = format!("Bearer {token}");
+
+    let client = reqwest::Client::default();
+    let home = NewLocationBody {
+        name: "Home".to_owned(),
+        latitude: 41.0,
+        longitude: 29.0,
+        default: true,
+    };
+    let response = client
+        .post("http://127.0.0.1:8000/api/locations")
+        .header("Authorization", &authorization)
+        .json(&home)
+        .send()
+        .await
+        .expect("location request failed");
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let home = response
+        .json::<LocationBody>()
+        .await
+        .expect("could not obtain location");
+
+    assert!(home.default);
+
+    let response = client
+        .post("http://127.0.0.1:8000/api/locations")
+        .header("Authorization", &authorization)
+        .json(&NewLocationBody {
+            name: "Home".to_owned(),
+            latitude: 40.0,
+            longitude: 30.0,
+            default: false,
+        })
+        .send()
+        .await
+        .expect("location request failed");
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(response.json::<Problem>().await.unwrap().code, ProblemCode::LocationExists);
+
+    let response = client
+        .post("http://127.0.0.1:8000/api/locations")
+        .header("Authorization", &authorization)
+        .json(&NewLocationBody {
+            name: String::new(),
+            latitude: 91.0,
+            longitude: 0.0,
+            default: false,
+        })
+        .send()
+        .await
+        .expect("location request failed");
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let problem = response.json::<Problem>().await.unwrap();
+
+    assert_eq!(problem.code, ProblemCode::InvalidFields);
+    assert_eq!(problem.errors.len(), 2);
+
+    let response = client
+        .put(format!("http://127.0.0.1:8000/api/locations/{}", home.id))
+        .header("Authorization", &authorization)
+        .json(&NewLocationBody {
+            name: "Office".to_owned(),
+            latitude: 41.1,
+            longitude: 29.1,
+            default: false,
+        })
+        .send()
+        .await
+        .expect("location request failed");
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let locations = client
+        .get("http://127.0.0.1:8000/api/locations")
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("locations request failed")
+        .json::<Vec<LocationBody>>()
+        .await
+        .expect("could not obtain locations");
+
+    assert_eq!(locations.len(), 1);
+    assert_eq!(locations[0].name, "Office");
+    assert!(!locations[0].default);
+
+    // Locations of other users are not visible
+    let other = User::random();
+    let other_id = database.connection.register_user(&other.username, &other.email, "")
+        .await
+        .expect("user persisting failed");
+    let other_token = create_token(other_id).expect("token creation failed");
+    let response = client
+        .get(format!("http://127.0.0.1:8000/api/locations/{}", home.id))
+        .header("Authorization", format!("Bearer {other_token}"))
+        .send()
+        .await
+        .expect("location request failed");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client
+        .delete(format!("http://127.0.0.1:8000/api/locations/{}", home.id))
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("location request failed");
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!("http://127.0.0.1:8000/api/locations/{}", home.id))
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("location request failed");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn location_limit_holds_for_concurrent_requests() {
+    let database = spawn_server().await;
+
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let token = create_token(user_id).expect("token creation failed");
+    let authorization = format!("Bearer {token}");
+
+    let client = reqwest::Client::default();
+    let requests = (0..60).map(|i| {
+        client
+            .post("http://127.0.0.1:8000/api/locations")
+            .header("Authorization", &authorization)
+            .json(&NewLocationBody {
+                name: format!("Location {i}"),
+                latitude: 41.0,
+                longitude: 29.0,
+                default: false,
+            })
+            .send()
+    });
+    let statuses = futures::future::join_all(requests)
+        .await
+        .into_iter()
+        .map(|response| response.expect("location request failed").status())
+        .collect::<Vec<_>>();
+
+    let created = statuses.iter().filter(|s| **s == StatusCode::CREATED).count();
+    let refused = statuses.iter().filter(|s| **s == StatusCode::CONFLICT).count();
+    assert_eq!((created, refused), (50, 10));
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn get_weather_uses_saved_locations() {
+    let upstream = MockServer::start().await;
+
+    // Saved locations are used instead of geolocation
+    Mock::given(method("GET"))
+        .and(path_regex("/[a-fA-F0-9\\.:]*/latlong"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("41.0,29.0"))
+        .expect(0)
+        .mount(&upstream)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .and(query_param("q", "39.9,32.8"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"current":{"last_updated":"2024-09-22 16:00","temp_c":15.0,"condition":{"text":"Cloudy"},"feelslike_c":14.0}}"#,
+        ))
+        .mount(&upstream)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .and(query_param("q", "41,29"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(WEATHER_RESPONSE_BODY))
+        .mount(&upstream)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .and(query_param("q", "0,0"))
+        .respond_with(ResponseTemplate::new(401).set_body_string(
+            r#"{"error":{"code":2006,"message":"API key is invalid."}}"#,
+        ))
+        .mount(&upstream)
+        .await;
+
+    let mut config = Config::read().unwrap();
+    config.upstream = mock_upstream_config(&upstream);
+    config.upstream.weather[0].policy = ProviderPolicy {
+        max_retries: 0,
+        ..ProviderPolicy::default()
+    };
+    let database = spawn_server_with_config(config).await;
+
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let token = create_token(user_id).expect("token creation failed");
+    let authorization = format!("Bearer {token}");
+
+    let client = reqwest::Client::default();
+    let mut ids = Vec::new();
+    for (name, latitude, longitude, default) in
+        [("Home", 41.0, 29.0, true), ("Office", 39.9, 32.8, false), ("Island", 0.0, 0.0, false)]
+    {
+        let location = client
+            .post("http://127.0.0.1:8000/api/locations")
+            .header("Authorization", &authorization)
+            .json(&NewLocationBody {
+                name: name.to_owned(),
+                latitude,
+                longitude,
+                default,
+            })
+            .send()
+            .await
+            .expect("location request failed")
+            .json::<LocationBody>()
+            .await
+            .expect("could not obtain location");
+        ids.push(location.id);
+    }
+
+    let response_body = client
+        .get("http://127.0.0.1:8000/api/weather")
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("weather request failed")
+        .json::<WeatherResponseBody>()
+        .await
+        .expect("could not obtain weather data");
+
+    assert!((response_body.temperature - 21.5).abs() < 0.01);
+    assert_eq!(response_body.location.saved_location_id, Some(ids[0]));
+    assert!(response_body.provenance.geolocation.is_none());
+
+    let response_body = client
+        .get(format!("http://127.0.0.1:8000/api/weather?location={}", ids[1]))
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("weather request failed")
+        .json::<WeatherResponseBody>()
+        .await
+        .expect("could not obtain weather data");
+
+    assert!((response_body.temperature - 15.0).abs() < 0.01);
+
+    let response = client
+        .get("http://127.0.0.1:8000/api/weather?location=0")
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("weather request failed");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let entries = client
+        .get("http://127.0.0.1:8000/api/weather/locations")
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("weather request failed")
+        .json::<Vec<LocationWeather>>()
+        .await
+        .expect("could not obtain weather data");
+
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].location.name, "Home");
+    assert!((entries[0].weather.as_ref().unwrap().temperature - 21.5).abs() < 0.01);
+    assert!((entries[1].weather.as_ref().unwrap().temperature - 15.0).abs() < 0.01);
+    assert!(entries[2].weather.is_none());
+    assert_eq!(entries[2].problem.as_ref().unwrap().code, ProblemCode::UpstreamError);
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn alert_rules_deliver_signed_alerts_once() {
+    let upstream = MockServer::start().await;
+    let receiver = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"current":{"last_updated":"2024-09-22 16:00","temp_c":-5.0,"condition":{"text":"Snow"},"feelslike_c":-9.0}}"#,
+        ))
+        .mount(&upstream)
+        .await;
+
+    // First delivery fails, so it is retried
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&receiver)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&receiver)
+        .await;
+
+    let mut config = Config::read().unwrap();
+    config.upstream = mock_upstream_config(&upstream);
+    config.alerts = AlertsConfig {
+        poll_interval_seconds: 1,
+        retry_base_delay_ms: 50,
+        retry_max_delay_ms: 100,
+        // The receiver listens on loopback
+        allow_private_webhooks: true,
+        ..AlertsConfig::default()
+    };
+    let database = spawn_server_with_config(config).await;
+
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let token = create_token(user_id).expect("token creation failed");
+    let authorization = format!("Bearer {token}");
+
+    let client = reqwest::Client::default();
+    let location = client
+        .post("http://127.0.0.1:8000/api/locations")
+        .header("Authorization", &authorization)
+        .json(&NewLocationBody {
+            name: "Home".to_owned(),
+            latitude: 41.0,
+            longitude: 29.0,
+            default: false,
+        })
+        .send()
+        .await
+        .expect("location request failed")
+        .json::<LocationBody>()
+        .await
+        .expect("could not obtain location");
+
+    let response = client
+        .post("http://127.0.0.1:8000/api/alerts/rules")
+        .header("Authorization", &authorization)
+        .json(&NewAlertRuleBody {
+            location_id: location.id,
+            condition: AlertCondition::PrecipitationExpected,
+            threshold: None,
+            within_hours: None,
+        })
+        .send()
+        .await
+        .expect("alert rule request failed");
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Problem>().await.unwrap().errors[0].field, "within_hours");
+
+    let rule = client
+        .post("http://127.0.0.1:8000/api/alerts/rules")
+        .header("Authorization", &authorization)
+        .json(&NewAlertRuleBody {
+            location_id: location.id,
+            condition: AlertCondition::TemperatureBelow,
+            threshold: Some(0.0),
+            within_hours: None,
+        })
+        .send()
+        .await
+        .expect("alert rule request failed")
+        .json::<AlertRuleBody>()
+        .await
+        .expect("could not obtain alert rule");
+
+    let webhook = client
+        .put("http://127.0.0.1:8000/api/alerts/webhook")
+        .header("Authorization", &authorization)
+        .json(&NewWebhookBody {
+            url: format!("{}/hook", receiver.uri()),
+        })
+        .send()
+        .await
+        .expect("webhook request failed")
+        .json::<WebhookBody>()
+        .await
+        .expect("could not obtain webhook");
+
+    // Several evaluations happen, the ongoing condition fires once
+    tokio::time::sleep(Duration::from_secs(4)).await;
+
+    let requests = receiver.received_requests().await.unwrap();
+
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+        requests[0].headers.get(ALERT_ID_HEADER),
+        requests[1].headers.get(ALERT_ID_HEADER)
+    );
+
+    let request = &requests[1];
+    let signature = request.headers.get(SIGNATURE_HEADER).unwrap().to_str().unwrap();
+    let (timestamp, signature) = signature
+        .strip_prefix("t=")
+        .and_then(|s| s.split_once(",v1="))
+        .expect("signature should be in t=<time>,v1=<hmac> form");
+    let mut mac = Hmac::<Sha256>::new_from_slice(webhook.secret.as_bytes()).unwrap();
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(&request.body);
+
+    assert_eq!(hex::encode(mac.finalize().into_bytes()), signature);
+
+    let event = serde_json::from_slice::<AlertEvent>(&request.body).unwrap();
+
+    assert_eq!(event.rule_id, rule.id);
+    assert_eq!(event.condition, AlertCondition::TemperatureBelow);
+    assert_eq!(event.location.name, "Home");
+    assert!((event.value + 5.0).abs() < 0.01);
+
+    let deliveries = client
+        .get("http://127.0.0.1:8000/api/alerts/deliveries")
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("deliveries request failed")
+        .json::<Vec<AlertDeliveryBody>>()
+        .await
+        .expect("could not obtain deliveries");
+
+    assert_eq!(deliveries.len(), 2);
+    assert!(deliveries[0].delivered);
+    assert_eq!(deliveries[0].attempt, 2);
+    assert_eq!(deliveries[1].status, Some(500));
+    assert_eq!(deliveries[0].alert_id, event.id);
+
+    let rules = client
+        .get("http://127.0.0.1:8000/api/alerts/rules")
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .expect("alert rules request failed")
+        .json::<Vec<AlertRuleBody>>()
+        .await
+        .expect("could not obtain alert rules");
+
+    assert!(rules[0].firing);
+    assert!(rules[0].last_fired_at.is_some());
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn webhooks_to_private_addresses_are_refused() {
+    let database = spawn_server().await;
+
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let token = create_token(user_id).expect("token creation failed");
+    let authorization = format!("Bearer {token}");
+
+    let client = reqwest::Client::default();
+    for url in [
+        "http://127.0.0.1:8000/api/health",
+        "http://localhost:8000/api/health",
+        "http://[::1]/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://10.0.0.1/hook",
+        "http://[fd00::1]/hook",
+    ] {
+        let response = client
+            .put("http://127.0.0.1:8000/api/alerts/webhook")
+            .header("Authorization", &authorization)
+            .json(&NewWebhookBody { url: url.to_owned() })
+            .send()
+            .await
+            .expect("webhook request failed");
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{url}");
+        assert_eq!(response.json::<Problem>().await.unwrap().code, ProblemCode::InvalidFields);
+    }
+
+    let response = client
+        .put("http://127.0.0.1:8000/api/alerts/webhook")
+        .header("Authorization", &authorization)
+        .json(&NewWebhookBody { url: "https://93.184.215.14/hook".to_owned() })
+        .send()
+        .await
+        .expect("webhook request failed");
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn weather_history_serves_stored_and_fetched_observations() {
+    let upstream = MockServer::start().await;
+
+    let today = Utc::now().date_naive();
+    let yesterday = today - chrono::Days::new(1);
+    let day_before = today - chrono::Days::new(2);
+    let start_of = |day: NaiveDate| day.and_time(NaiveTime::MIN).and_utc().timestamp();
+
+    let hours = (0..24)
+        .map(|hour| {
+            format!(
+                r#"{{"time_epoch":{},"temp_c":20.0,"feelslike_c":19.0,"condition":{{"text":"Sunny","code":1000}},"precip_mm":0.0}}"#,
+                start_of(yesterday) + hour * 3600
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    Mock::given(method("GET"))
+        .and(path("/v1/history.json"))
+        .and(query_param("dt", yesterday.format("%Y-%m-%d").to_string()))
+        .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+            r#"{{"forecast":{{"forecastday":[{{"hour":[{hours}]}}]}}}}"#
+        )))
+        .with_priority(1)
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    // Days padding the range are requested as well, as their hours may fall in the range in UTC
+    Mock::given(method("GET"))
+        .and(path("/v1/history.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"forecast":{"forecastday":[]}}"#))
+        .expect(2)
+        .mount(&upstream)
+        .await;
+
+    let mut config = Config::read().unwrap();
+    config.upstream = mock_upstream_config(&upstream);
+    let database = spawn_server_with_config(config).await;
+
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let location_id = database.connection.create_location(
+        user_id,
+        &queries::NewLocation {
+            name: "Home".to_owned(),
+            latitude: 41.0,
+            longitude: 29.0,
+            is_default: false,
+        },
+        1,
+    )
+    .await
+    .expect("location persisting failed")
+    .expect("location limit reached");
+
+    let stored = (0..24)
+        .map(|hour| Observation {
+            observed_at: start_of(day_before) + hour * 3600,
+            temperature_c: 10.0,
+            feels_like_c: None,
+            condition: Some("Cloudy".to_owned()),
+            condition_code: None,
+            wind_kph: None,
+            humidity: None,
+            pressure_mb: None,
+            precip_mm: None,
+            cloud: None,
+            uv: None,
+            provider: "weatherapi".to_owned(),
+        })
+        .collect::<Vec<_>>();
+    database.connection.store_observations(GridCell::containing(41.0, 29.0), &stored)
+        .await
+        .expect("observation persisting failed");
+
+    let token = create_token(user_id).expect("token creation failed");
+    let client = reqwest::Client::default();
+    let history = |page: u32| {
+        client
+            .get("http://127.0.0.1:8000/api/weather/history")
+            .query(&[
+                ("from", day_before.to_string()),
+                ("to", yesterday.to_string()),
+                ("location", location_id.to_string()),
+                ("units", "imperial".to_owned()),
+                ("page", page.to_string()),
+                ("page_size", "30".to_owned()),
+            ])
+            .header("Authorization", format!("Bearer {token}"))
+            .send()
+    };
+
+    let first_page = history(1)
+        .await
+        .expect("history request failed")
+        .json::<HistoryResponseBody>()
+        .await
+        .expect("could not obtain history");
+
+    assert_eq!(first_page.total, 48);
+    assert_eq!(first_page.saved_location_id, Some(location_id));
+    assert_eq!(first_page.observations.len(), 30);
+    assert_eq!(first_page.observations[0].time.timestamp(), start_of(day_before));
+    assert!((first_page.observations[0].temperature - 50.0).abs() < 0.01);
+    assert!(first_page.observations[0].stored);
+    assert!(!first_page.observations[24].stored);
+    assert!((first_page.observations[24].temperature - 68.0).abs() < 0.01);
+
+    // Fetched observations are stored, so the provider is not called again
+    let second_page = history(2)
+        .await
+        .expect("history request failed")
+        .json::<HistoryResponseBody>()
+        .await
+        .expect("could not obtain history");
+
+    assert_eq!(second_page.observations.len(), 18);
+    assert!(second_page.observations.iter().all(|o| o.stored));
+    assert_eq!(second_page.observations[17].condition.as_deref(), Some("Sunny"));
+
+    let response = client
+        .get("http://127.0.0.1:8000/api/weather/history")
+        .query(&[("from", yesterday.to_string()), ("to", (today + chrono::Days::new(1)).to_string())])
+        .header("Authorization", format!("Bearer {token}"))
+        .send()
+        .await
+        .expect("history request failed");
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let problem = response.json::<Problem>().await.expect("could not obtain problem");
+    assert_eq!(problem.code, ProblemCode::InvalidFields);
+    assert_eq!(problem.errors[0].field, "to");
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn weather_stream_shares_polling_between_subscribers() {
+    let upstream = MockServer::start().await;
+
+    // The first two polls report the same update, so subscribers are notified once for them
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"current":{"last_updated_epoch":1000,"last_updated":"2024-09-22 16:00","temp_c":10.0,"condition":{"text":"Cloudy"},"feelslike_c":9.0}}"#,
+        ))
+        .up_to_n_times(2)
+        .with_priority(1)
+        .mount(&upstream)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"current":{"last_updated_epoch":2000,"last_updated":"2024-09-22 16:15","temp_c":12.0,"condition":{"text":"Sunny"},"feelslike_c":11.0}}"#,
+        ))
+        .mount(&upstream)
+        .await;
+
+    let mut config = Config::read().unwrap();
+    config.upstream = mock_upstream_config(&upstream);
+    config.stream = StreamConfig {
+        poll_interval_seconds: 1,
+        keep_alive_seconds: 1,
+    };
+    let database = spawn_server_with_config(config).await;
+
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let location_id = database.connection.create_location(
+        user_id,
+        &queries::NewLocation {
+            name: "Home".to_owned(),
+            latitude: 41.0,
+            longitude: 29.0,
+            is_default: false,
+        },
+        1,
+    )
+    .await
+    .expect("location persisting failed")
+    .expect("location limit reached");
+    let token = create_token(user_id).expect("token creation failed");
+
+    let client = reqwest::Client::default();
+    let mut subscribers = Vec::new();
+    for units in ["metric", "imperial"] {
+        let response = client
+            .get("http://127.0.0.1:8000/api/weather/stream")
+            .query(&[("location", location_id.to_string()), ("units", units.to_owned())])
+            .header("Authorization", format!("Bearer {token}"))
+            .send()
+            .await
+            .expect("stream request failed");
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/event-stream"));
+        subscribers.push(response);
+    }
+
+    let mut temperatures = Vec::new();
+    for subscriber in &mut subscribers {
+        let events = tokio::time::timeout(Duration::from_secs(10), read_events(subscriber, 2))
+            .await
+            .expect("stream did not send updates in time");
+        temperatures.push(events.iter().map(|e| e.temperature).collect::<Vec<_>>());
+        assert_eq!(events[0].location.saved_location_id, Some(location_id));
+    }
+
+    assert_eq!(temperatures, [vec![10.0, 12.0], vec![50.0, 53.6]]);
+
+    // A feed per subscriber would have called the API at least twice as often
+    let calls = upstream.received_requests().await.unwrap().len();
+    assert!((3..=4).contains(&calls), "{calls} calls were made");
+
+    // The feed stops after its subscribers leave
+    drop(subscribers);
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    let calls = upstream.received_requests().await.unwrap().len();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(upstream.received_requests().await.unwrap().len(), calls);
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn weather_stream_ends_when_user_is_disabled() {
+    let upstream = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(WEATHER_RESPONSE_BODY))
+        .mount(&upstream)
+        .await;
+
+    let mut config = Config::read().unwrap();
+    config.upstream = mock_upstream_config(&upstream);
+    config.stream = StreamConfig {
+        poll_interval_seconds: 1,
+        keep_alive_seconds: 1,
+    };
+    let database = spawn_server_with_config(config).await;
+
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let location_id = database.connection.create_location(
+        user_id,
+        &queries::NewLocation {
+            name: "Home".to_owned(),
+            latitude: 41.0,
+            longitude: 29.0,
+            is_default: false,
+        },
+        1,
+    )
+    .await
+    .expect("location persisting failed")
+    .expect("location limit reached");
+    let token = create_token(user_id).expect("token creation failed");
+
+    let mut response = reqwest::Client::default()
+        .get("http://127.0.0.1:8000/api/weather/stream")
+        .query(&[("location", location_id.to_string())])
+        .header("Authorization", format!("Bearer {token}"))
+        .send()
+        .await
+        .expect("stream request failed");
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    tokio::time::timeout(Duration::from_secs(10), read_events(&mut response, 1))
+        .await
+        .expect("stream did not send updates in time");
+
+    database.connection.set_user_disabled(user_id, Some(0))
+        .await
+        .expect("disabling user failed");
+
+    // Keep-alive comments are sent until the status of the user is checked again
+    let ended = tokio::time::timeout(Duration::from_secs(10), async {
+        while response.chunk().await.expect("reading the stream failed").is_some() {}
+    })
+    .await;
+
+    assert!(ended.is_ok(), "stream of a disabled user did not end");
+
+    database.close().await;
+}
+
+/// Reads Server-Sent Events of weather updates until given number of them are received.
+async fn read_events(response: &mut reqwest::Response, count: usize) -> Vec<WeatherResponseBody> {
+    let mut buffer = String::new();
+    let mut events = Vec::new();
+
+    while events.len() < count {
+        let chunk = response
+            .chunk()
+            .await
+            .expect("reading the stream failed")
+            .expect("stream ended early");
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+
+        while let Some(end) = buffer.find("\n\n") {
+            let event = buffer[..end].to_owned();
+            buffer.drain(..end + 2);
+
+            if let Some(data) = event.lines().find_map(|line| line.strip_prefix("data: ")) {
+                events.push(serde_json::from_str(data).expect("event is not a weather update"));
+            }
+        }
+    }
+
+    events
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn get_weather_maps_upstream_failures_to_gateway_statuses() {
+    let upstream = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex("/[a-fA-F0-9\\.:]*/latlong"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("41.0,29.0"))
+        .mount(&upstream)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .respond_with(ResponseTemplate::new(403).set_body_string(
+            r#"{"error":{"code":2007,"message":"API key has exceeded calls per month quota."}}"#,
+        ))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&upstream)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .respond_with(ResponseTemplate::new(401).set_body_string(
+            r#"{"error":{"code":2006,"message":"API key is invalid."}}"#,
+        ))
+        .up_to_n_times(1)
+        .with_priority(2)
+        .mount(&upstream)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .with_priority(3)
+        .mount(&upstream)
+        .await;
+
+    let mut config = Config::read().unwrap();
+    config.upstream = mock_upstream_config(&upstream);
+    config.upstream.weather[0].policy = ProviderPolicy {
+        read_timeout_ms: 200,
+        max_retries: 0,
+        ..ProviderPolicy::default()
+    };
+    config.dev_location = Some(DevLocation::Ip {
+        ip: "203.0.113.7".parse().unwrap(),
+    });
+    let database = spawn_server_with_config(config).await;
+
+    let user = User::random();
+    let user_id = database.connection.register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+    let token = create_token(user_id).expect("token creation failed");
+    let authorization = format!("Bearer {token}");
+
+    let client = reqwest::Client::default();
+    for expected in [
+        StatusCode::SERVICE_UNAVAILABLE,
+        StatusCode::BAD_GATEWAY,
+        StatusCode::GATEWAY_TIMEOUT,
+    ] {
+        let response = client
+            .get("http://127.0.0.1:8000/api/weather")
+            .header("Authorization", &authorization)
+            .send()
+            .await
+            .expect("weather request failed");
+
+        assert_eq!(response.status(), expected);
+    }
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn admin_backups_require_token_and_keep_retention() {
+    std::env::set_var("ADMIN_TOKEN", "admin-token");
+    let directory = format!("database/backups-{}", random_database_name());
+    let mut config = Config::read().unwrap();
+    config.backup = BackupConfig {
+        directory: directory.clone().into(),
+        retention: 2,
+        ..BackupConfig::default()
+    };
+    let database = spawn_server_with_config(config).await;
+
+    let user = User::random();
+    database
+        .connection
+        .register_user(&user.username, &user.email, "")
+        .await
+        .expect("user persisting failed");
+
+    let client = reqwest::Client::default();
+    let response = client
+        .post("http://127.0.0.1:8000/api/admin/backups")
+        .bearer_auth("not-the-admin-token")
+        .send()
+        .await
+        .expect("backup request failed");
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    if database.url.is_some() {
+        let response = client
+            .post("http://127.0.0.1:8000/api/admin/backups")
+            .bearer_auth("admin-token")
+            .send()
+            .await
+            .expect("backup request failed");
+
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+
+        database.close().await;
+        return;
+    }
+
+    let mut created = Vec::new();
+    for _ in 0..3 {
+        let response = client
+            .post("http://127.0.0.1:8000/api/admin/backups")
+            .bearer_auth("admin-token")
+            .send()
+            .await
+            .expect("backup request failed");
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        created.push(response.json::<BackupBody>().await.expect("could not parse backup"));
+    }
+
+    let backups = client
+        .get("http://127.0.0.1:8000/api/admin/backups")
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .expect("backup list request failed")
+        .json::<Vec<BackupBody>>()
+        .await
+        .expect("could not parse backups");
+    let names = backups.iter().map(|b| b.name.as_str()).collect::<Vec<_>>();
+
+    assert_eq!(names, [created[2].name.as_str(), created[1].name.as_str()]);
+
+    let backup = SqlitePool::connect(&format!("sqlite://{directory}/{}", created[2].name))
+        .await
+        .expect("backup connection failed");
+    let username: String = sqlx::query_scalar("SELECT username FROM user")
+        .fetch_one(&backup)
+        .await
+        .expect("reading backed up user failed");
+    backup.close().await;
+
+    assert_eq!(username, user.username);
+
+    let _ = std::fs::remove_dir_all(directory);
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn audit_log_records_account_events_and_exports_jsonl() {
+    std::env::set_var("ADMIN_TOKEN", "admin-token");
+    let database = spawn_server().await;
+
+    let register_body = RegisterBody {
+        username: "audited_user".to_owned(),
+        email: "audited@example.com".to_owned(),
+        password: "Password123!".to_owned(),
+    };
+    let client = reqwest::Client::default();
+    let user_id = client
+        .post("http://127.0.0.1:8000/api/register")
+        .header("User-Agent", "audit-test/1.0")
+        .json(&register_body)
+        .send()
+        .await
+        .expect("registration request failed")
+        .json::<RegisterResponseBody>()
+        .await
+        .expect("could not obtain registration response body")
+        .user_id;
+    let response = client
+        .post("http://127.0.0.1:8000/api/register")
+        .json(&register_body)
+        .send()
+        .await
+        .expect("registration request failed");
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    for password in ["WrongPassword1!", "Password123!"] {
+        client
+            .post("http://127.0.0.1:8000/api/login")
+            .header("User-Agent", "audit-test/1.0")
+            .json(&LoginBody {
+                identifier: "audited_user".to_owned(),
+                password: password.to_owned(),
+            })
+            .send()
+            .await
+            .expect("login request failed");
+    }
+
+    let response = client
+        .get(format!("http://127.0.0.1:8000/api/admin/audit?user_id={user_id}"))
+        .send()
+        .await
+        .expect("audit request failed");
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let events = client
+        .get(format!("http://127.0.0.1:8000/api/admin/audit?user_id={user_id}"))
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .expect("audit request failed")
+        .json::<Vec<AuditEventBody>>()
+        .await
+        .expect("could not parse audit events");
+    let kinds = events.iter().map(|e| (e.event, e.outcome)).collect::<Vec<_>>();
+
+    assert_eq!(
+        kinds,
+        [
+            (AuditEventKind::TokenIssuance, AuditOutcome::Success),
+            (AuditEventKind::Login, AuditOutcome::Success),
+            (AuditEventKind::Login, AuditOutcome::Failure),
+            (AuditEventKind::Registration, AuditOutcome::Success),
+        ]
+    );
+    assert!(events.iter().all(|e| e.client_ip.as_deref() == Some("127.0.0.1")));
+    assert!(events.iter().all(|e| e.user_agent.as_deref() == Some("audit-test/1.0")));
+
+    let failed_registrations = client
+        .get("http://127.0.0.1:8000/api/admin/audit?event=registration&outcome=failure&ip=127.0.0.1")
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .expect("audit request failed")
+        .json::<Vec<AuditEventBody>>()
+        .await
+        .expect("could not parse audit events");
+
+    assert_eq!(failed_registrations.len(), 1);
+    assert_eq!(failed_registrations[0].user_id, None);
+
+    let next_page = client
+        .get(format!(
+            "http://127.0.0.1:8000/api/admin/audit?user_id={user_id}&limit=2&before={}",
+            events[1].id
+        ))
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .expect("audit request failed")
+        .json::<Vec<AuditEventBody>>()
+        .await
+        .expect("could not parse audit events");
+
+    assert_eq!(
+        next_page.iter().map(|e| e.id).collect::<Vec<_>>(),
+        [events[2].id, events[3].id]
+    );
+
+    let response = client
+        .get("http://127.0.0.1:8000/api/admin/audit?ip=not-an-address")
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .expect("audit request failed");
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = client
+        .get(format!("http://127.0.0.1:8000/api/admin/audit/export?user_id={user_id}"))
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .expect("audit export request failed");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/x-ndjson"
+    );
+
+    let export = response.text().await.expect("could not read audit export");
+    let exported = export
+        .lines()
+        .map(|line| serde_json::from_str::<AuditEventBody>(line).expect("could not parse exported event"))
+        .map(|e| e.id)
+        .collect::<Vec<_>>();
+
+    assert_eq!(exported, events.iter().map(|e| e.id).collect::<Vec<_>>());
+
+    database.close().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn shutdown_handle_stops_server_and_closes_database() {
+    let mut config = Config::read().unwrap();
+    config.database_name = random_database_name();
+    config.database.url = test_database_url(&config.database_name);
+
+    let server = weather_server_lib::setup(&config)
+        .await
+        .expect("server initialization failed");
+    let database = server.database();
+    let shutdown = server.shutdown_handle();
+    let serving = tokio::spawn(server.serve());
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(5), serving)
+        .await
+        .expect("server did not shut down in time")
+        .expect("server task panicked")
+        .expect("server execution failed");
+
+    assert!(database.is_closed());
+
+    let response = reqwest::Client::default()
+        .get("http://127.0.0.1:8000/api/health_check")
+        .send()
+        .await;
+
+    assert!(response.is_err());
+
+    Database::new(&config, &database).close().await;
+}
+
+/// Current weather served by the mocked weather API.
+const WEATHER_RESPONSE_BODY: &str =
+    r#"{"current":{"last_updated":"2024-09-22 16:00","temp_c":21.5,"condition":{"text":"Sunny"},"feelslike_c":20.0}}"#;
+
+/// Single `ipapi` geolocation and `weatherapi` weather providers, both served by the mock server.
+fn mock_upstream_config(upstream: &MockServer) -> UpstreamConfig {
+    UpstreamConfig {
+        geolocation: vec![ProviderConfig {
+            host: Some(upstream.uri()),
+            ..ProviderConfig::new(GeolocationProvider::Ipapi)
+        }],
+        weather: vec![ProviderConfig {
+            host: Some(upstream.uri()),
+            ..ProviderConfig::new(WeatherProvider::Weatherapi)
+        }],
+    }
+}
+
+#[must_use]
+async fn spawn_server() -> Database {
+    spawn_server_with_config(Config::read().unwrap()).await
+}
+
+#[must_use]
+async fn spawn_server_with_config(mut config: Config) -> Database {
+    config.database_name = random_database_name();
+    config.database.url = test_database_url(&config.database_name);
+
+    let server = weather_server_lib::setup(&config)
+        .await
+        .expect("server initialization failed");
+
+    let database = server.database();
+    tokio::spawn(server.serve());
+
+    // Poem server does not initialize quickly enough for us to query it immediately
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    Database::new(&config, &database)
+}
+
+fn random_database_name() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(|x| x as char)
+        .collect()
+}
+
+/// URL of a PostgreSQL database with given name, if tests are to run against the PostgreSQL server
+/// `TEST_DATABASE_URL` points to, e.g. `postgres://postgres@localhost:5432`.
+fn test_database_url(name: &str) -> Option<String> {
+    std::env::var("TEST_DATABASE_URL")
+        .ok()
+        .map(|server| format!("{}/{name}", server.trim_end_matches('/')))
+}
+
+struct Database {
+    name: String,
+    url: Option<String>,
+    connection: queries::Database,
+}
+
+impl Database {
+    fn new(config: &Config, connection: &queries::Database) -> Self {
+        Self {
+            name: config.database_name.clone(),
+            url: config.database.url.clone(),
+            connection: connection.clone(),
+        }
+    }
+
+    async fn close(self) {
+        // We need to close the connection or some changes are not flushed
+        // Possibly not important as we will be deleting those files
+        self.connection.close().await;
+
+        if let Some(url) = &self.url {
+            let _ = Postgres::force_drop_database(url).await;
+            return;
+        }
 
         // Cleanup files SQLite files created for this test
         std::env::current_dir()
@@ -0,0 +1,547 @@
+use chrono::Utc;
+use clap::Parser;
+use rand::{thread_rng, Rng};
+use rand_distr::Alphanumeric;
+use sqlx::migrate::MigrateDatabase;
+use sqlx::{Postgres, SqlitePool};
+use weather_server_lib::backup::Backups;
+use weather_server_lib::cli::Cli;
+use weather_server_lib::config::Config;
+use weather_server_lib::password;
+use weather_server_lib::queries::{AlertDelivery, AuditFilter, NewAlertRule, NewLocation, Webhook};
+
+#[tokio::test]
+async fn user_create_persists_user() {
+    let config = random_database_config();
+
+    Cli::try_parse_from([
+        "weather_server_demo",
+        "user",
+        "create",
+        "--username",
+        "operator_1",
+        "--email",
+        "operator@example.com",
+        "--password",
+        "Password123!",
+    ])
+    .expect("arguments should parse")
+    .execute(&config)
+    .await
+    .expect("user creation failed");
+
+    let database = weather_server_lib::database(&config)
+        .await
+        .expect("database connection failed");
+    let users = database.list_users()
+        .await
+        .expect("listing users failed");
+
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].username, "operator_1");
+    assert_eq!(users[0].email, "operator@example.com");
+
+    database.close().await;
+    remove_database(&config).await;
+}
+
+#[tokio::test]
+async fn user_create_rejects_invalid_credentials() {
+    let config = random_database_config();
+
+    let result = Cli::try_parse_from([
+        "weather_server_demo",
+        "user",
+        "create",
+        "--username",
+        "op",
+        "--email",
+        "operator@example.com",
+        "--password",
+        "Password123!",
+    ])
+    .expect("arguments should parse")
+    .execute(&config)
+    .await;
+
+    assert!(result.is_err());
+
+    remove_database(&config).await;
+}
+
+#[tokio::test]
+async fn user_reset_password_replaces_password() {
+    let config = random_database_config();
+
+    let database = weather_server_lib::database(&config)
+        .await
+        .expect("database connection failed");
+    let user_id = database.register_user(
+        "operator_1",
+        "operator@example.com",
+        &password::hash("Password123!"),
+    )
+    .await
+    .expect("user persisting failed");
+
+    Cli::try_parse_from([
+        "weather_server_demo",
+        "user",
+        "reset-password",
+        "operator@example.com",
+        "--password",
+        "NewPassword456?",
+    ])
+    .expect("arguments should parse")
+    .execute(&config)
+    .await
+    .expect("password reset failed");
+
+    let (_, hash) = database.get_user_id_and_password_by_username_or_email(
+        "operator_1",
+        "operator_1",
+    )
+    .await;
+
+    assert!(password::validate("NewPassword456?".to_owned(), hash).await);
+
+    let filter = AuditFilter {
+        event: Some("password_reset".to_owned()),
+        ..AuditFilter::default()
+    };
+    let events = database.list_audit_events(&filter, 10)
+        .await
+        .expect("listing audit events failed");
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].user_id, Some(user_id));
+    assert_eq!(events[0].outcome, "success");
+
+    database.close().await;
+    remove_database(&config).await;
+}
+
+#[tokio::test]
+async fn user_delete_fails_for_missing_user() {
+    let config = random_database_config();
+
+    let result = Cli::try_parse_from(["weather_server_demo", "user", "delete", "42"])
+        .expect("arguments should parse")
+        .execute(&config)
+        .await;
+
+    assert!(result.is_err());
+
+    remove_database(&config).await;
+}
+
+#[tokio::test]
+async fn user_create_and_token_issue_are_audited() {
+    let config = random_database_config();
+
+    Cli::try_parse_from([
+        "weather_server_demo",
+        "user",
+        "create",
+        "--username",
+        "operator_1",
+        "--email",
+        "operator@example.com",
+        "--password",
+        "Password123!",
+    ])
+    .expect("arguments should parse")
+    .execute(&config)
+    .await
+    .expect("user creation failed");
+
+    let database = weather_server_lib::database(&config)
+        .await
+        .expect("database connection failed");
+    let user_id = database.list_users()
+        .await
+        .expect("listing users failed")[0]
+        .id;
+
+    Cli::try_parse_from(["weather_server_demo", "token", "issue", &user_id.to_string()])
+        .expect("arguments should parse")
+        .execute(&config)
+        .await
+        .expect("token issuance failed");
+
+    let filter = AuditFilter {
+        user_id: Some(user_id),
+        ..AuditFilter::default()
+    };
+    let events = database.list_audit_events(&filter, 10)
+        .await
+        .expect("listing audit events failed");
+    database.close().await;
+
+    let kinds = events
+        .iter()
+        .map(|e| (e.event.as_str(), e.outcome.as_str()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(kinds, [("token_issuance", "success"), ("registration", "success")]);
+    assert!(events.iter().all(|e| e.client_ip.is_none() && e.user_agent.is_none()));
+
+    remove_database(&config).await;
+}
+
+#[tokio::test]
+async fn user_delete_keeps_user_restorable_until_purged() {
+    let config = random_database_config();
+
+    let database = weather_server_lib::database(&config)
+        .await
+        .expect("database connection failed");
+    let user_id = database.register_user("operator_1", "operator@example.com", "")
+        .await
+        .expect("user persisting failed");
+
+    let execute = |arguments: &[&str]| {
+        let cli = Cli::try_parse_from(["weather_server_demo", "user"].iter().chain(arguments))
+            .expect("arguments should parse");
+        let config = &config;
+        async move { cli.execute(config).await }
+    };
+
+    execute(&["delete", &user_id.to_string()]).await.expect("deletion failed");
+    execute(&["purge"]).await.expect("purge failed");
+
+    let user = database.get_user_by_id(user_id)
+        .await
+        .expect("reading user failed")
+        .expect("user should be kept for the retention period");
+
+    assert!(user.deleted_at.is_some());
+
+    execute(&["restore", &user_id.to_string()]).await.expect("restoring failed");
+    execute(&["disable", &user_id.to_string()]).await.expect("disabling failed");
+
+    let user = database.get_user_by_id(user_id)
+        .await
+        .expect("reading user failed")
+        .expect("user should exist");
+
+    assert!(user.deleted_at.is_none());
+    assert!(user.disabled_at.is_some());
+
+    let result = Cli::try_parse_from(["weather_server_demo", "token", "issue", &user_id.to_string()])
+        .expect("arguments should parse")
+        .execute(&config)
+        .await;
+
+    assert!(result.is_err());
+
+    // Deleted before the retention period
+    let deleted_at = Utc::now().timestamp() - i64::from(config.accounts.deletion_retention_days + 1) * 86_400;
+    database.set_user_deleted(user_id, Some(deleted_at))
+        .await
+        .expect("deleting user failed");
+    execute(&["purge"]).await.expect("purge failed");
+
+    let user = database.get_user_by_id(user_id)
+        .await
+        .expect("reading user failed");
+
+    assert!(user.is_none());
+
+    let filter = AuditFilter {
+        user_id: Some(user_id),
+        ..AuditFilter::default()
+    };
+    let events = database.list_audit_events(&filter, 10)
+        .await
+        .expect("listing audit events failed");
+    let kinds = events
+        .iter()
+        .map(|e| (e.event.as_str(), e.outcome.as_str()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        kinds,
+        [
+            ("purge", "success"),
+            ("disabling", "success"),
+            ("restoration", "success"),
+            ("deletion", "success"),
+        ]
+    );
+
+    database.close().await;
+    remove_database(&config).await;
+}
+
+#[tokio::test]
+async fn user_delete_now_deletes_rows_of_user_without_foreign_keys() {
+    let mut config = random_database_config();
+    // Rows are not deleted by cascading foreign keys
+    config.database.sqlite.foreign_keys = false;
+
+    let database = weather_server_lib::database(&config)
+        .await
+        .expect("database connection failed");
+    let user_id = database.register_user("operator_1", "operator@example.com", "")
+        .await
+        .expect("user persisting failed");
+    let location = NewLocation {
+        name: "Home".to_string(),
+        latitude: 51.5,
+        longitude: -0.12,
+        is_default: true,
+    };
+    let location_id = database.create_location(user_id, &location, 1)
+        .await
+        .expect("location persisting failed")
+        .expect("location limit reached");
+    let webhook = Webhook {
+        url: "https://example.com/alerts".to_string(),
+        secret: "secret".to_string(),
+    };
+    database.set_webhook(user_id, &webhook)
+        .await
+        .expect("webhook persisting failed");
+    let rule = NewAlertRule {
+        location_id,
+        condition: "temperature_above".to_string(),
+        threshold: 30.0,
+        within_hours: None,
+    };
+    let rule_id = database.create_alert_rule(user_id, &rule, 1)
+        .await
+        .expect("alert rule persisting failed")
+        .expect("alert rule limit reached");
+    let delivery = AlertDelivery {
+        user_id,
+        rule_id,
+        event_id: "event".to_string(),
+        attempt: 1,
+        status_code: Some(200),
+        error: None,
+        delivered: true,
+        attempted_at: Utc::now().timestamp(),
+    };
+    database.log_alert_delivery(&delivery)
+        .await
+        .expect("alert delivery persisting failed");
+
+    Cli::try_parse_from(["weather_server_demo", "user", "delete", &user_id.to_string(), "--now"])
+        .expect("arguments should parse")
+        .execute(&config)
+        .await
+        .expect("deletion failed");
+
+    assert!(database.get_user_by_id(user_id).await.expect("reading user failed").is_none());
+    assert!(database.list_locations(user_id).await.expect("reading locations failed").is_empty());
+    assert!(database.get_webhook(user_id).await.expect("reading webhook failed").is_none());
+    assert!(database.list_alert_rules(user_id).await.expect("reading alert rules failed").is_empty());
+    assert!(database.list_alert_deliveries(user_id, 10)
+        .await
+        .expect("reading alert deliveries failed")
+        .is_empty());
+
+    database.close().await;
+    remove_database(&config).await;
+}
+
+#[tokio::test]
+async fn migrate_creates_database_at_configured_path_in_wal_mode() {
+    let mut config = random_database_config();
+    let directory = format!("database/{}", config.database_name);
+    config.database.url = Some(format!("{directory}/nested/users.db"));
+
+    Cli::try_parse_from(["weather_server_demo", "migrate"])
+        .expect("arguments should parse")
+        .execute(&config)
+        .await
+        .expect("migration failed");
+
+    // The journal mode of WAL databases persists, connections that do not set one still report it
+    let database = SqlitePool::connect(&format!("sqlite://{directory}/nested/users.db"))
+        .await
+        .expect("database connection failed");
+    let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+        .fetch_one(&database)
+        .await
+        .expect("reading journal mode failed");
+    database.close().await;
+
+    assert_eq!(journal_mode, "wal");
+
+    let _ = std::fs::remove_dir_all(directory);
+}
+
+#[tokio::test]
+async fn backup_restore_rejects_unknown_migrations_and_replaces_database() {
+    // Backups are only supported for SQLite databases
+    let mut config = random_database_config();
+    config.database.url = None;
+    config.backup.directory = format!("database/backups-{}", config.database_name).into();
+
+    let database = weather_server_lib::database(&config)
+        .await
+        .expect("database connection failed");
+    database.register_user("operator_1", "operator@example.com", "")
+        .await
+        .expect("user persisting failed");
+    let backup = Backups::new(&config.backup, database.clone())
+        .create()
+        .await
+        .expect("backup failed");
+    database.register_user("operator_2", "operator2@example.com", "")
+        .await
+        .expect("user persisting failed");
+    database.close().await;
+
+    // A backup taken by a newer version has migrations this version does not know
+    let newer = config.backup.directory.join("newer.db");
+    std::fs::copy(config.backup.directory.join(&backup.name), &newer).expect("copying backup failed");
+    let connection = SqlitePool::connect(&format!("sqlite://{}", newer.display()))
+        .await
+        .expect("backup connection failed");
+    sqlx::query(
+        "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time) \
+         VALUES (99999999999999, 'future', TRUE, x'00', 0)",
+    )
+    .execute(&connection)
+    .await
+    .expect("adding migration failed");
+    connection.close().await;
+
+    let result = Cli::try_parse_from(["weather_server_demo", "backup", "restore", "newer.db"])
+        .expect("arguments should parse")
+        .execute(&config)
+        .await;
+
+    assert!(result.is_err());
+
+    Cli::try_parse_from(["weather_server_demo", "backup", "restore", &backup.name])
+        .expect("arguments should parse")
+        .execute(&config)
+        .await
+        .expect("restore failed");
+
+    let database = weather_server_lib::database(&config)
+        .await
+        .expect("database connection failed");
+    let users = database.list_users()
+        .await
+        .expect("listing users failed");
+    database.close().await;
+
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].username, "operator_1");
+
+    let _ = std::fs::remove_dir_all(&config.backup.directory);
+    remove_database(&config).await;
+}
+
+#[tokio::test]
+async fn migrate_stores_canonical_identities_and_reports_collisions() {
+    // Users registered before canonical identities existed are inserted directly into SQLite
+    let mut config = random_database_config();
+    config.database.url = None;
+
+    let database = weather_server_lib::database(&config)
+        .await
+        .expect("database connection failed");
+    database.close().await;
+
+    let connection = SqlitePool::connect(&config.database_url())
+        .await
+        .expect("database connection failed");
+    sqlx::query(
+        "INSERT INTO user (username, email, password) \
+         VALUES ('Operator_1', 'op@example.com', ''), ('operator_1', 'other@example.com', '')",
+    )
+    .execute(&connection)
+    .await
+    .expect("inserting users failed");
+    connection.close().await;
+
+    Cli::try_parse_from(["weather_server_demo", "migrate"])
+        .expect("arguments should parse")
+        .execute(&config)
+        .await
+        .expect("migration failed");
+
+    let connection = SqlitePool::connect(&config.database_url())
+        .await
+        .expect("database connection failed");
+    let identities: Vec<(Option<String>, Option<String>)> =
+        sqlx::query_as("SELECT username_canonical, email_canonical FROM user ORDER BY id")
+            .fetch_all(&connection)
+            .await
+            .expect("reading canonical identities failed");
+    connection.close().await;
+
+    // The newer user's colliding username is left missing, its email is stored
+    assert_eq!(
+        identities,
+        [
+            (Some("operator_1".to_owned()), Some("op@example.com".to_owned())),
+            (None, Some("other@example.com".to_owned())),
+        ]
+    );
+
+    Cli::try_parse_from(["weather_server_demo", "user", "collisions"])
+        .expect("arguments should parse")
+        .execute(&config)
+        .await
+        .expect("listing collisions failed");
+
+    let database = weather_server_lib::database(&config)
+        .await
+        .expect("database connection failed");
+    let mut user_ids = Vec::new();
+    for identifier in ["OTHER@example.com", "operator_1", "Operator_1", "OPERATOR_1"] {
+        let (user_id, _) = database
+            .get_user_id_and_password_by_username_or_email(identifier, identifier)
+            .await;
+        user_ids.push(user_id);
+    }
+    let reset = database.update_password_by_username_or_email("operator_1", "operator_1", "")
+        .await
+        .expect("resetting password failed");
+    database.close().await;
+
+    // The newer user logs in with its colliding username exactly as registered, other variants are the older user's
+    assert_eq!(user_ids, [2, 2, 1, 1]);
+    assert_eq!(reset, Some(2));
+
+    remove_database(&config).await;
+}
+
+fn random_database_config() -> Config {
+    let mut config = Config::read().unwrap();
+
+    config.database_name = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(|x| x as char)
+        .collect();
+    // Tests run against PostgreSQL when a server URL is given, e.g. `postgres://postgres@localhost:5432`
+    config.database.url = std::env::var("TEST_DATABASE_URL")
+        .ok()
+        .map(|server| format!("{}/{}", server.trim_end_matches('/'), config.database_name));
+
+    config
+}
+
+async fn remove_database(config: &Config) {
+    if config.database.url.is_some() {
+        let _ = Postgres::force_drop_database(&config.database_url()).await;
+        return;
+    }
+
+    std::fs::read_dir("database")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|f| f.file_name().to_string_lossy().contains(&config.database_name))
+        .for_each(|f| {
+            let _ = std::fs::remove_file(f.path());
+        });
+}
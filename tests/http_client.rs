@@ -6,9 +6,14 @@ use std::str::FromStr;
 use wiremock::matchers::{method, path, path_regex};
 use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
 
+use weather_server_lib::config::{
+    GeolocationProvider, ProviderConfig, ProviderPolicy, UpstreamConfig, WeatherProvider,
+};
 use weather_server_lib::http_client::{
-    Condition, Coordinate, Current, HttpClient, Location, WeatherApiResponse,
+    Condition, Coordinate, Current, Error, HttpClient, Location, WeatherApiResponse,
+    MAX_ERROR_BODY_LENGTH,
 };
+use weather_server_lib::resilience::CircuitState;
 
 #[tokio::test]
 async fn geolocation_api_succeeds_for_non_loopback_ip() {
@@ -137,17 +142,353 @@ impl Respond for WeatherResponder {
         }
 
         let response = WeatherApiResponse {
-            location: Location,
+            location: Location::default(),
             current: Current {
                 last_updated: String::new(),
                 temp_c: self.temperature,
                 condition: Condition {
                     text: self.condition.clone(),
+                    ..Condition::default()
                 },
                 feelslike_c: self.feels_like,
+                ..Current::default()
             },
         };
 
         ResponseTemplate::new(200).set_body_json(response)
     }
 }
+
+#[tokio::test]
+async fn geolocation_api_call_is_retried_after_server_error() {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex("/[a-fA-F0-9\\.:]*/latlong"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .with_priority(1)
+        .mount(&mock_server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex("/[a-fA-F0-9\\.:]*/latlong"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("45.0,45.0"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = client_with_policy(&mock_server, fast_policy());
+
+    let response = client
+        .get_coordinates_for_ip("176.12.12.12")
+        .await
+        .expect("request should succeed after a retry");
+
+    assert!(response.latitude - 45.0 < 0.000_000_001);
+}
+
+#[tokio::test]
+async fn retry_after_longer_than_max_delay_is_not_waited() {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex("/[a-fA-F0-9\\.:]*/latlong"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "120"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = client_with_policy(&mock_server, fast_policy());
+
+    let result = client.get_coordinates_for_ip("176.12.12.12").await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn circuit_opens_after_consecutive_failures() {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex("/[a-fA-F0-9\\.:]*/latlong"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&mock_server)
+        .await;
+
+    let policy = ProviderPolicy {
+        max_retries: 0,
+        failure_threshold: 2,
+        ..fast_policy()
+    };
+    let client = client_with_policy(&mock_server, policy);
+
+    for _ in 0..2 {
+        assert!(client.get_coordinates_for_ip("176.12.12.12").await.is_err());
+    }
+
+    let result = client.get_coordinates_for_ip("176.12.12.12").await;
+
+    assert!(matches!(result, Err(Error::CircuitOpen)));
+    assert_eq!(client.circuit_states()[0], ("ipapi", CircuitState::Open));
+}
+
+#[tokio::test]
+async fn geolocation_falls_over_to_next_provider() {
+    let failing_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&failing_server)
+        .await;
+
+    let fallback_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/json/176.12.12.12"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"status":"success","lat":41.0,"lon":29.0}"#,
+        ))
+        .expect(1)
+        .mount(&fallback_server)
+        .await;
+
+    let config = UpstreamConfig {
+        geolocation: vec![
+            ProviderConfig {
+                host: Some(failing_server.uri()),
+                ..ProviderConfig::new(GeolocationProvider::Ipapi)
+            },
+            ProviderConfig {
+                host: Some(fallback_server.uri()),
+                ..ProviderConfig::new(GeolocationProvider::IpApi)
+            },
+        ],
+        ..UpstreamConfig::default()
+    };
+    let client = HttpClient::from_config(&config).expect("could not create HTTP client");
+
+    let response = client
+        .get_coordinates_for_ip("176.12.12.12")
+        .await
+        .expect("fallback provider should succeed");
+
+    assert_eq!(response.provider, "ip-api");
+    assert!(response.latitude - 41.0 < 0.000_000_001);
+    assert!(response.longitude - 29.0 < 0.000_000_001);
+}
+
+#[tokio::test]
+async fn open_meteo_response_is_converted() {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/forecast"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"latitude":45.0,"longitude":45.0,"timezone":"Asia/Tbilisi","utc_offset_seconds":14400,"current":{"time":"2024-09-22T16:00","interval":900,"temperature_2m":21.5,"apparent_temperature":20.0,"weather_code":3,"wind_direction_10m":350,"visibility":24140.0}}"#,
+        ))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let config = UpstreamConfig {
+        weather: vec![ProviderConfig {
+            host: Some(mock_server.uri()),
+            ..ProviderConfig::new(WeatherProvider::OpenMeteo)
+        }],
+        ..UpstreamConfig::default()
+    };
+    let client = HttpClient::from_config(&config).expect("could not create HTTP client");
+
+    let response = client
+        .get_weather_for_coordinates(45.0, 45.0)
+        .await
+        .expect("request to API failed");
+
+    assert_eq!(response.provider, "open-meteo");
+    assert!(response.current.temp_c - 21.5 < 0.000_000_001);
+    assert_eq!(response.current.condition.text, "Overcast");
+    assert_eq!(response.current.last_updated, "2024-09-22 16:00");
+    assert_eq!(
+        response.last_updated().map(|t| t.to_rfc3339()).as_deref(),
+        Some("2024-09-22T16:00:00+04:00")
+    );
+    assert_eq!(response.current.condition.code, Some(3));
+    assert_eq!(response.current.wind_dir.as_deref(), Some("N"));
+    assert!(response.current.vis_km.unwrap() - 24.14 < 0.000_000_001);
+    assert!(response.current.humidity.is_none());
+}
+
+#[tokio::test]
+async fn weather_api_forecast_is_limited_to_requested_hours() {
+    let mock_server = MockServer::start().await;
+
+    let now = chrono::Utc::now().timestamp();
+    let current_hour = now - now.rem_euclid(3600);
+    let hours = (-2..6)
+        .map(|i| {
+            format!(
+                r#"{{"time_epoch":{},"precip_mm":0.0,"chance_of_rain":{}}}"#,
+                current_hour + i * 3600,
+                (i + 2) * 10
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    Mock::given(method("GET"))
+        .and(path("/v1/forecast.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+            r#"{{"forecast":{{"forecastday":[{{"hour":[{hours}]}}]}}}}"#
+        )))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = HttpClient::new_with_hosts(&mock_server.uri(), &mock_server.uri())
+        .expect("could not create HTTP client");
+
+    let response = client
+        .get_forecast_for_coordinates(41.0, 29.0, 3)
+        .await
+        .expect("request to API failed");
+
+    let chances = response
+        .hours
+        .iter()
+        .map(|h| h.chance_of_rain)
+        .collect::<Vec<_>>();
+
+    assert_eq!(chances, [Some(20), Some(30), Some(40)]);
+    assert_eq!(response.hours[0].time_epoch, current_hour);
+}
+
+#[tokio::test]
+async fn weather_api_full_payload_is_parsed() {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"location":{"name":"Istanbul","region":"Istanbul","country":"Turkey","lat":41.02,"lon":28.96,"tz_id":"Europe/Istanbul","localtime_epoch":1727013937,"localtime":"2024-09-22 17:05"},"current":{"last_updated_epoch":1727013600,"last_updated":"2024-09-22 17:00","temp_c":21.5,"temp_f":70.7,"is_day":1,"condition":{"text":"Sunny","icon":"//cdn.weatherapi.com/weather/64x64/day/113.png","code":1000},"wind_kph":13.0,"wind_degree":20,"wind_dir":"NNE","pressure_mb":1015.0,"precip_mm":0.0,"humidity":56,"cloud":0,"feelslike_c":21.5,"vis_km":10.0,"uv":6.0,"gust_kph":15.0,"air_quality":{"co":230.3,"no2":13.0,"o3":77.0,"so2":4.1,"pm2_5":8.6,"pm10":12.1,"us-epa-index":1,"gb-defra-index":1}}}"#,
+        ))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = client_with_policy(&mock_server, fast_policy());
+
+    let response = client
+        .get_weather_for_coordinates(45.0, 45.0)
+        .await
+        .expect("request to API failed");
+
+    let current = &response.current;
+    assert_eq!(current.condition.code, Some(1000));
+    assert_eq!(current.is_day, Some(1));
+    assert_eq!(current.wind_dir.as_deref(), Some("NNE"));
+    assert_eq!(current.humidity, Some(56));
+    assert_eq!(
+        current.air_quality.as_ref().and_then(|a| a.us_epa_index),
+        Some(1)
+    );
+
+    assert_eq!(response.location.name.as_deref(), Some("Istanbul"));
+    assert_eq!(response.location.tz_id.as_deref(), Some("Europe/Istanbul"));
+    assert_eq!(
+        response.utc_offset().map(|o| o.local_minus_utc()),
+        Some(3 * 3600)
+    );
+    assert_eq!(
+        response.last_updated().map(|t| t.to_rfc3339()).as_deref(),
+        Some("2024-09-22T17:00:00+03:00")
+    );
+}
+
+#[tokio::test]
+async fn weather_api_error_details_are_reported() {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .respond_with(ResponseTemplate::new(403).set_body_string(
+            r#"{"error":{"code":2007,"message":"API key has exceeded calls per month quota."}}"#,
+        ))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = client_with_policy(&mock_server, fast_policy());
+
+    let Err(error) = client.get_weather_for_coordinates(45.0, 45.0).await else {
+        panic!("request should fail");
+    };
+
+    assert!(error.is_unavailable());
+    assert!(!error.is_timeout());
+    let Error::UnexpectedStatus {
+        status,
+        code,
+        message,
+        body,
+    } = error
+    else {
+        panic!("error should carry the response status");
+    };
+    assert_eq!(status, 403);
+    assert_eq!(code.as_deref(), Some("2007"));
+    assert_eq!(
+        message.as_deref(),
+        Some("API key has exceeded calls per month quota.")
+    );
+    assert!(body.contains("2007"));
+}
+
+#[tokio::test]
+async fn unexpected_weather_api_body_is_kept_truncated() {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/v1/current.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("x".repeat(2048)))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let client = client_with_policy(&mock_server, fast_policy());
+
+    let result = client.get_weather_for_coordinates(45.0, 45.0).await;
+
+    let Err(Error::JsonParsingFailed { body, .. }) = result else {
+        panic!("response should fail to parse");
+    };
+    assert_eq!(body.len(), MAX_ERROR_BODY_LENGTH);
+}
+
+fn fast_policy() -> ProviderPolicy {
+    ProviderPolicy {
+        retry_base_delay_ms: 10,
+        retry_max_delay_ms: 50,
+        ..ProviderPolicy::default()
+    }
+}
+
+fn client_with_policy(mock_server: &MockServer, policy: ProviderPolicy) -> HttpClient {
+    let config = UpstreamConfig {
+        geolocation: vec![ProviderConfig {
+            provider: GeolocationProvider::Ipapi,
+            host: Some(mock_server.uri()),
+            policy: policy.clone(),
+        }],
+        weather: vec![ProviderConfig {
+            provider: WeatherProvider::Weatherapi,
+            host: Some(mock_server.uri()),
+            policy,
+        }],
+    };
+
+    HttpClient::from_config(&config).expect("could not create HTTP client")
+}
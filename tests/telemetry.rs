@@ -0,0 +1,54 @@
+use tracing::Instrument;
+use wiremock::matchers::{header_exists, method, path, path_regex};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use weather_server_lib::config::{LogConfig, TelemetryConfig};
+use weather_server_lib::http_client::HttpClient;
+use weather_server_lib::logging;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_are_exported_and_trace_context_is_propagated() {
+    let collector = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/v1/traces"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&collector)
+        .await;
+
+    let geolocation_api = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex("/[a-fA-F0-9\\.:]*/latlong"))
+        .and(header_exists("traceparent"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("45.0,45.0"))
+        .expect(1)
+        .mount(&geolocation_api)
+        .await;
+
+    let config = TelemetryConfig {
+        otlp_endpoint: Some(format!("{}/v1/traces", collector.uri())),
+        ..TelemetryConfig::default()
+    };
+    let guard = logging::init(&LogConfig::default(), &config).expect("could not initialize logging");
+
+    let client = HttpClient::new_with_hosts(&geolocation_api.uri(), &geolocation_api.uri())
+        .expect("could not create HTTP client");
+    client
+        .get_coordinates_for_ip("176.12.12.12")
+        .instrument(tracing::info_span!("test"))
+        .await
+        .expect("request to API failed");
+
+    // Dropping the guard flushes the spans, which blocks until the collector responds
+    tokio::task::spawn_blocking(move || drop(guard))
+        .await
+        .expect("flushing spans failed");
+
+    let exports = collector
+        .received_requests()
+        .await
+        .expect("request recording should be enabled");
+
+    assert!(!exports.is_empty());
+}